@@ -6,6 +6,9 @@ import {ECDSA} from "@openzeppelin/contracts/utils/cryptography/ECDSA.sol";
 contract CcmpContract {
     using ECDSA for bytes32;
 
+    // `0x19` as in EIP-191 followed by a byte that is not an EIP-191 version
+    bytes5 public constant DIGEST_PREFIX = "\x19CCMP";
+
     uint256 public index;
     address public canister_address;    
 
@@ -25,5 +28,11 @@ contract CcmpContract {
         return canister_address == ECDSA.recover(_message_hash, _signature);
     }
 
+    /// The digest the canister signs for a payload hash, tagged with the encoding
+    /// of the payload: 0 plain, 1 abi.encodePacked, 2 abi.encode, 255 batch root.
+    function ccmpDigest(uint8 _tag, bytes32 _payload_hash) external pure returns (bytes32) {
+        return keccak256(abi.encodePacked(DIGEST_PREFIX, _tag, _payload_hash));
+    }
+
     event CcmpMessage(uint256 indexed index, uint256 ccmp_chain_id, address sender, bytes message, bytes receiver);
 }
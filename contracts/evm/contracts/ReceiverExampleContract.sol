@@ -1,9 +1,25 @@
 // SPDX-License-Identifier: MIT
 pragma solidity 0.8.19;
 
+import {MerkleProof} from "@openzeppelin/contracts/utils/cryptography/MerkleProof.sol";
+
 import "./interfaces/ICcmpContract.sol";
 
 contract ReceiverExampleContract {
+    struct CcmpMessage {
+        uint256 index;
+        uint256 from_chain_id;
+        uint256 to_chain_id;
+        bytes sender;
+        bytes message;
+        address receiver;
+    }
+
+    uint8 constant PLAIN_TAG = 0;
+    uint8 constant ABI_ENCODE_PACKED_TAG = 1;
+    uint8 constant ABI_ENCODE_TAG = 2;
+    uint8 constant BATCH_ROOT_TAG = 255;
+
     ICcmpContract public ccmp_contract;
     bytes message;
 
@@ -22,8 +38,49 @@ contract ReceiverExampleContract {
     ) public {
         bytes32 message_hash = keccak256(abi.encodePacked(_index, _from_chain_id, _to_chain_id, _sender, _message, _receiver));
 
-        require(ccmp_contract.isValidMessage(message_hash, _signature), "invalid signature");
+        bytes32 digest = ccmp_contract.ccmpDigest(ABI_ENCODE_PACKED_TAG, message_hash);
+
+        require(ccmp_contract.isValidMessage(digest, _signature), "invalid signature");
+
+        message = _message;
+    }
+
+    function receiveEncodedMessage(bytes memory _encoded, bytes memory _signature) public {
+        bytes32 digest = ccmp_contract.ccmpDigest(ABI_ENCODE_TAG, keccak256(_encoded));
+
+        require(ccmp_contract.isValidMessage(digest, _signature), "invalid signature");
+
+        (, , , , bytes memory _message, ) = abi.decode(_encoded, (uint256, uint256, uint256, bytes, bytes, address));
+
+        message = _message;
+    }
+
+    function receiveRawMessage(bytes memory _message, bytes memory _signature) public {
+        bytes32 digest = ccmp_contract.ccmpDigest(PLAIN_TAG, keccak256(_message));
+
+        require(ccmp_contract.isValidMessage(digest, _signature), "invalid signature");
 
         message = _message;
     }
+
+    function receiveMessageBatch(
+        CcmpMessage[] memory _messages,
+        bytes32[][] memory _proofs,
+        bytes32 _root,
+        bytes memory _signature
+    ) public {
+        require(_messages.length == _proofs.length, "invalid proofs length");
+        bytes32 digest = ccmp_contract.ccmpDigest(BATCH_ROOT_TAG, _root);
+
+        require(ccmp_contract.isValidMessage(digest, _signature), "invalid signature");
+
+        for (uint256 i = 0; i < _messages.length; i++) {
+            CcmpMessage memory _msg = _messages[i];
+            bytes32 leaf = keccak256(abi.encodePacked(_msg.index, _msg.from_chain_id, _msg.to_chain_id, _msg.sender, _msg.message, _msg.receiver));
+
+            require(MerkleProof.verify(_proofs[i], _root, leaf), "invalid proof");
+
+            message = _msg.message;
+        }
+    }
 }
\ No newline at end of file
@@ -7,4 +7,5 @@ interface ICcmpContract {
         bytes32  _message_hash,
         bytes memory _signature
     ) external view returns (bool);
+    function ccmpDigest(uint8 _tag, bytes32 _payload_hash) external pure returns (bytes32);
 }
\ No newline at end of file
@@ -8,7 +8,7 @@ describe("ReceiverExample", function () {
 
     beforeEach(async function () {
         const CcmpContract = await ethers.getContractFactory("CcmpContract");
-        ccmp_contract = await CcmpContract.deploy("0xf3197AbCab1712D6b24Ee09363901Bf7bfb1b1c0");
+        ccmp_contract = await CcmpContract.deploy("0xD42B89AeDdb1848056AD725F1E270dF0AAa4b597");
         
         await ccmp_contract.waitForDeployment();
         const ccmp_contract_address = await ccmp_contract.getAddress();
@@ -24,7 +24,7 @@ describe("ReceiverExample", function () {
         const _sender = "0xe86c4a45c1da21f8838a1ea26fc852bd66489ce9";
         const _message = "0x68656c6c6f20776f726c64";
         const _receiver = "0x9A551f1a0e3416049CfC98cB694a7875757BaA3D";
-        const _signature = "0x0735c2159bd1ddae1186ae09e85dc391a1236abd77bc113ee91d853627ff014b4714d76139b55aafcc04121970fb27210a9eb96fc9563ac22a52df923eaeccc11c";
+        const _signature = "0x3530c3572ea22d19e52347407451381cc004943a3f833be7dfdd47f2684bcfd47267a098795b5631f4c64ee31211f9090c019589675b0835b3e5c077009a80c71c";
         
         await receiver_example_contract.receiveMessage(_index, _from_chain_id, _to_chain_id, _sender, _message, _receiver, _signature);
     });
@@ -1,8 +1,18 @@
+use candid::Principal;
+use futures::FutureExt;
 use scopeguard::defer;
 
-use crate::{log, STORAGE};
-
-const PENDING_TX_BATCH: usize = 10;
+use crate::{
+    jobs::{join_per_chain, RunMeter},
+    log, storage_get,
+    types::{
+        chains::ChainsStorage,
+        daemons::DaemonsStorage,
+        dead_letters::{DeadLettersStorage, FailedStage},
+        pending_tx::PendingTransactionError,
+    },
+    STORAGE,
+};
 
 #[derive(Debug, thiserror::Error)]
 pub enum CheckerError {}
@@ -17,10 +27,13 @@ pub fn run() {
 }
 
 pub async fn check() -> Result<(), CheckerError> {
+    let mut meter = RunMeter::start();
+
     let pending_txs = STORAGE.with(|storage| {
         let mut storage = storage.borrow_mut();
 
-        let drain_end = PENDING_TX_BATCH.min(storage.pending_txs_storage.0.len());
+        let drain_end =
+            (storage.checker_job.batch_size() as usize).min(storage.pending_txs_storage.0.len());
 
         storage
             .pending_txs_storage
@@ -31,55 +44,88 @@ pub async fn check() -> Result<(), CheckerError> {
 
     if pending_txs.is_empty() {
         log!("[CHECKER] finished, no pending txs to check");
+        ChainsStorage::finish_draining();
         STORAGE.with(|storage| {
-            let mut storage = storage.borrow_mut();
-            storage.checker_job.stop();
+            let storage = &mut *storage.borrow_mut();
+            storage.checker_job.stop(&mut storage.scheduler);
         });
         return Ok(());
     }
 
     defer! {
         STORAGE.with(|storage| {
-            let mut storage = storage.borrow_mut();
-            storage.checker_job.run();
+            let storage = &mut *storage.borrow_mut();
+            storage.checker_job.run(&mut storage.scheduler);
         });
     }
 
-    let mut futures = vec![];
-    for pending_tx in pending_txs.iter() {
-        futures.push(pending_tx.clone().check());
-    }
-
-    let results = futures::future::join_all(futures).await;
+    let checks = pending_txs
+        .into_iter()
+        .map(|mut pending_tx| {
+            (
+                pending_tx.message.to_chain_id,
+                async move {
+                    let result = pending_tx.check().await;
+                    (pending_tx, result)
+                }
+                .boxed_local(),
+            )
+        })
+        .collect();
 
-    STORAGE.with(|storage| {
-        let mut storage = storage.borrow_mut();
+    meter.suspend();
 
-        for (i, result) in results.into_iter().enumerate() {
-            let pending_tx = &pending_txs[i];
+    let results = join_per_chain(checks, storage_get!(max_concurrency_per_chain)).await;
+    let checked_number = results.len();
 
-            if let Err(err) = result {
-                log!("[CHECKER] error: {}", err);
-                continue;
-            }
+    let (instructions, latency) = meter.finish();
+    let failed = STORAGE.with(|storage| {
+        let mut storage = storage.borrow_mut();
 
+        let mut failed = vec![];
+        for (pending_tx, result) in results {
+            // a transaction is kept until its nonce is taken, it may have been
+            // sent even if its broadcast failed
             match result {
                 Ok(is_finished) => {
                     if !is_finished {
-                        storage.pending_txs_storage.0.push(pending_tx.clone());
+                        storage.pending_txs_storage.0.push(pending_tx);
                     }
                 }
+                Err(err @ PendingTransactionError::Failed(_)) => {
+                    failed.push((pending_tx, err.to_string()))
+                }
                 Err(err) => {
                     log!("[CHECKER] error: {}", err);
-                    storage.pending_txs_storage.0.push(pending_tx.clone())
+                    storage.pending_txs_storage.0.push(pending_tx)
                 }
             }
         }
+
+        storage
+            .checker_job
+            .record_run(checked_number as u64, instructions, latency);
+
+        failed
     });
 
+    for (pending_tx, err) in failed {
+        log!(
+            "[CHECKER] transaction failed, tx hash: {}, error: {}",
+            pending_tx.tx_hash,
+            err
+        );
+
+        let owner = DaemonsStorage::get_daemon(pending_tx.message.daemon_id)
+            .map_or(Principal::anonymous(), |daemon| daemon.creator);
+        DeadLettersStorage::add(owner, pending_tx.message, FailedStage::Write, err);
+    }
+
+    ChainsStorage::finish_draining();
+
     log!(
         "[CHECKER] finished, pending txs checked: {}",
-        pending_txs.len()
+        checked_number
     );
 
     Ok(())
@@ -1,3 +1,61 @@
 pub mod checker;
 pub mod signer;
 pub mod writer;
+
+use futures::{
+    future::{join_all, LocalBoxFuture},
+    stream, StreamExt,
+};
+use ic_cdk::api::{instruction_counter, time};
+use itertools::Itertools;
+
+/// Measures a run of a job for the adaptation of its batch size.
+pub struct RunMeter {
+    started_at: u64,
+    start_instructions: u64,
+    instructions: u64,
+}
+
+impl RunMeter {
+    pub fn start() -> Self {
+        Self {
+            started_at: time(),
+            start_instructions: instruction_counter(),
+            instructions: 0,
+        }
+    }
+
+    /// Called before the first await, the run is executed inside the tick
+    /// until then.
+    pub fn suspend(&mut self) {
+        self.instructions = instruction_counter().saturating_sub(self.start_instructions);
+    }
+
+    /// Called after the last await, returns the instructions of the heaviest
+    /// message of the run and its latency.
+    pub fn finish(self) -> (u64, u64) {
+        (
+            self.instructions.max(instruction_counter()),
+            time().saturating_sub(self.started_at),
+        )
+    }
+}
+
+/// Runs the futures of every chain concurrently, at most `max_concurrency` of
+/// one chain at a time, and returns their outputs in order per chain.
+pub async fn join_per_chain<'a, T>(
+    futures: Vec<(u64, LocalBoxFuture<'a, T>)>,
+    max_concurrency: u64,
+) -> Vec<T> {
+    let chains = futures
+        .into_iter()
+        .into_group_map()
+        .into_values()
+        .map(|futures| {
+            stream::iter(futures)
+                .buffered(max_concurrency.max(1) as usize)
+                .collect::<Vec<_>>()
+        });
+
+    join_all(chains).await.into_iter().flatten().collect()
+}
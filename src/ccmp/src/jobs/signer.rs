@@ -1,10 +1,19 @@
-use futures::future::join_all;
+use futures::FutureExt;
+use itertools::Itertools;
 use scopeguard::defer;
 use thiserror::Error;
 
-use crate::{log, types::messages::MessageError, STORAGE};
-
-const BATCH_TO_SIGN_SIZE: usize = 10;
+use crate::{
+    jobs::{join_per_chain, RunMeter},
+    log, storage_get,
+    types::{
+        chains::{ChainState, ChainsStorage, CHAIN_REMOVED_ERROR},
+        daemons::{DaemonsStorage, DeliveryMode},
+        dead_letters::{DeadLettersStorage, FailedStage},
+        messages::{Encoding, Message, MessageError},
+    },
+    STORAGE,
+};
 
 #[derive(Error, Debug)]
 pub enum SignerError {
@@ -22,57 +31,154 @@ pub fn run() {
 }
 
 async fn sign() -> Result<(), SignerError> {
-    let messages = STORAGE.with(|storage| {
-        let mut storage = storage.borrow_mut();
+    let is_paused = STORAGE.with(|storage| {
+        let storage = &mut *storage.borrow_mut();
+        if storage.relay_paused {
+            storage.signer_job.stop(&mut storage.scheduler);
+        }
+        storage.relay_paused
+    });
+    if is_paused {
+        log!("[SIGNER] relay is paused");
+        return Ok(());
+    }
 
-        let drain_end = BATCH_TO_SIGN_SIZE.min(storage.listened_messages.len());
+    let mut meter = RunMeter::start();
 
-        storage
+    let (messages, rejected) = STORAGE.with(|storage| {
+        let storage = &mut *storage.borrow_mut();
+        let batch_size = storage.signer_job.batch_size() as usize;
+        let quotas = &storage.quotas_storage;
+        let chains = &storage.chains_storage;
+
+        let rejected = storage
             .listened_messages
-            .drain(..drain_end)
-            .collect::<Vec<_>>()
+            .extract(|message| chains.state_of(message.to_chain_id) == Some(ChainState::Removed));
+
+        // messages to paused chains wait in the queue
+        let messages = storage.listened_messages.drain_fair(
+            batch_size,
+            |owner| quotas.weight(owner),
+            |message| !chains.is_outbound_paused(message.to_chain_id),
+        );
+
+        (messages, rejected)
     });
 
+    for (owner, message) in rejected {
+        DeadLettersStorage::reject(
+            owner,
+            message,
+            FailedStage::Sign,
+            CHAIN_REMOVED_ERROR.to_string(),
+        );
+    }
+
     if messages.is_empty() {
         log!("[SIGNER] finished, no messages to sign");
         STORAGE.with(|storage| {
-            let mut storage = storage.borrow_mut();
-            storage.signer_job.stop();
+            let storage = &mut *storage.borrow_mut();
+            storage.signer_job.stop(&mut storage.scheduler);
         });
         return Ok(());
     }
 
     defer! {
         STORAGE.with(|storage| {
-            let mut storage = storage.borrow_mut();
-            storage.signer_job.run();
+            let storage = &mut *storage.borrow_mut();
+            storage.signer_job.run(&mut storage.scheduler);
         })
     };
 
+    // receivers rebuild the leaves of a batch with `abi.encodePacked`, messages
+    // in other encodings are signed one by one
+    let (batched, single): (Vec<_>, Vec<_>) = messages.into_iter().partition(|(_, message)| {
+        DaemonsStorage::delivery_mode(message.daemon_id) == DeliveryMode::Batched
+            && ChainsStorage::get_chain_metadata(message.to_chain_id).map_or(
+                false,
+                |chain_metadata| {
+                    chain_metadata.chain_type.supports_batches()
+                        && chain_metadata.encoding == Encoding::AbiEncodePacked
+                },
+            )
+    });
+
+    let messages_number = single.len() + batched.len();
+
     let mut futures = vec![];
-    for message in messages {
-        futures.push(message.sign());
+    for (owner, message) in single {
+        futures.push((
+            message.to_chain_id,
+            async move {
+                let result = message.clone().sign().await.map_err(|e| e.to_string());
+                vec![(owner, message, result)]
+            }
+            .boxed_local(),
+        ));
     }
 
-    let mut signed_messages = join_all(futures)
-        .await
-        .iter()
-        .filter_map(|result| match result {
-            Ok(message) => Some(message),
+    // one root is signed per daemon and receiver, the writer delivers such
+    // a batch in one transaction
+    let batches = batched.into_iter().into_group_map_by(|(_, message)| {
+        (
+            message.daemon_id,
+            message.to_chain_id,
+            message.receiver.clone(),
+        )
+    });
+    for ((_, to_chain_id, _), batch) in batches {
+        futures.push((
+            to_chain_id,
+            async move {
+                let messages = batch.iter().map(|(_, message)| message.clone()).collect();
+
+                match Message::sign_batch(messages).await {
+                    Ok(signed_messages) => batch
+                        .into_iter()
+                        .zip(signed_messages)
+                        .map(|((owner, message), signed_message)| {
+                            (owner, message, Ok(signed_message))
+                        })
+                        .collect::<Vec<_>>(),
+                    Err(err) => batch
+                        .into_iter()
+                        .map(|(owner, message)| (owner, message, Err(err.to_string())))
+                        .collect::<Vec<_>>(),
+                }
+            }
+            .boxed_local(),
+        ));
+    }
+
+    meter.suspend();
+
+    let results = join_per_chain(futures, storage_get!(max_concurrency_per_chain)).await;
+
+    let mut signed_messages = vec![];
+    for (owner, message, result) in results.into_iter().flatten() {
+        match result {
+            Ok(signed_message) => signed_messages.push((owner, signed_message)),
             Err(err) => {
                 log!("[SIGNER] error: {}", err);
-                None
+                DeadLettersStorage::add(owner, message, FailedStage::Sign, err);
             }
-        })
-        .cloned()
-        .collect::<Vec<_>>();
+        }
+    }
 
     let signed_messages_number = signed_messages.len();
 
+    let (instructions, latency) = meter.finish();
     STORAGE.with(|storage| {
-        let mut storage = storage.borrow_mut();
-        storage.signed_messages.append(&mut signed_messages);
-        storage.writer_job.start();
+        let storage = &mut *storage.borrow_mut();
+
+        for (owner, message) in signed_messages {
+            storage.signed_messages.push(owner, message);
+        }
+
+        storage.writer_job.start(&mut storage.scheduler);
+        storage
+            .signer_job
+            .record_run(messages_number as u64, instructions, latency);
     });
 
     log!(
@@ -1,15 +1,39 @@
-use futures::future::join_all;
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+};
+
+use candid::Principal;
+use futures::FutureExt;
 use itertools::Itertools;
 use scopeguard::defer;
 use thiserror::Error;
 
-use crate::{log, STORAGE};
-
-const BATCH_TO_WRITE_SIZE: usize = 10;
+use crate::{
+    jobs::{join_per_chain, RunMeter},
+    log, storage_get,
+    types::{
+        chains::{ChainState, ChainType, ChainsStorage, CHAIN_REMOVED_ERROR},
+        daemons::DaemonsStorage,
+        dead_letters::{DeadLettersStorage, FailedStage},
+        lanes::Lane,
+        messages::Message,
+    },
+    STORAGE,
+};
 
 #[derive(Error, Debug)]
 pub enum WriterError {}
 
+// the message with its delivery result, none if it was held back
+type DeliveryResult = (Principal, Message, Option<Result<(), String>>);
+
+thread_local! {
+    // lanes with a delivery to a canister in flight, the rest of such a lane
+    // waits for it
+    static ICP_DELIVERIES: RefCell<HashSet<Lane>> = RefCell::default();
+}
+
 pub fn run() {
     log!("[WRITER] starting]");
     ic_cdk::spawn(async {
@@ -20,51 +44,274 @@ pub fn run() {
 }
 
 async fn write() -> Result<(), WriterError> {
-    let messages = STORAGE.with(|storage| {
-        let mut storage = storage.borrow_mut();
+    let is_paused = STORAGE.with(|storage| {
+        let storage = &mut *storage.borrow_mut();
+        if storage.relay_paused {
+            storage.writer_job.stop(&mut storage.scheduler);
+        }
+        storage.relay_paused
+    });
+    if is_paused {
+        log!("[WRITER] relay is paused");
+        return Ok(());
+    }
 
-        let drain_end = BATCH_TO_WRITE_SIZE.min(storage.signed_messages.len());
+    let mut meter = RunMeter::start();
 
-        storage
+    let blocked_lanes = Lane::blocked_lanes();
+    let delivering_lanes = ICP_DELIVERIES.with(|deliveries| deliveries.borrow().clone());
+
+    let (messages, rejected) = STORAGE.with(|storage| {
+        let storage = &mut *storage.borrow_mut();
+        let batch_size = storage.writer_job.batch_size() as usize;
+        let quotas = &storage.quotas_storage;
+        let chains = &storage.chains_storage;
+
+        let rejected = storage
             .signed_messages
-            .drain(..drain_end)
-            .collect::<Vec<_>>()
+            .extract(|message| chains.state_of(message.to_chain_id) == Some(ChainState::Removed));
+
+        // messages to paused chains wait in the queue
+        let messages = storage.signed_messages.drain_fair(
+            batch_size,
+            |owner| quotas.weight(owner),
+            |message| {
+                Lane::is_open(&blocked_lanes, message)
+                    && !delivering_lanes.contains(&Lane::of(message))
+                    && !chains.is_outbound_paused(message.to_chain_id)
+            },
+        );
+
+        (messages, rejected)
     });
 
+    for (owner, message) in rejected {
+        DeadLettersStorage::reject(
+            owner,
+            message,
+            FailedStage::Write,
+            CHAIN_REMOVED_ERROR.to_string(),
+        );
+    }
+
     if messages.is_empty() {
         log!("[WRITER] finished, no messages to write");
+        ChainsStorage::finish_draining();
         STORAGE.with(|storage| {
-            let mut storage = storage.borrow_mut();
-            storage.writer_job.stop();
+            let storage = &mut *storage.borrow_mut();
+            storage.writer_job.stop(&mut storage.scheduler);
         });
         return Ok(());
     }
 
     defer! {
         STORAGE.with(|storage| {
-            let mut storage = storage.borrow_mut();
-            storage.writer_job.run();
+            let storage = &mut *storage.borrow_mut();
+            storage.writer_job.run(&mut storage.scheduler);
         })
     };
 
-    let futures = messages
+    let messages_number = messages.len();
+
+    let (icp_groups, groups): (Vec<_>, Vec<_>) = messages
         .into_iter()
-        .group_by(|msg| msg.to_chain_id)
+        .into_group_map_by(|(_, msg)| msg.to_chain_id)
         .into_iter()
-        .map(|(_, group)| {
-            let group = group.collect::<Vec<_>>();
-            async move {
-                for message in group {
-                    if let Err(err) = message.send().await {
-                        log!("[WRITER]: error {}", err);
-                    };
-                }
-            }
+        .partition(|(to_chain_id, _)| {
+            ChainsStorage::get_chain_metadata(*to_chain_id).map_or(false, |chain_metadata| {
+                chain_metadata.chain_type == ChainType::Icp
+            })
+        });
+
+    // a canister may take any time to answer, so deliveries to canisters are
+    // not awaited by the run, each lane is delivered on its own and held until
+    // its delivery is done
+    let icp_lanes = icp_groups.into_iter().flat_map(|(_, group)| {
+        group
+            .into_iter()
+            .into_group_map_by(|(_, msg)| Lane::of(msg))
+    });
+    for (lane, group) in icp_lanes {
+        ICP_DELIVERIES.with(|deliveries| deliveries.borrow_mut().insert(lane.clone()));
+
+        ic_cdk::spawn(async move {
+            let results = deliver(group).await;
+            ICP_DELIVERIES.with(|deliveries| deliveries.borrow_mut().remove(&lane));
+            record_results(results);
+        });
+    }
+
+    // the messages of a principal to a chain are sent one by one, so the order
+    // of the queue is the order of its nonces and therefore the order of
+    // delivery, principals are delivered to a chain concurrently up to the cap
+    let futures = groups
+        .into_iter()
+        .flat_map(|(to_chain_id, group)| {
+            group
+                .into_iter()
+                .into_group_map_by(|(owner, _)| *owner)
+                .into_values()
+                .map(move |group| (to_chain_id, deliver(group).boxed_local()))
         })
         .collect::<Vec<_>>();
 
-    join_all(futures).await;
+    meter.suspend();
+
+    let results = join_per_chain(futures, storage_get!(max_concurrency_per_chain)).await;
+    record_results(results.into_iter().flatten().collect());
+
+    ChainsStorage::finish_draining();
+
+    let (instructions, latency) = meter.finish();
+    STORAGE.with(|storage| {
+        storage
+            .borrow_mut()
+            .writer_job
+            .record_run(messages_number as u64, instructions, latency)
+    });
 
     log!("[WRITER] finished]");
     Ok(())
 }
+
+/// Delivers the messages of one chain in their order, a message of an ordered
+/// lane that failed holds back the rest of its lane.
+async fn deliver(group: Vec<(Principal, Message)>) -> Vec<DeliveryResult> {
+    let mut results = vec![];
+    let mut failed_lanes = HashSet::new();
+    for delivery in into_deliveries(group) {
+        let (held, delivery): (Vec<_>, Vec<_>) = delivery
+            .into_iter()
+            .partition(|(_, message)| failed_lanes.contains(&Lane::of(message)));
+
+        results.extend(
+            held.into_iter()
+                .map(|(owner, message)| (owner, message, None)),
+        );
+
+        if delivery.is_empty() {
+            continue;
+        }
+
+        let messages = delivery
+            .iter()
+            .map(|(_, message)| message.clone())
+            .collect::<Vec<_>>();
+
+        let result = if messages[0].batch.is_some() {
+            Message::send_batch(messages).await
+        } else {
+            messages[0].clone().send().await
+        }
+        .map_err(|e| e.to_string());
+
+        for (owner, message) in delivery {
+            if result.is_err() && DaemonsStorage::is_ordered(message.daemon_id) {
+                failed_lanes.insert(Lane::of(&message));
+            }
+
+            results.push((owner, message, Some(result.clone())));
+        }
+    }
+    results
+}
+
+fn record_results(results: Vec<DeliveryResult>) {
+    for (owner, message, result) in results {
+        match result {
+            Some(Ok(())) => DeadLettersStorage::resolve(&message),
+            Some(Err(err)) => {
+                log!("[WRITER]: error {}", err);
+                DeadLettersStorage::add(owner, message, FailedStage::Write, err);
+            }
+            // held behind a failed message of the same ordered lane
+            None => STORAGE.with(|storage| {
+                storage
+                    .borrow_mut()
+                    .signed_messages
+                    .insert_ordered(owner, message)
+            }),
+        }
+    }
+}
+
+/// Splits messages of one chain into transactions: the messages of a batch go
+/// together in the place of the first of them, as the drained messages of
+/// different principals are interleaved, any other message is a transaction
+/// on its own.
+fn into_deliveries(messages: Vec<(Principal, Message)>) -> Vec<Vec<(Principal, Message)>> {
+    let mut deliveries: Vec<Vec<(Principal, Message)>> = vec![];
+    // position of the delivery of each batch, by receiver and root
+    let mut batches: HashMap<(Vec<u8>, Vec<u8>), usize> = HashMap::new();
+
+    for (owner, message) in messages {
+        let Some(batch) = &message.batch else {
+            deliveries.push(vec![(owner, message)]);
+            continue;
+        };
+
+        let key = (message.receiver.clone(), batch.root.clone());
+        match batches.get(&key) {
+            Some(&position) => deliveries[position].push((owner, message)),
+            None => {
+                batches.insert(key, deliveries.len());
+                deliveries.push(vec![(owner, message)]);
+            }
+        }
+    }
+
+    deliveries
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::types::{message_queue::MessageQueue, messages::BatchProof};
+
+    use super::*;
+
+    fn batched(owner: u8, index: u64, root: u8) -> Message {
+        Message {
+            index,
+            daemon_id: owner as u64,
+            receiver: vec![1; 20],
+            batch: Some(BatchProof {
+                root: vec![root; 32],
+                proof: vec![],
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn into_deliveries_keeps_batches_of_interleaved_principals_together() {
+        let (first, second) = (Principal::from_slice(&[1]), Principal::from_slice(&[2]));
+        let mut queue = MessageQueue::default();
+        queue.push(first, batched(1, 0, 1));
+        queue.push(first, batched(1, 1, 1));
+        queue.push(second, batched(2, 0, 2));
+        queue.push(second, batched(2, 1, 2));
+        queue.push(second, Message::default());
+
+        let drained = queue.drain_fair(usize::MAX, |_| 1, |_| true);
+        assert_ne!(drained[1].0, first);
+
+        let deliveries = into_deliveries(drained)
+            .into_iter()
+            .map(|delivery| {
+                delivery
+                    .into_iter()
+                    .map(|(owner, message)| (owner, message.index))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            deliveries,
+            vec![
+                vec![(first, 0), (first, 1)],
+                vec![(second, 0), (second, 1)],
+                vec![(second, 0)],
+            ]
+        );
+    }
+}
@@ -12,7 +12,7 @@ use candid::export_service;
 use ic_cdk::init;
 use ic_cdk_timers::set_timer;
 
-use types::{config::Config, Storage};
+use types::{config::Config, scheduler::Scheduler, Storage};
 
 use crate::types::job::{Job, JobType};
 
@@ -24,20 +24,42 @@ thread_local! {
 
 #[init]
 fn init(config: Config) {
+    config.validate().expect("invalid config");
+
     storage_set!(key, config.key);
 
-    let mut signer_job = Job::new(config.signer_interval_secs, JobType::Signer);
-    let mut writer_job = Job::new(config.writer_interval_secs, JobType::Writer);
-    let mut checker_job = Job::new(config.checker_interval_secs, JobType::Checker);
+    storage_set!(max_concurrency_per_chain, config.max_concurrency_per_chain);
 
-    signer_job.start();
-    writer_job.start();
-    checker_job.start();
+    let mut signer_job = Job::new(
+        config.signer_interval_secs,
+        JobType::Signer,
+        config.signer_batch_size,
+    );
+    let mut writer_job = Job::new(
+        config.writer_interval_secs,
+        JobType::Writer,
+        config.writer_batch_size,
+    );
+    let mut checker_job = Job::new(
+        config.checker_interval_secs,
+        JobType::Checker,
+        config.checker_batch_size,
+    );
+
+    STORAGE.with(|storage| {
+        let storage = &mut *storage.borrow_mut();
+
+        signer_job.start(&mut storage.scheduler);
+        writer_job.start(&mut storage.scheduler);
+        checker_job.start(&mut storage.scheduler);
+    });
 
     storage_set!(signer_job, signer_job);
     storage_set!(writer_job, writer_job);
     storage_set!(checker_job, checker_job);
 
+    Scheduler::start();
+
     set_timer(
         Duration::from_secs(POST_INIT_PASK_DELAY),
         tasks::post_init::execute,
@@ -46,9 +68,25 @@ fn init(config: Config) {
 
 #[allow(dead_code)]
 fn export_candid() -> String {
-    use methods::daemons::RegisterDaemonArgs;
+    use candid::Principal;
+    use ic_cdk::api::management_canister::bitcoin::BitcoinNetwork;
+    use methods::daemons::{QueuePosition, RegisterDaemonArgs};
     use std::collections::HashMap;
-    use types::{balances::Balance, chains::ChainMetadata, config::ConfigUpdate, daemons::Daemon};
+    use types::{
+        audit_log::AuditLogPage,
+        balances::Balance,
+        chains::{ChainMetadata, ChainPause, ChainState, ChainType, PauseScope},
+        config::{ConfigChange, ConfigUpdate},
+        daemons::Daemon,
+        dead_letters::DeadLetter,
+        keys::{KeyDerivation, KeyRotation},
+        lanes::BlockedLane,
+        messages::Encoding,
+        quotas::PrincipalQuota,
+        roles::Role,
+        scheduler::Scheduler,
+    };
+    use utils::{eip712::Eip712Domain, evm_transport::EvmProvider};
 
     export_service!();
     __export_service()
@@ -0,0 +1,30 @@
+use candid::candid_method;
+use ic_cdk::query;
+use thiserror::Error;
+
+use crate::types::{
+    audit_log::{AuditLog, AuditLogPage},
+    roles::{Role, RolesStorage},
+};
+
+#[derive(Error, Debug)]
+pub enum AuditLogError {
+    #[error("caller does not have the {0:?} role")]
+    MissingRole(Role),
+}
+
+/// A page of the audit log of privileged calls, oldest first.
+#[candid_method(query)]
+#[query]
+fn get_audit_log(offset: u64, limit: u64) -> Result<AuditLogPage, String> {
+    _get_audit_log(offset, limit).map_err(|e| e.to_string())
+}
+
+#[inline]
+fn _get_audit_log(offset: u64, limit: u64) -> Result<AuditLogPage, AuditLogError> {
+    if !RolesStorage::has_role(&ic_cdk::caller(), Role::Auditor) {
+        return Err(AuditLogError::MissingRole(Role::Auditor));
+    }
+
+    Ok(AuditLog::get_page(offset, limit))
+}
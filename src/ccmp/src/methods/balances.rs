@@ -6,9 +6,7 @@ use ic_cdk::{
     api::call::{msg_cycles_accept, msg_cycles_available},
     query, update,
 };
-use ic_web3_rs::{
-    ic::pubkey_to_address, transports::ICHttp, types::TransactionId, Error as Web3Error, Web3,
-};
+use ic_web3_rs::{ic::pubkey_to_address, types::TransactionId, Error as Web3Error, Web3};
 
 use crate::{
     log,
@@ -17,7 +15,10 @@ use crate::{
         chains::ChainsStorageError,
         evm_chains::EvmChainsStorage,
     },
-    utils::{transform_processors::call_options, u256_to_nat},
+    utils::{
+        transform_processors::{call_options, Transform},
+        u256_to_nat,
+    },
 };
 
 const DEFAULT_MAX_RESP: u64 = 500_000;
@@ -104,14 +105,14 @@ async fn _add_tokens_to_evm_chain(tx_hash: String, chain_id: u64) -> Result<(),
     let evm_chain =
         EvmChainsStorage::get_chain(chain_id).ok_or(BalancesError::BalanceDoesNotExist)?;
 
-    let w3 = Web3::new(ICHttp::new(&evm_chain.rpc, Some(DEFAULT_MAX_RESP)).unwrap());
+    let w3 = Web3::new(evm_chain.transport(Some(DEFAULT_MAX_RESP), None));
 
     let formatted_tx_hash =
         H256::from_str(&tx_hash).map_err(|e| BalancesError::InvalidTxHash(e.to_string()))?;
 
     let Some(tx_receipt) = w3
         .eth()
-        .transaction_receipt(formatted_tx_hash, call_options("transform".to_string()))
+        .transaction_receipt(formatted_tx_hash, call_options(Transform::Default))
         .await? else {
             return Err(BalancesError::TxDoesNotExist);
         };
@@ -141,7 +142,7 @@ async fn _add_tokens_to_evm_chain(tx_hash: String, chain_id: u64) -> Result<(),
         .eth()
         .transaction(
             TransactionId::Hash(formatted_tx_hash),
-            call_options("transform".to_string()),
+            call_options(Transform::Default),
         )
         .await? else {
             return Err(BalancesError::TxDoesNotExist);
@@ -1,42 +1,76 @@
 use std::collections::HashMap;
 
 use candid::candid_method;
-use ic_cdk::{api::is_controller, query, update};
+use ic_cdk::api::management_canister::bitcoin::BitcoinNetwork;
+use ic_cdk::{query, update};
 use thiserror::Error;
 
 use crate::{
     log,
     types::{
-        chains::{ChainMetadata, ChainsStorage, ChainsStorageError},
+        audit_log::AuditLog,
+        bitcoin_chains::{BitcoinChain, BitcoinChainError, BitcoinChainsStorage},
+        chains::{
+            ChainMetadata, ChainPause, ChainState, ChainType, ChainsStorage, ChainsStorageError,
+            PauseScope, CHAIN_REMOVED_ERROR,
+        },
+        dead_letters::{DeadLettersStorage, FailedStage},
         evm_chains::{EvmChain, EvmChainError, EvmChainsStorage},
+        icp_chains::IcpChain,
+        messages::Encoding,
+        roles::{Role, RolesStorage},
+        solana_chains::{SolanaChain, SolanaChainError, SolanaChainsStorage},
     },
+    utils::{
+        eip712::Eip712Domain,
+        evm_transport::{EvmProvider, EvmTransport},
+    },
+    STORAGE,
 };
 
 #[derive(Error, Debug)]
 enum ChainsError {
     #[error("evm chain error: {0}")]
     EvmChain(#[from] EvmChainError),
-    #[error("caller is not a controller")]
-    CallerIsNotAController,
+    #[error("solana chain error: {0}")]
+    SolanaChain(#[from] SolanaChainError),
+    #[error("bitcoin chain error: {0}")]
+    BitcoinChain(#[from] BitcoinChainError),
+    #[error("caller does not have the {0:?} role")]
+    MissingRole(Role),
     #[error("chains storage error: {0}")]
     ChainsStorage(#[from] ChainsStorageError),
 }
 
+/// Registers an EVM chain, one per chain id. With `genesis_hash` set the rpc,
+/// and any rpc or provider the chain is switched to later, has to report it.
 #[candid_method(update)]
 #[update]
-async fn add_evm_chain(name: String, rpc: String) -> Result<u64, String> {
-    _add_evm_chain(name, rpc).await.map_err(|e| e.to_string())
+async fn add_evm_chain(
+    name: String,
+    rpc: String,
+    genesis_hash: Option<String>,
+) -> Result<u64, String> {
+    _add_evm_chain(name, rpc, genesis_hash)
+        .await
+        .map_err(|e| e.to_string())
 }
 
 #[inline]
-async fn _add_evm_chain(name: String, rpc: String) -> Result<u64, ChainsError> {
-    if !is_controller(&ic_cdk::caller()) {
-        return Err(ChainsError::CallerIsNotAController);
+async fn _add_evm_chain(
+    name: String,
+    rpc: String,
+    genesis_hash: Option<String>,
+) -> Result<u64, ChainsError> {
+    if !RolesStorage::has_role(&ic_cdk::caller(), Role::ChainOperator) {
+        return Err(ChainsError::MissingRole(Role::ChainOperator));
     }
 
-    let evm_chain = EvmChain::new(name, rpc).await?;
+    let evm_chain = EvmChain::new(name, rpc, genesis_hash).await?;
+
+    let id = EvmChainsStorage::add(evm_chain)?;
 
-    let id = EvmChainsStorage::add(evm_chain);
+    AuditLog::record("add_evm_chain", None, EvmChainsStorage::get_chain(id));
 
     log!("[CHAINS] evm chain added, id: {}", id);
 
@@ -45,21 +79,188 @@ async fn _add_evm_chain(name: String, rpc: String) -> Result<u64, ChainsError> {
 
 #[candid_method(update)]
 #[update]
-async fn remove_chain(id: u64) -> Result<(), String> {
-    _remove_chain(id).await.map_err(|e| e.to_string())
+async fn add_solana_chain(name: String, rpc: String) -> Result<u64, String> {
+    _add_solana_chain(name, rpc)
+        .await
+        .map_err(|e| e.to_string())
 }
 
 #[inline]
-async fn _remove_chain(id: u64) -> Result<(), ChainsError> {
-    if !is_controller(&ic_cdk::caller()) {
-        return Err(ChainsError::CallerIsNotAController);
+async fn _add_solana_chain(name: String, rpc: String) -> Result<u64, ChainsError> {
+    if !RolesStorage::has_role(&ic_cdk::caller(), Role::ChainOperator) {
+        return Err(ChainsError::MissingRole(Role::ChainOperator));
     }
 
-    ChainsStorage::remove_chain(id)?;
+    let solana_chain = SolanaChain::new(name, rpc).await?;
 
-    log!("[CHAINS] chain removed, id: {}", id);
+    let id = SolanaChainsStorage::add(solana_chain);
 
-    Ok(())
+    AuditLog::record("add_solana_chain", None, SolanaChainsStorage::get_chain(id));
+
+    log!("[CHAINS] solana chain added, id: {}", id);
+
+    Ok(id)
+}
+
+/// Registers a Bitcoin network as a source chain, daemons on it watch an
+/// address for `OP_RETURN` payloads confirmed `min_confirmations` times, 6 if none.
+/// The rpc node has to run with `-txindex` to serve the transactions.
+#[candid_method(update)]
+#[update]
+async fn add_bitcoin_chain(
+    name: String,
+    network: BitcoinNetwork,
+    rpc: String,
+    min_confirmations: Option<u32>,
+) -> Result<u64, String> {
+    _add_bitcoin_chain(name, network, rpc, min_confirmations)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[inline]
+async fn _add_bitcoin_chain(
+    name: String,
+    network: BitcoinNetwork,
+    rpc: String,
+    min_confirmations: Option<u32>,
+) -> Result<u64, ChainsError> {
+    if !RolesStorage::has_role(&ic_cdk::caller(), Role::ChainOperator) {
+        return Err(ChainsError::MissingRole(Role::ChainOperator));
+    }
+
+    let bitcoin_chain = BitcoinChain::new(name, network, rpc, min_confirmations).await?;
+
+    let id = BitcoinChainsStorage::add(bitcoin_chain);
+
+    AuditLog::record(
+        "add_bitcoin_chain",
+        None,
+        BitcoinChainsStorage::get_chain(id),
+    );
+
+    log!("[CHAINS] bitcoin chain added, id: {}", id);
+
+    Ok(id)
+}
+
+/// Registers the Internet Computer as a chain, messages to it are delivered
+/// to the canister `receiver` by calling its `ccmp_receive` method.
+#[candid_method(update)]
+#[update]
+fn add_icp_chain(name: String) -> Result<u64, String> {
+    _add_icp_chain(name).map_err(|e| e.to_string())
+}
+
+#[inline]
+fn _add_icp_chain(name: String) -> Result<u64, ChainsError> {
+    if !RolesStorage::has_role(&ic_cdk::caller(), Role::ChainOperator) {
+        return Err(ChainsError::MissingRole(Role::ChainOperator));
+    }
+
+    let id = IcpChain::add(name);
+
+    AuditLog::record("add_icp_chain", None, ChainsStorage::get_chain_metadata(id));
+
+    log!("[CHAINS] icp chain added, id: {}", id);
+
+    Ok(id)
+}
+
+/// Removes a chain, refused while daemons listen to it unless `force` is set.
+/// Queued messages to the chain are delivered before it is removed, with
+/// `force` the daemons are stopped and the messages are dead lettered instead.
+#[candid_method(update)]
+#[update]
+fn remove_chain(id: u64, force: bool) -> Result<ChainState, String> {
+    _remove_chain(id, force).map_err(|e| e.to_string())
+}
+
+#[inline]
+fn _remove_chain(id: u64, force: bool) -> Result<ChainState, ChainsError> {
+    if !RolesStorage::has_role(&ic_cdk::caller(), Role::ChainOperator) {
+        return Err(ChainsError::MissingRole(Role::ChainOperator));
+    }
+
+    let before = ChainsStorage::get_chain_metadata(id);
+    let (state, cancelled) = ChainsStorage::remove_chain(id, force)?;
+
+    AuditLog::record(
+        "remove_chain",
+        before,
+        ChainsStorage::get_chain_metadata(id),
+    );
+
+    for (owner, message) in cancelled {
+        DeadLettersStorage::reject(
+            owner,
+            message,
+            FailedStage::Write,
+            CHAIN_REMOVED_ERROR.to_string(),
+        );
+    }
+
+    log!(
+        "[CHAINS] chain removal started, id: {}, state: {:?}",
+        id,
+        state
+    );
+
+    Ok(state)
+}
+
+/// Stops listening to the chain, delivering to it or both, messages to a chain
+/// with a paused outbound side are kept in the queues.
+#[candid_method(update)]
+#[update]
+fn pause_chain(id: u64, scope: PauseScope) -> Result<ChainPause, String> {
+    _pause_chain(id, scope).map_err(|e| e.to_string())
+}
+
+#[inline]
+fn _pause_chain(id: u64, scope: PauseScope) -> Result<ChainPause, ChainsError> {
+    if !RolesStorage::has_role(&ic_cdk::caller(), Role::Pauser) {
+        return Err(ChainsError::MissingRole(Role::Pauser));
+    }
+
+    let before = ChainsStorage::get_chain_metadata(id).map(|chain_metadata| chain_metadata.pause);
+    let pause = ChainsStorage::pause_chain(id, scope)?;
+
+    AuditLog::record("pause_chain", before, Some(pause));
+
+    log!("[CHAINS] chain paused, id: {}, scope: {:?}", id, scope);
+
+    Ok(pause)
+}
+
+#[candid_method(update)]
+#[update]
+fn resume_chain(id: u64, scope: PauseScope) -> Result<ChainPause, String> {
+    _resume_chain(id, scope).map_err(|e| e.to_string())
+}
+
+#[inline]
+fn _resume_chain(id: u64, scope: PauseScope) -> Result<ChainPause, ChainsError> {
+    if !RolesStorage::has_role(&ic_cdk::caller(), Role::Pauser) {
+        return Err(ChainsError::MissingRole(Role::Pauser));
+    }
+
+    let before = ChainsStorage::get_chain_metadata(id).map(|chain_metadata| chain_metadata.pause);
+    let pause = ChainsStorage::resume_chain(id, scope)?;
+
+    AuditLog::record("resume_chain", before, Some(pause));
+
+    // the jobs may have stopped with only messages to the chain left
+    STORAGE.with(|storage| {
+        let storage = &mut *storage.borrow_mut();
+
+        storage.signer_job.start(&mut storage.scheduler);
+        storage.writer_job.start(&mut storage.scheduler);
+    });
+
+    log!("[CHAINS] chain resumed, id: {}, scope: {:?}", id, scope);
+
+    Ok(pause)
 }
 
 #[candid_method(query)]
@@ -74,21 +275,243 @@ fn get_chains_metadata() -> Result<HashMap<u64, ChainMetadata>, String> {
     ChainsStorage::get_chains_metadata().map_err(|e| e.to_string())
 }
 
+/// The local id of the chain registered under a native id: the decimal chain
+/// id of an EVM chain, the genesis hash of a Solana cluster or a Bitcoin network.
+#[candid_method(query)]
+#[query]
+fn get_local_chain_id(chain_type: ChainType, native_id: String) -> Option<u64> {
+    ChainsStorage::get_local_chain_id(chain_type, &native_id)
+}
+
+/// Replaces the rpc of an EVM chain, the new one has to be on the same chain.
 #[candid_method(update)]
 #[update]
-fn update_evm_chain_rpc(id: u64, rpc: String) -> Result<(), String> {
-    _update_evm_chain_rpc(id, rpc).map_err(|e| e.to_string())
+async fn update_evm_chain_rpc(id: u64, rpc: String) -> Result<(), String> {
+    _update_evm_chain_rpc(id, rpc)
+        .await
+        .map_err(|e| e.to_string())
 }
 
 #[inline]
-fn _update_evm_chain_rpc(id: u64, rpc: String) -> Result<(), ChainsError> {
-    if !is_controller(&ic_cdk::caller()) {
-        return Err(ChainsError::CallerIsNotAController);
+async fn _update_evm_chain_rpc(id: u64, rpc: String) -> Result<(), ChainsError> {
+    if !RolesStorage::has_role(&ic_cdk::caller(), Role::ChainOperator) {
+        return Err(ChainsError::MissingRole(Role::ChainOperator));
     }
 
+    let evm_chain = EvmChainsStorage::get_chain(id).ok_or(EvmChainError::EvmChainNotFound)?;
+    evm_chain
+        .verify(EvmTransport::new(&rpc, &evm_chain.provider, None, None))
+        .await?;
+
     EvmChainsStorage::update_rpc(id, rpc)?;
 
+    AuditLog::record(
+        "update_evm_chain_rpc",
+        Some(evm_chain),
+        EvmChainsStorage::get_chain(id),
+    );
+
     log!("[CHAINS] evm chain rpc updated, id: {}", id);
 
     Ok(())
 }
+
+/// Switches the transport of an EVM chain between HTTPS outcalls to its rpc and
+/// the EVM RPC canister, the new provider has to be on the same chain.
+#[candid_method(update)]
+#[update]
+async fn set_evm_chain_provider(id: u64, provider: EvmProvider) -> Result<(), String> {
+    _set_evm_chain_provider(id, provider)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[inline]
+async fn _set_evm_chain_provider(id: u64, provider: EvmProvider) -> Result<(), ChainsError> {
+    if !RolesStorage::has_role(&ic_cdk::caller(), Role::ChainOperator) {
+        return Err(ChainsError::MissingRole(Role::ChainOperator));
+    }
+
+    provider.validate().map_err(EvmChainError::from)?;
+
+    let evm_chain = EvmChainsStorage::get_chain(id).ok_or(EvmChainError::EvmChainNotFound)?;
+
+    evm_chain
+        .verify(EvmTransport::new(&evm_chain.rpc, &provider, None, None))
+        .await?;
+
+    EvmChainsStorage::set_provider(id, provider)?;
+
+    AuditLog::record(
+        "set_evm_chain_provider",
+        Some(evm_chain),
+        EvmChainsStorage::get_chain(id),
+    );
+
+    log!("[CHAINS] evm chain provider updated, id: {}", id);
+
+    Ok(())
+}
+
+#[candid_method(update)]
+#[update]
+fn update_solana_chain_rpc(id: u64, rpc: String) -> Result<(), String> {
+    _update_solana_chain_rpc(id, rpc).map_err(|e| e.to_string())
+}
+
+#[inline]
+fn _update_solana_chain_rpc(id: u64, rpc: String) -> Result<(), ChainsError> {
+    if !RolesStorage::has_role(&ic_cdk::caller(), Role::ChainOperator) {
+        return Err(ChainsError::MissingRole(Role::ChainOperator));
+    }
+
+    let before = SolanaChainsStorage::get_chain(id);
+    SolanaChainsStorage::update_rpc(id, rpc)?;
+
+    AuditLog::record(
+        "update_solana_chain_rpc",
+        before,
+        SolanaChainsStorage::get_chain(id),
+    );
+
+    log!("[CHAINS] solana chain rpc updated, id: {}", id);
+
+    Ok(())
+}
+
+#[candid_method(update)]
+#[update]
+async fn update_bitcoin_chain_rpc(id: u64, rpc: String) -> Result<(), String> {
+    _update_bitcoin_chain_rpc(id, rpc)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[inline]
+async fn _update_bitcoin_chain_rpc(id: u64, rpc: String) -> Result<(), ChainsError> {
+    if !RolesStorage::has_role(&ic_cdk::caller(), Role::ChainOperator) {
+        return Err(ChainsError::MissingRole(Role::ChainOperator));
+    }
+
+    let before = BitcoinChainsStorage::get_chain(id);
+    let network = before
+        .as_ref()
+        .ok_or(BitcoinChainError::BitcoinChainNotFound)?
+        .network;
+
+    BitcoinChain::verify_rpc(&rpc, network).await?;
+
+    BitcoinChainsStorage::update_rpc(id, rpc)?;
+
+    AuditLog::record(
+        "update_bitcoin_chain_rpc",
+        before,
+        BitcoinChainsStorage::get_chain(id),
+    );
+
+    log!("[CHAINS] bitcoin chain rpc updated, id: {}", id);
+
+    Ok(())
+}
+
+#[candid_method(update)]
+#[update]
+fn set_bitcoin_chain_confirmations(id: u64, min_confirmations: u32) -> Result<(), String> {
+    _set_bitcoin_chain_confirmations(id, min_confirmations).map_err(|e| e.to_string())
+}
+
+#[inline]
+fn _set_bitcoin_chain_confirmations(id: u64, min_confirmations: u32) -> Result<(), ChainsError> {
+    if !RolesStorage::has_role(&ic_cdk::caller(), Role::ChainOperator) {
+        return Err(ChainsError::MissingRole(Role::ChainOperator));
+    }
+
+    let before = BitcoinChainsStorage::get_chain(id);
+    BitcoinChainsStorage::set_min_confirmations(id, min_confirmations)?;
+
+    AuditLog::record(
+        "set_bitcoin_chain_confirmations",
+        before,
+        BitcoinChainsStorage::get_chain(id),
+    );
+
+    log!(
+        "[CHAINS] bitcoin chain confirmations updated, id: {}, confirmations: {}",
+        id,
+        min_confirmations
+    );
+
+    Ok(())
+}
+
+/// The address that pays the fees of the caller's messages to Solana chains,
+/// the caller funds it on every Solana chain its messages are delivered to.
+#[candid_method(update)]
+#[update]
+async fn get_solana_fee_payer() -> Result<String, String> {
+    SolanaChain::get_fee_payer(&ic_cdk::caller())
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[candid_method(update)]
+#[update]
+fn set_chain_encoding(id: u64, encoding: Encoding, tagged_digest: bool) -> Result<(), String> {
+    _set_chain_encoding(id, encoding, tagged_digest).map_err(|e| e.to_string())
+}
+
+#[inline]
+fn _set_chain_encoding(
+    id: u64,
+    encoding: Encoding,
+    tagged_digest: bool,
+) -> Result<(), ChainsError> {
+    if !RolesStorage::has_role(&ic_cdk::caller(), Role::ChainOperator) {
+        return Err(ChainsError::MissingRole(Role::ChainOperator));
+    }
+
+    let before = ChainsStorage::get_chain_metadata(id);
+    ChainsStorage::set_encoding(id, encoding, tagged_digest)?;
+
+    AuditLog::record(
+        "set_chain_encoding",
+        before,
+        ChainsStorage::get_chain_metadata(id),
+    );
+
+    log!("[CHAINS] chain encoding updated, id: {}", id);
+
+    Ok(())
+}
+
+#[candid_method(update)]
+#[update]
+fn set_evm_chain_eip712_domain(
+    id: u64,
+    name: String,
+    version: String,
+    verifying_contract: String,
+) -> Result<Eip712Domain, String> {
+    _set_evm_chain_eip712_domain(id, name, version, verifying_contract).map_err(|e| e.to_string())
+}
+
+#[inline]
+fn _set_evm_chain_eip712_domain(
+    id: u64,
+    name: String,
+    version: String,
+    verifying_contract: String,
+) -> Result<Eip712Domain, ChainsError> {
+    if !RolesStorage::has_role(&ic_cdk::caller(), Role::ChainOperator) {
+        return Err(ChainsError::MissingRole(Role::ChainOperator));
+    }
+
+    let before = EvmChainsStorage::get_chain(id).and_then(|evm_chain| evm_chain.eip712_domain);
+    let domain = EvmChainsStorage::set_eip712_domain(id, name, version, verifying_contract)?;
+
+    AuditLog::record("set_evm_chain_eip712_domain", before, Some(domain.clone()));
+
+    log!("[CHAINS] evm chain eip712 domain updated, id: {}", id);
+
+    Ok(domain)
+}
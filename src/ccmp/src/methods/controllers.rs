@@ -1,35 +1,59 @@
 use candid::candid_method;
-use ic_cdk::{api::is_controller, query, update};
+use ic_cdk::{query, update};
 use thiserror::Error;
 
 use crate::{
-    log,
-    types::config::{Config, ConfigUpdate},
+    log, storage_get,
+    types::{
+        audit_log::AuditLog,
+        config::{Config, ConfigChange, ConfigError, ConfigUpdate},
+        roles::{Role, RolesStorage},
+        scheduler::Scheduler,
+        Storage,
+    },
+    STORAGE,
 };
 
 #[derive(Error, Debug)]
 pub enum ControllerError {
-    #[error("caller is not a controller")]
-    CallerIsNotAController,
+    #[error("caller does not have the {0:?} role")]
+    MissingRole(Role),
+    #[error("config error: {0}")]
+    Config(#[from] ConfigError),
 }
 
+/// Validates the update and returns the fields it changes, with `dry_run` set
+/// nothing is applied.
 #[candid_method(update)]
 #[update]
-fn update_config(config: ConfigUpdate) -> Result<(), String> {
-    _update_config(config).map_err(|e| e.to_string())
+fn update_config(config: ConfigUpdate, dry_run: bool) -> Result<Vec<ConfigChange>, String> {
+    _update_config(config, dry_run).map_err(|e| e.to_string())
 }
 
 #[inline]
-fn _update_config(config: ConfigUpdate) -> Result<(), ControllerError> {
-    if !is_controller(&ic_cdk::caller()) {
-        return Err(ControllerError::CallerIsNotAController);
+fn _update_config(
+    config: ConfigUpdate,
+    dry_run: bool,
+) -> Result<Vec<ConfigChange>, ControllerError> {
+    if !RolesStorage::has_role(&ic_cdk::caller(), Role::Admin) {
+        return Err(ControllerError::MissingRole(Role::Admin));
     }
 
+    config.validate()?;
+
+    let changes = config.changes();
+    if dry_run {
+        return Ok(changes);
+    }
+
+    let before = Config::get();
     config.apply();
 
+    AuditLog::record("update_config", Some(before), Some(Config::get()));
+
     log!("[CONTROLLERS] config updated: {:?}", config);
 
-    Ok(())
+    Ok(changes)
 }
 
 #[candid_method(query)]
@@ -40,9 +64,65 @@ fn get_config() -> Result<Config, String> {
 
 #[inline]
 fn _get_config() -> Result<Config, ControllerError> {
-    if !is_controller(&ic_cdk::caller()) {
-        return Err(ControllerError::CallerIsNotAController);
+    if !RolesStorage::has_role(&ic_cdk::caller(), Role::Auditor) {
+        return Err(ControllerError::MissingRole(Role::Auditor));
     }
 
     Ok(Config::get())
 }
+
+#[candid_method(query)]
+#[query]
+fn get_scheduler() -> Result<Scheduler, String> {
+    _get_scheduler().map_err(|e| e.to_string())
+}
+
+#[inline]
+fn _get_scheduler() -> Result<Scheduler, ControllerError> {
+    if !RolesStorage::has_role(&ic_cdk::caller(), Role::Auditor) {
+        return Err(ControllerError::MissingRole(Role::Auditor));
+    }
+
+    Ok(STORAGE.with(|storage| storage.borrow().scheduler.clone()))
+}
+
+/// Stops the signer, the writer and the rebroadcasts of the checker, the circuit
+/// breaker of the whole relay. Daemons keep listening and queue the messages
+/// they find, the checker keeps checking sent transactions.
+#[candid_method(update)]
+#[update]
+fn pause_relay() -> Result<(), String> {
+    _set_relay_paused(true).map_err(|e| e.to_string())
+}
+
+#[candid_method(update)]
+#[update]
+fn resume_relay() -> Result<(), String> {
+    _set_relay_paused(false).map_err(|e| e.to_string())
+}
+
+#[candid_method(query)]
+#[query]
+fn is_relay_paused() -> bool {
+    storage_get!(relay_paused)
+}
+
+#[inline]
+fn _set_relay_paused(paused: bool) -> Result<(), ControllerError> {
+    if !RolesStorage::has_role(&ic_cdk::caller(), Role::Pauser) {
+        return Err(ControllerError::MissingRole(Role::Pauser));
+    }
+
+    let before = Storage::set_relay_paused(paused);
+
+    let method = if paused {
+        "pause_relay"
+    } else {
+        "resume_relay"
+    };
+    AuditLog::record(method, Some(before), Some(paused));
+
+    log!("[CONTROLLERS] relay paused: {}", paused);
+
+    Ok(())
+}
@@ -1,6 +1,6 @@
 use std::time::Duration;
 
-use candid::{candid_method, CandidType};
+use candid::{candid_method, CandidType, Principal};
 use ic_cdk::{query, update};
 use lazy_static::lazy_static;
 use regex::Regex;
@@ -13,15 +13,29 @@ use crate::{
     types::{
         balances::BalancesStorage,
         chains::{ChainType, ChainsStorage},
-        daemons::{Daemon, DaemonsStorage},
+        daemons::{Daemon, DaemonsStorage, DeliveryMode},
+        dead_letters::DEFAULT_MAX_RETRIES,
+        icp_chains::IcpSendersStorage,
+        keys::KeyDerivation,
+        lanes::{BlockedLane, Lane},
+        messages::Message,
+        quotas::QuotasStorage,
     },
+    utils::base58,
     STORAGE,
 };
 
 lazy_static! {
     static ref EVM_ADDRESS_REGEX: Regex = Regex::new(r"^0x[a-fA-F0-9]{40}$").unwrap();
+    // base58 legacy and bech32 segwit addresses of mainnet, testnet and regtest
+    static ref BITCOIN_ADDRESS_REGEX: Regex = Regex::new(
+        r"^([13mn2][1-9A-HJ-NP-Za-km-z]{25,34}|(bc|tb|bcrt)1[02-9ac-hj-np-z]{8,87})$"
+    )
+    .unwrap();
 }
 
+const SOLANA_ADDRESS_LENGTH: usize = 32;
+
 // TODO: calculate more precisely
 const MINIMUM_CYCLES: u64 = 100_000_000_000;
 
@@ -29,6 +43,8 @@ const MINIMUM_CYCLES: u64 = 100_000_000_000;
 pub enum DaemonsError {
     #[error("chain not found")]
     ChainNotFound,
+    #[error("chain is removed")]
+    ChainRemoved,
     #[error("balance not found")]
     BalanceNotFound,
     #[error("invalid ccmp contract address")]
@@ -41,6 +57,10 @@ pub enum DaemonsError {
     NotDaemonCreator,
     #[error("insufficient cycles")]
     InsufficientCycles,
+    #[error("daemons limit exceeded")]
+    DaemonsLimitExceeded,
+    #[error("daemon sends messages of a canister and does not listen")]
+    SenderDaemon,
 }
 
 #[derive(CandidType, Deserialize, Serialize, Debug, Clone, Default, Validate)]
@@ -49,6 +69,38 @@ pub struct RegisterDaemonArgs {
     pub ccmp_contract: String,
     #[validate(range(min = 1, max = 3600))]
     pub interval_in_secs: u64,
+    #[validate(range(max = 20))]
+    pub max_retries: Option<u64>,
+    pub ordered: Option<bool>,
+    pub delivery_mode: Option<DeliveryMode>,
+    pub key_derivation: Option<KeyDerivation>,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone)]
+pub enum QueueStage {
+    Listened,
+    Signed,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone)]
+pub struct QueuePosition {
+    pub daemon_id: u64,
+    pub index: u64,
+    pub to_chain_id: u64,
+    pub stage: QueueStage,
+    pub position: u64,
+}
+
+impl QueuePosition {
+    fn new(stage: QueueStage, position: u64, message: Message) -> Self {
+        Self {
+            daemon_id: message.daemon_id,
+            index: message.index,
+            to_chain_id: message.to_chain_id,
+            stage,
+            position,
+        }
+    }
 }
 
 #[candid_method(update)]
@@ -71,20 +123,34 @@ pub fn _register_daemon(args: RegisterDaemonArgs) -> Result<u64, DaemonsError> {
         return Err(DaemonsError::InsufficientCycles);
     }
 
+    if !QuotasStorage::can_add_daemon(&caller) {
+        return Err(DaemonsError::DaemonsLimitExceeded);
+    }
+
     let Some(chain_metadata) = ChainsStorage::get_chain_metadata(args.listen_chain_id) else {
         return Err(DaemonsError::ChainNotFound);
     };
 
+    if !chain_metadata.state.accepts_messages() {
+        return Err(DaemonsError::ChainRemoved);
+    }
+
     if !is_valid_ccmp_contract(&args.ccmp_contract, chain_metadata.chain_type) {
         return Err(DaemonsError::InvalidCcmpContractAddress);
     }
 
-    let id = DaemonsStorage::add_daemon(
-        args.listen_chain_id,
-        args.ccmp_contract,
-        Duration::from_secs(args.interval_in_secs),
-        caller,
-    );
+    let id = DaemonsStorage::add_daemon(Daemon {
+        creator: caller,
+        listen_chain_id: args.listen_chain_id,
+        ccmp_contract: args.ccmp_contract,
+        interval: Duration::from_secs(args.interval_in_secs),
+        is_active: true,
+        max_retries: args.max_retries.unwrap_or(DEFAULT_MAX_RETRIES),
+        ordered: args.ordered.unwrap_or_default(),
+        delivery_mode: args.delivery_mode.unwrap_or_default(),
+        key_derivation: args.key_derivation.unwrap_or_default(),
+        ..Default::default()
+    });
 
     Daemon::start(id);
 
@@ -98,6 +164,12 @@ pub fn _register_daemon(args: RegisterDaemonArgs) -> Result<u64, DaemonsError> {
 fn is_valid_ccmp_contract(ccmp_contract: &str, chain_type: ChainType) -> bool {
     match chain_type {
         ChainType::Evm => EVM_ADDRESS_REGEX.is_match(ccmp_contract),
+        ChainType::Solana => base58::decode(ccmp_contract).map_or(false, |program_id| {
+            program_id.len() == SOLANA_ADDRESS_LENGTH
+        }),
+        // nothing to listen to, canisters send messages with the canister's methods
+        ChainType::Icp => false,
+        ChainType::Bitcoin => BITCOIN_ADDRESS_REGEX.is_match(ccmp_contract),
         _ => panic!("unknown chain type"),
     }
 }
@@ -141,6 +213,41 @@ fn get_daemons() -> Vec<Daemon> {
     })
 }
 
+/// Returns the caller's queued messages with their positions in the signing and
+/// writing queues, the positions account for the other principals' shares.
+#[candid_method(query)]
+#[query]
+fn get_queue_positions() -> Vec<QueuePosition> {
+    STORAGE.with(|storage| {
+        let storage = storage.borrow();
+
+        let caller = ic_cdk::caller();
+        let weight = |owner: &Principal| storage.quotas_storage.weight(owner);
+
+        let listened = storage
+            .listened_messages
+            .positions(&caller, weight)
+            .into_iter()
+            .map(|(position, message)| QueuePosition::new(QueueStage::Listened, position, message));
+
+        let signed = storage
+            .signed_messages
+            .positions(&caller, weight)
+            .into_iter()
+            .map(|(position, message)| QueuePosition::new(QueueStage::Signed, position, message));
+
+        listened.chain(signed).collect()
+    })
+}
+
+/// Returns the caller's lanes that wait for their head message to be delivered
+/// or discarded, along with the number of messages held behind it.
+#[candid_method(query)]
+#[query]
+fn get_blocked_lanes() -> Vec<BlockedLane> {
+    Lane::blocked_lanes_of(&ic_cdk::caller())
+}
+
 #[candid_method(update)]
 #[update]
 fn start_daemon(id: u64) -> Result<(), String> {
@@ -167,6 +274,18 @@ fn _start_daemon(id: u64) -> Result<(), DaemonsError> {
         return Err(DaemonsError::NotDaemonCreator);
     }
 
+    if IcpSendersStorage::is_sender_daemon(id) {
+        return Err(DaemonsError::SenderDaemon);
+    }
+
+    let is_removed = ChainsStorage::get_chain_metadata(daemon.listen_chain_id)
+        .map_or(true, |chain_metadata| {
+            !chain_metadata.state.accepts_messages()
+        });
+    if is_removed {
+        return Err(DaemonsError::ChainRemoved);
+    }
+
     Daemon::start(id);
 
     Ok(())
@@ -0,0 +1,83 @@
+use candid::candid_method;
+use ic_cdk::{query, update};
+use thiserror::Error;
+
+use crate::{
+    log,
+    types::dead_letters::{DeadLetter, DeadLettersError, DeadLettersStorage},
+    STORAGE,
+};
+
+#[derive(Error, Debug)]
+pub enum DeadLettersMethodsError {
+    #[error("dead letters error: {0}")]
+    DeadLetters(#[from] DeadLettersError),
+    #[error("dead letter not found")]
+    DeadLetterNotFound,
+    #[error("not the owner of this message")]
+    NotMessageOwner,
+}
+
+#[candid_method(query)]
+#[query]
+fn get_dead_letters() -> Vec<DeadLetter> {
+    STORAGE.with(|storage| {
+        let storage = storage.borrow();
+
+        let caller = ic_cdk::caller();
+
+        storage
+            .dead_letters_storage
+            .dead_letters
+            .values()
+            .filter(|dead_letter| dead_letter.owner == caller)
+            .cloned()
+            .collect::<Vec<_>>()
+    })
+}
+
+#[candid_method(update)]
+#[update]
+fn retry_message(id: u64) -> Result<(), String> {
+    _retry_message(id).map_err(|e| e.to_string())
+}
+
+#[inline]
+fn _retry_message(id: u64) -> Result<(), DeadLettersMethodsError> {
+    check_owner(id)?;
+
+    DeadLettersStorage::retry(id)?;
+
+    log!("[DEAD LETTERS] message retried manually, id: {}", id);
+
+    Ok(())
+}
+
+#[candid_method(update)]
+#[update]
+fn discard_message(id: u64) -> Result<(), String> {
+    _discard_message(id).map_err(|e| e.to_string())
+}
+
+#[inline]
+fn _discard_message(id: u64) -> Result<(), DeadLettersMethodsError> {
+    check_owner(id)?;
+
+    DeadLettersStorage::discard(id)?;
+
+    log!("[DEAD LETTERS] message discarded, id: {}", id);
+
+    Ok(())
+}
+
+fn check_owner(id: u64) -> Result<(), DeadLettersMethodsError> {
+    let Some(dead_letter) = DeadLettersStorage::get_dead_letter(id) else {
+        return Err(DeadLettersMethodsError::DeadLetterNotFound);
+    };
+
+    if dead_letter.owner != ic_cdk::caller() {
+        return Err(DeadLettersMethodsError::NotMessageOwner);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,190 @@
+use candid::candid_method;
+use ic_cdk::{query, update};
+use thiserror::Error;
+
+use crate::{
+    log,
+    types::{
+        audit_log::AuditLog,
+        chains::ChainsStorage,
+        daemons::DaemonsStorage,
+        keys::{KeyRotation, KeysError, KeysStorage},
+        messages::Message,
+        roles::{Role, RolesStorage},
+    },
+};
+
+#[derive(Error, Debug)]
+pub enum KeysMethodsError {
+    #[error("daemon not found")]
+    DaemonNotFound,
+    #[error("chain not found")]
+    ChainNotFound,
+    #[error("keys error: {0}")]
+    Keys(#[from] KeysError),
+    #[error("caller does not have the {0:?} role")]
+    MissingRole(Role),
+}
+
+/// Returns the signer of the messages of the daemon to the chain, it is the one
+/// to trust in the `CcmpContract` of the chain: an address for EVM chains and
+/// a public key for chains verifying Schnorr signatures.
+#[candid_method(update)]
+#[update]
+async fn get_signer_address(daemon_id: u64, to_chain_id: u64) -> Result<String, String> {
+    _get_signer_address(daemon_id, to_chain_id)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[inline]
+async fn _get_signer_address(daemon_id: u64, to_chain_id: u64) -> Result<String, KeysMethodsError> {
+    check_route(daemon_id, to_chain_id)?;
+
+    let key = Message::attestation_key(daemon_id, to_chain_id);
+
+    Ok(KeysStorage::get_signer(key).await?)
+}
+
+/// Returns the address of the next key during a key rotation.
+#[candid_method(update)]
+#[update]
+async fn get_next_signer_address(
+    daemon_id: u64,
+    to_chain_id: u64,
+) -> Result<Option<String>, String> {
+    _get_next_signer_address(daemon_id, to_chain_id)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[inline]
+async fn _get_next_signer_address(
+    daemon_id: u64,
+    to_chain_id: u64,
+) -> Result<Option<String>, KeysMethodsError> {
+    check_route(daemon_id, to_chain_id)?;
+
+    let Some(key) = Message::next_attestation_key(daemon_id, to_chain_id) else {
+        return Ok(None);
+    };
+
+    Ok(Some(KeysStorage::get_signer(key).await?))
+}
+
+fn check_route(daemon_id: u64, to_chain_id: u64) -> Result<(), KeysMethodsError> {
+    if DaemonsStorage::get_daemon(daemon_id).is_none() {
+        return Err(KeysMethodsError::DaemonNotFound);
+    }
+
+    if ChainsStorage::get_chain_metadata(to_chain_id).is_none() {
+        return Err(KeysMethodsError::ChainNotFound);
+    }
+
+    Ok(())
+}
+
+#[candid_method(query)]
+#[query]
+fn get_key_rotation() -> Option<KeyRotation> {
+    KeysStorage::get_rotation()
+}
+
+#[candid_method(update)]
+#[update]
+async fn start_key_rotation(next_key: String) -> Result<KeyRotation, String> {
+    _start_key_rotation(next_key)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[inline]
+async fn _start_key_rotation(next_key: String) -> Result<KeyRotation, KeysMethodsError> {
+    if !RolesStorage::has_role(&ic_cdk::caller(), Role::Admin) {
+        return Err(KeysMethodsError::MissingRole(Role::Admin));
+    }
+
+    let before = KeysStorage::get_rotation();
+    let rotation = KeysStorage::start_rotation(next_key).await?;
+
+    AuditLog::record("start_key_rotation", before, Some(rotation.clone()));
+
+    log!(
+        "[KEYS] key rotation started, next key: {}, address: {}",
+        rotation.next_key,
+        rotation.next_address
+    );
+
+    Ok(rotation)
+}
+
+/// Called once the `CcmpContract` of the chain trusts the next key.
+#[candid_method(update)]
+#[update]
+fn switch_chain_key(chain_id: u64) -> Result<(), String> {
+    _switch_chain_key(chain_id).map_err(|e| e.to_string())
+}
+
+#[inline]
+fn _switch_chain_key(chain_id: u64) -> Result<(), KeysMethodsError> {
+    if !RolesStorage::has_role(&ic_cdk::caller(), Role::Admin) {
+        return Err(KeysMethodsError::MissingRole(Role::Admin));
+    }
+
+    let before = KeysStorage::get_rotation();
+    KeysStorage::switch_chain(chain_id)?;
+
+    AuditLog::record("switch_chain_key", before, KeysStorage::get_rotation());
+
+    log!("[KEYS] chain switched to the next key, id: {}", chain_id);
+
+    Ok(())
+}
+
+#[candid_method(update)]
+#[update]
+fn complete_key_rotation() -> Result<(), String> {
+    _complete_key_rotation().map_err(|e| e.to_string())
+}
+
+#[inline]
+fn _complete_key_rotation() -> Result<(), KeysMethodsError> {
+    if !RolesStorage::has_role(&ic_cdk::caller(), Role::Admin) {
+        return Err(KeysMethodsError::MissingRole(Role::Admin));
+    }
+
+    let before = KeysStorage::attestation_key();
+    KeysStorage::complete_rotation()?;
+
+    AuditLog::record(
+        "complete_key_rotation",
+        Some(before),
+        Some(KeysStorage::attestation_key()),
+    );
+
+    Ok(())
+}
+
+#[candid_method(update)]
+#[update]
+fn cancel_key_rotation() -> Result<(), String> {
+    _cancel_key_rotation().map_err(|e| e.to_string())
+}
+
+#[inline]
+fn _cancel_key_rotation() -> Result<(), KeysMethodsError> {
+    if !RolesStorage::has_role(&ic_cdk::caller(), Role::Admin) {
+        return Err(KeysMethodsError::MissingRole(Role::Admin));
+    }
+
+    let rotation = KeysStorage::cancel_rotation()?;
+
+    AuditLog::record("cancel_key_rotation", Some(rotation.clone()), None);
+
+    log!(
+        "[KEYS] key rotation cancelled, next key: {}",
+        rotation.next_key
+    );
+
+    Ok(())
+}
@@ -0,0 +1,120 @@
+use candid::{candid_method, Principal};
+use ic_cdk::update;
+use thiserror::Error;
+
+use crate::{
+    log,
+    types::{
+        balances::BalancesStorage,
+        chains::{ChainType, ChainsStorage},
+        icp_chains::{IcpChain, IcpSendersStorage},
+        messages::Message,
+        quotas::QuotasStorage,
+        MINIMUM_CYCLES,
+    },
+    STORAGE,
+};
+
+const EVM_ADDRESS_LENGTH: usize = 20;
+const SOLANA_ADDRESS_LENGTH: usize = 32;
+
+#[derive(Error, Debug)]
+pub enum MessagesError {
+    #[error("balance not found")]
+    BalanceNotFound,
+    #[error("insufficient cycles")]
+    InsufficientCycles,
+    #[error("queued messages limit exceeded")]
+    QueuedMessagesLimitExceeded,
+    #[error("chain not found")]
+    ChainNotFound,
+    #[error("chain is removed")]
+    ChainRemoved,
+    #[error("icp chain not found")]
+    IcpChainNotFound,
+    #[error("invalid receiver")]
+    InvalidReceiver,
+}
+
+/// Queues a message of the calling canister for signing and delivery, the
+/// returned index identifies it on the destination together with the caller.
+#[candid_method(update)]
+#[update]
+fn send_message(to_chain_id: u64, receiver: Vec<u8>, payload: Vec<u8>) -> Result<u64, String> {
+    _send_message(to_chain_id, receiver, payload).map_err(|e| e.to_string())
+}
+
+#[inline]
+fn _send_message(
+    to_chain_id: u64,
+    receiver: Vec<u8>,
+    payload: Vec<u8>,
+) -> Result<u64, MessagesError> {
+    let caller = ic_cdk::caller();
+
+    let Some(balance) = BalancesStorage::get_balance(&caller) else {
+        return Err(MessagesError::BalanceNotFound);
+    };
+
+    if balance.cycles < MINIMUM_CYCLES {
+        return Err(MessagesError::InsufficientCycles);
+    }
+
+    if !QuotasStorage::can_queue_messages(&caller) {
+        return Err(MessagesError::QueuedMessagesLimitExceeded);
+    }
+
+    let Some(chain_metadata) = ChainsStorage::get_chain_metadata(to_chain_id) else {
+        return Err(MessagesError::ChainNotFound);
+    };
+
+    if !chain_metadata.state.accepts_messages() {
+        return Err(MessagesError::ChainRemoved);
+    }
+
+    if !is_valid_receiver(&receiver, chain_metadata.chain_type) {
+        return Err(MessagesError::InvalidReceiver);
+    }
+
+    let from_chain_id = IcpChain::chain_id().ok_or(MessagesError::IcpChainNotFound)?;
+
+    let daemon_id = IcpSendersStorage::get_or_add(caller, from_chain_id);
+    let index = IcpSendersStorage::next_index(&caller);
+
+    let message = Message {
+        index,
+        from_chain_id,
+        to_chain_id,
+        sender: caller.as_slice().to_vec(),
+        message: payload,
+        receiver,
+        daemon_id,
+        ..Default::default()
+    };
+
+    STORAGE.with(|storage| {
+        let storage = &mut *storage.borrow_mut();
+
+        storage.listened_messages.push(caller, message);
+        storage.signer_job.start(&mut storage.scheduler);
+    });
+
+    log!(
+        "[MESSAGES] message queued, daemon id: {}, index: {}, to chain: {}",
+        daemon_id,
+        index,
+        to_chain_id
+    );
+
+    Ok(index)
+}
+
+fn is_valid_receiver(receiver: &[u8], chain_type: ChainType) -> bool {
+    match chain_type {
+        ChainType::Evm => receiver.len() == EVM_ADDRESS_LENGTH,
+        ChainType::Solana => receiver.len() == SOLANA_ADDRESS_LENGTH,
+        ChainType::Icp => Principal::try_from_slice(receiver).is_ok(),
+        // bitcoin chains are only read from
+        ChainType::Bitcoin | ChainType::Unknown => false,
+    }
+}
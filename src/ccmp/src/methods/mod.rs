@@ -1,7 +1,13 @@
+mod audit_log;
 mod balances;
 mod chains;
 mod controllers;
 pub mod daemons;
+mod dead_letters;
+mod keys;
+mod messages;
+mod quotas;
+mod roles;
 mod transforms;
 
 use candid::candid_method;
@@ -0,0 +1,54 @@
+use candid::{candid_method, Principal};
+use ic_cdk::{query, update};
+use thiserror::Error;
+
+use crate::{
+    log,
+    types::{
+        audit_log::AuditLog,
+        quotas::{PrincipalQuota, QuotasStorage},
+        roles::{Role, RolesStorage},
+    },
+};
+
+#[derive(Error, Debug)]
+pub enum QuotasError {
+    #[error("caller does not have the {0:?} role")]
+    MissingRole(Role),
+}
+
+#[candid_method(update)]
+#[update]
+fn set_quota(principal: Principal, quota: PrincipalQuota) -> Result<(), String> {
+    _set_quota(principal, quota).map_err(|e| e.to_string())
+}
+
+#[inline]
+fn _set_quota(principal: Principal, quota: PrincipalQuota) -> Result<(), QuotasError> {
+    if !RolesStorage::has_role(&ic_cdk::caller(), Role::FeeManager) {
+        return Err(QuotasError::MissingRole(Role::FeeManager));
+    }
+
+    log!(
+        "[QUOTAS] quota updated, principal: {}, quota: {:?}",
+        principal,
+        quota
+    );
+
+    let before = QuotasStorage::get_quota(&principal);
+    QuotasStorage::set_quota(principal, quota.clone());
+
+    AuditLog::record(
+        "set_quota",
+        Some((principal, before)),
+        Some((principal, quota)),
+    );
+
+    Ok(())
+}
+
+#[candid_method(query)]
+#[query]
+fn get_quota() -> PrincipalQuota {
+    QuotasStorage::get_quota(&ic_cdk::caller())
+}
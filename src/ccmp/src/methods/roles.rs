@@ -0,0 +1,102 @@
+use std::collections::HashMap;
+
+use candid::{candid_method, Principal};
+use ic_cdk::{query, update};
+use thiserror::Error;
+
+use crate::{
+    log,
+    types::{
+        audit_log::AuditLog,
+        roles::{Role, RolesStorage},
+    },
+};
+
+#[derive(Error, Debug)]
+pub enum RolesError {
+    #[error("caller does not have the {0:?} role")]
+    MissingRole(Role),
+    #[error("principal already has the role")]
+    AlreadyGranted,
+    #[error("principal does not have the role")]
+    NotGranted,
+}
+
+#[candid_method(update)]
+#[update]
+fn grant_role(principal: Principal, role: Role) -> Result<(), String> {
+    _grant_role(principal, role).map_err(|e| e.to_string())
+}
+
+#[inline]
+fn _grant_role(principal: Principal, role: Role) -> Result<(), RolesError> {
+    if !RolesStorage::has_role(&ic_cdk::caller(), Role::Admin) {
+        return Err(RolesError::MissingRole(Role::Admin));
+    }
+
+    let before = RolesStorage::holders(role);
+    if !RolesStorage::grant(role, principal) {
+        return Err(RolesError::AlreadyGranted);
+    }
+
+    AuditLog::record(
+        "grant_role",
+        Some((role, before)),
+        Some((role, RolesStorage::holders(role))),
+    );
+
+    log!(
+        "[ROLES] role granted, principal: {}, role: {:?}",
+        principal,
+        role
+    );
+
+    Ok(())
+}
+
+#[candid_method(update)]
+#[update]
+fn revoke_role(principal: Principal, role: Role) -> Result<(), String> {
+    _revoke_role(principal, role).map_err(|e| e.to_string())
+}
+
+#[inline]
+fn _revoke_role(principal: Principal, role: Role) -> Result<(), RolesError> {
+    if !RolesStorage::has_role(&ic_cdk::caller(), Role::Admin) {
+        return Err(RolesError::MissingRole(Role::Admin));
+    }
+
+    let before = RolesStorage::holders(role);
+    if !RolesStorage::revoke(role, &principal) {
+        return Err(RolesError::NotGranted);
+    }
+
+    AuditLog::record(
+        "revoke_role",
+        Some((role, before)),
+        Some((role, RolesStorage::holders(role))),
+    );
+
+    log!(
+        "[ROLES] role revoked, principal: {}, role: {:?}",
+        principal,
+        role
+    );
+
+    Ok(())
+}
+
+#[candid_method(query)]
+#[query]
+fn get_role_holders() -> Result<HashMap<Role, Vec<Principal>>, String> {
+    _get_role_holders().map_err(|e| e.to_string())
+}
+
+#[inline]
+fn _get_role_holders() -> Result<HashMap<Role, Vec<Principal>>, RolesError> {
+    if !RolesStorage::has_role(&ic_cdk::caller(), Role::Auditor) {
+        return Err(RolesError::MissingRole(Role::Auditor));
+    }
+
+    Ok(RolesStorage::get_role_holders())
+}
@@ -1,7 +1,14 @@
 use ic_cdk::api::management_canister::http_request::{HttpResponse, TransformArgs};
 use ic_cdk::query;
 
+use crate::utils::transform_processors::{process_response, process_send_raw_transaction_response};
+
+#[query]
+fn transform(args: TransformArgs) -> HttpResponse {
+    process_response(args.response)
+}
+
 #[query]
-fn transform(response: TransformArgs) -> HttpResponse {
-    response.response
+fn transform_send_raw_transaction(args: TransformArgs) -> HttpResponse {
+    process_send_raw_transaction_response(args.response, &args.context)
 }
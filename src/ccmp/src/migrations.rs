@@ -1,31 +1,74 @@
+mod v0;
+
+use std::time::Duration;
+
 use ic_cdk::{post_upgrade, pre_upgrade};
+use ic_cdk_timers::set_timer;
 
 use crate::{
-    types::{daemons::DaemonsStorage, Storage},
-    STORAGE,
+    log, tasks,
+    types::{daemons::DaemonsStorage, scheduler::Scheduler, Storage},
+    POST_INIT_PASK_DELAY, STORAGE,
 };
 
+// saved along with the storage, bumped with a migration from the previous
+// layout whenever a change of the storage can not be decoded from it
+const STORAGE_VERSION: u32 = 1;
+
 #[pre_upgrade]
 fn pre_upgrade() {
     let storage = STORAGE.with(|s| s.take());
 
-    ic_cdk::storage::stable_save((storage,)).expect("Failed to save storage before upgrade");
+    ic_cdk::storage::stable_save((STORAGE_VERSION, storage))
+        .expect("Failed to save storage before upgrade");
 }
 
 #[post_upgrade]
 fn post_upgrade() {
-    let (mut storage,): (Storage,) =
-        ic_cdk::storage::stable_restore().expect("Failed to restore storage after upgrade");
+    let mut storage = restore_storage();
 
-    storage.signer_job.stop();
-    storage.writer_job.stop();
-    storage.checker_job.stop();
+    let Storage {
+        signer_job,
+        writer_job,
+        checker_job,
+        scheduler,
+        ..
+    } = &mut storage;
 
-    storage.signer_job.start();
-    storage.writer_job.start();
-    storage.checker_job.start();
+    signer_job.resume(scheduler);
+    writer_job.resume(scheduler);
+    checker_job.resume(scheduler);
 
     STORAGE.with(|s| s.replace(storage));
 
-    DaemonsStorage::start_active_daemons();
+    DaemonsStorage::resume_active_daemons();
+
+    Scheduler::start();
+
+    // the key is probed again, it may have stopped working since the last probe
+    set_timer(
+        Duration::from_secs(POST_INIT_PASK_DELAY),
+        tasks::post_init::execute,
+    );
+}
+
+fn restore_storage() -> Storage {
+    if let Ok((version, storage)) = ic_cdk::storage::stable_restore::<(u32, Storage)>() {
+        if version != STORAGE_VERSION {
+            ic_cdk::trap(&format!("Unknown storage version: {}", version));
+        }
+
+        return storage;
+    }
+
+    // the storage was saved without a version before
+    let (storage,): (v0::Storage,) =
+        ic_cdk::storage::stable_restore().expect("Failed to restore storage after upgrade");
+
+    log!(
+        "[MIGRATIONS] storage migrated to version {}",
+        STORAGE_VERSION
+    );
+
+    storage.into()
 }
@@ -0,0 +1,278 @@
+//! The storage layout saved before the versioning of the stable storage, only
+//! decoded on an upgrade and converted to the current layout.
+
+use std::{collections::HashMap, time::Duration};
+
+use candid::{CandidType, Nat, Principal};
+use serde::Deserialize;
+
+use crate::types::{
+    balances::{self, BalancesStorage},
+    chains::{self, ChainType},
+    config::{MAX_BATCH_SIZE, MAX_CONCURRENCY_PER_CHAIN},
+    daemons,
+    evm_chains::{self, EvmChainsStorage},
+    job::{self, JobType},
+    message_queue::MessageQueue,
+    messages,
+    pending_tx::{self, PendingTransactionsStorage},
+    Storage as CurrentStorage,
+};
+
+#[derive(CandidType, Deserialize)]
+pub struct Storage {
+    key: String,
+    public_key: String,
+    chains_storage: ChainsStorage,
+    signer_job: Job,
+    writer_job: Job,
+    checker_job: Job,
+    listened_messages: Vec<Message>,
+    signed_messages: Vec<Message>,
+    balances_storage: HashMap<Principal, Balance>,
+    daemon_storage: DaemonsStorage,
+    pending_txs_storage: Vec<PendingTransaction>,
+}
+
+#[derive(CandidType, Deserialize)]
+struct ChainsStorage {
+    chains_count: u64,
+    chains_metadata: HashMap<u64, ChainMetadata>,
+    evm_chains_storage: HashMap<u64, EvmChain>,
+}
+
+#[derive(CandidType, Deserialize)]
+struct ChainMetadata {
+    name: String,
+    chain_type: ChainType,
+}
+
+#[derive(CandidType, Deserialize)]
+struct EvmChain {
+    name: String,
+    id: u64,
+    rpc: String,
+}
+
+#[derive(CandidType, Deserialize)]
+struct Job {
+    interval_secs: u64,
+    is_active: bool,
+    job_type: JobType,
+}
+
+#[derive(CandidType, Deserialize)]
+struct Message {
+    index: u64,
+    from_chain_id: u64,
+    to_chain_id: u64,
+    sender: Vec<u8>,
+    message: Vec<u8>,
+    receiver: Vec<u8>,
+    signature: Option<Vec<u8>>,
+    daemon_id: u64,
+}
+
+#[derive(CandidType, Deserialize)]
+struct Balance {
+    public_key: String,
+    cycles: Nat,
+    chains_data: HashMap<u64, ChainEntry>,
+}
+
+#[derive(CandidType, Deserialize)]
+struct ChainEntry {
+    tokens: Nat,
+    nonce: Vec<u64>,
+    tx_count: u64,
+    last_block: u64,
+}
+
+#[derive(CandidType, Deserialize)]
+struct DaemonsStorage {
+    daemon_count: u64,
+    daemons: HashMap<u64, Daemon>,
+}
+
+#[derive(CandidType, Deserialize)]
+struct Daemon {
+    id: u64,
+    creator: Principal,
+    listen_chain_id: u64,
+    ccmp_contract: String,
+    interval: Duration,
+    is_active: bool,
+}
+
+#[derive(CandidType, Deserialize)]
+struct PendingTransaction {
+    tx_hash: String,
+    message: Message,
+    gas_price: Nat,
+}
+
+impl From<Storage> for CurrentStorage {
+    /// The jobs and the daemons took every queued message per run, so the limits
+    /// start at their maximums. Queued messages are owned by the creators of
+    /// their daemons.
+    fn from(storage: Storage) -> Self {
+        let mut current = CurrentStorage {
+            key: storage.key,
+            public_key: storage.public_key,
+            max_concurrency_per_chain: MAX_CONCURRENCY_PER_CHAIN,
+            ..Default::default()
+        };
+
+        for old_job in [storage.signer_job, storage.writer_job, storage.checker_job] {
+            let mut job = job::Job::new(old_job.interval_secs, old_job.job_type, MAX_BATCH_SIZE);
+            if old_job.is_active {
+                job.start(&mut current.scheduler);
+            }
+
+            match old_job.job_type {
+                JobType::Signer => current.signer_job = job,
+                JobType::Writer => current.writer_job = job,
+                JobType::Checker => current.checker_job = job,
+                JobType::Unknown => {}
+            }
+        }
+
+        let evm_chains = &storage.chains_storage.evm_chains_storage;
+        current.chains_storage = chains::ChainsStorage {
+            chains_count: storage.chains_storage.chains_count,
+            chains_metadata: storage
+                .chains_storage
+                .chains_metadata
+                .into_iter()
+                .map(|(id, metadata)| {
+                    let native_id = evm_chains.get(&id).map(|chain| chain.id.to_string());
+                    let metadata = chains::ChainMetadata::new(
+                        id,
+                        metadata.name,
+                        metadata.chain_type,
+                        native_id,
+                    );
+
+                    (id, metadata)
+                })
+                .collect(),
+            evm_chains_storage: EvmChainsStorage(
+                storage
+                    .chains_storage
+                    .evm_chains_storage
+                    .into_iter()
+                    .map(|(id, chain)| {
+                        let chain = evm_chains::EvmChain {
+                            name: chain.name,
+                            id: chain.id,
+                            rpc: chain.rpc,
+                            ..Default::default()
+                        };
+
+                        (id, chain)
+                    })
+                    .collect(),
+            ),
+            ..Default::default()
+        };
+
+        let daemons: HashMap<u64, daemons::Daemon> = storage
+            .daemon_storage
+            .daemons
+            .into_iter()
+            .map(|(id, daemon)| {
+                let daemon = daemons::Daemon {
+                    id: daemon.id,
+                    creator: daemon.creator,
+                    listen_chain_id: daemon.listen_chain_id,
+                    ccmp_contract: daemon.ccmp_contract,
+                    interval: daemon.interval,
+                    is_active: daemon.is_active,
+                    ..Default::default()
+                };
+
+                (id, daemon)
+            })
+            .collect();
+
+        current.listened_messages = into_queue(storage.listened_messages, &daemons);
+        current.signed_messages = into_queue(storage.signed_messages, &daemons);
+
+        current.daemon_storage = daemons::DaemonsStorage {
+            daemon_count: storage.daemon_storage.daemon_count,
+            daemons,
+        };
+
+        current.pending_txs_storage = PendingTransactionsStorage(
+            storage
+                .pending_txs_storage
+                .into_iter()
+                .map(|tx| {
+                    pending_tx::PendingTransaction::new(tx.tx_hash, tx.message.into(), tx.gas_price)
+                })
+                .collect(),
+        );
+
+        current.balances_storage = BalancesStorage(
+            storage
+                .balances_storage
+                .into_iter()
+                .map(|(principal, balance)| {
+                    let balance = balances::Balance {
+                        public_key: balance.public_key,
+                        cycles: balance.cycles,
+                        chains_data: balance
+                            .chains_data
+                            .into_iter()
+                            .map(|(chain_id, entry)| {
+                                let entry = balances::ChainEntry {
+                                    tokens: entry.tokens,
+                                    nonce: entry.nonce,
+                                    tx_count: entry.tx_count,
+                                    last_block: entry.last_block,
+                                    ..Default::default()
+                                };
+
+                                (chain_id, entry)
+                            })
+                            .collect(),
+                    };
+
+                    (principal, balance)
+                })
+                .collect(),
+        );
+
+        current
+    }
+}
+
+impl From<Message> for messages::Message {
+    fn from(message: Message) -> Self {
+        Self {
+            index: message.index,
+            from_chain_id: message.from_chain_id,
+            to_chain_id: message.to_chain_id,
+            sender: message.sender,
+            message: message.message,
+            receiver: message.receiver,
+            signature: message.signature,
+            daemon_id: message.daemon_id,
+            ..Default::default()
+        }
+    }
+}
+
+fn into_queue(messages: Vec<Message>, daemons: &HashMap<u64, daemons::Daemon>) -> MessageQueue {
+    let mut queue = MessageQueue::default();
+
+    for message in messages {
+        let owner = daemons
+            .get(&message.daemon_id)
+            .map_or(Principal::anonymous(), |daemon| daemon.creator);
+
+        queue.push(owner, message.into());
+    }
+
+    queue
+}
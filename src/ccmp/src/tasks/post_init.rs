@@ -2,7 +2,7 @@ use thiserror::Error;
 
 use crate::{
     log,
-    types::{Storage, StorageError},
+    types::{audit_log::AuditLog, Storage, StorageError},
 };
 
 #[derive(Error, Debug)]
@@ -20,8 +20,19 @@ pub fn execute() {
     })
 }
 
+/// Probes the key of the config after init and after every upgrade, nothing can
+/// be signed without it, so the relay is paused until the key works and the
+/// relay is resumed.
 async fn post_init() -> Result<(), PostInitError> {
-    Storage::get_public_key().await?;
+    if let Err(err) = Storage::get_public_key().await {
+        let before = Storage::set_relay_paused(true);
+        AuditLog::record("post_init", Some(before), Some(true));
+
+        log!("[POST INIT] key probe failed, relay paused");
+
+        return Err(err.into());
+    }
+
     log!("[POST INIT] finished]");
     Ok(())
 }
@@ -0,0 +1,64 @@
+use std::fmt::Debug;
+
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+
+use crate::STORAGE;
+
+pub const MAX_AUDIT_LOG_PAGE_SIZE: u64 = 100;
+
+/// A privileged call that succeeded, with the affected values before and after
+/// it in their debug representation.
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone)]
+pub struct AuditEntry {
+    pub id: u64,
+    pub caller: Principal,
+    pub timestamp: u64,
+    pub method: String,
+    pub before: Option<String>,
+    pub after: Option<String>,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone)]
+pub struct AuditLogPage {
+    pub entries: Vec<AuditEntry>,
+    pub total: u64,
+}
+
+/// Entries are only ever appended, their ids are their positions in the log.
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone, Default)]
+pub struct AuditLog(pub Vec<AuditEntry>);
+
+impl AuditLog {
+    pub fn record<T: Debug>(method: &str, before: Option<T>, after: Option<T>) {
+        STORAGE.with(|storage| {
+            let entries = &mut storage.borrow_mut().audit_log.0;
+
+            entries.push(AuditEntry {
+                id: entries.len() as u64,
+                caller: ic_cdk::caller(),
+                timestamp: ic_cdk::api::time(),
+                method: method.to_string(),
+                before: before.map(|value| format!("{:?}", value)),
+                after: after.map(|value| format!("{:?}", value)),
+            });
+        })
+    }
+
+    /// Entries from `offset` on, oldest first, at most `MAX_AUDIT_LOG_PAGE_SIZE` of them.
+    pub fn get_page(offset: u64, limit: u64) -> AuditLogPage {
+        STORAGE.with(|storage| {
+            let entries = &storage.borrow().audit_log.0;
+
+            AuditLogPage {
+                entries: entries
+                    .iter()
+                    .skip(offset as usize)
+                    .take(limit.min(MAX_AUDIT_LOG_PAGE_SIZE) as usize)
+                    .cloned()
+                    .collect(),
+                total: entries.len() as u64,
+            }
+        })
+    }
+}
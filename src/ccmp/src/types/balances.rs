@@ -13,6 +13,14 @@ pub struct ChainEntry {
     pub nonce: Vec<u64>,
     pub tx_count: u64,
     pub last_block: u64,
+    // the newest processed transaction of a daemon listening a Solana chain
+    pub last_signature: Option<String>,
+    // a Solana daemon reads the transactions between `last_signature` and the
+    // cursor before the newer ones
+    pub signatures_cursor: Option<String>,
+    // the last read transaction of a daemon listening a Bitcoin chain, at `last_block`
+    pub last_txid: Option<String>,
+    pub messages_count: u64,
 }
 
 #[derive(CandidType, Deserialize, Serialize, Debug, Clone, Default)]
@@ -32,7 +40,7 @@ impl Balance {
 }
 
 #[derive(CandidType, Deserialize, Serialize, Debug, Clone, Default)]
-pub struct BalancesStorage(HashMap<Principal, Balance>);
+pub struct BalancesStorage(pub HashMap<Principal, Balance>);
 
 #[allow(dead_code)]
 impl BalancesStorage {
@@ -169,6 +177,74 @@ impl BalancesStorage {
         });
     }
 
+    pub fn update_last_signature(principal: &Principal, chain_id: u64, last_signature: String) {
+        STORAGE.with(|state| {
+            let mut state = state.borrow_mut();
+            let token_entry = state
+                .balances_storage
+                .0
+                .get_mut(principal)
+                .expect("should get a balance")
+                .chains_data
+                .entry(chain_id)
+                .or_insert(ChainEntry::default());
+
+            token_entry.last_signature = Some(last_signature);
+        });
+    }
+
+    pub fn update_signatures_cursor(principal: &Principal, chain_id: u64, cursor: Option<String>) {
+        STORAGE.with(|state| {
+            let mut state = state.borrow_mut();
+            let token_entry = state
+                .balances_storage
+                .0
+                .get_mut(principal)
+                .expect("should get a balance")
+                .chains_data
+                .entry(chain_id)
+                .or_insert(ChainEntry::default());
+
+            token_entry.signatures_cursor = cursor;
+        });
+    }
+
+    pub fn update_last_txid(principal: &Principal, chain_id: u64, height: u64, txid: String) {
+        STORAGE.with(|state| {
+            let mut state = state.borrow_mut();
+            let token_entry = state
+                .balances_storage
+                .0
+                .get_mut(principal)
+                .expect("should get a balance")
+                .chains_data
+                .entry(chain_id)
+                .or_insert(ChainEntry::default());
+
+            token_entry.last_block = height;
+            token_entry.last_txid = Some(txid);
+        });
+    }
+
+    /// Indexes messages of sources which do not index them themselves.
+    pub fn next_message_index(principal: &Principal, chain_id: u64) -> u64 {
+        STORAGE.with(|state| {
+            let mut state = state.borrow_mut();
+            let token_entry = state
+                .balances_storage
+                .0
+                .get_mut(principal)
+                .expect("should get a balance")
+                .chains_data
+                .entry(chain_id)
+                .or_insert(ChainEntry::default());
+
+            token_entry.messages_count += 1;
+
+            token_entry.messages_count - 1
+        })
+    }
+
     pub fn increment_tx_count(principal: &Principal, chain_id: u64) -> u64 {
         STORAGE.with(|state| {
             let mut state = state.borrow_mut();
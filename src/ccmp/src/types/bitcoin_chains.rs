@@ -0,0 +1,331 @@
+use std::collections::HashMap;
+
+use candid::CandidType;
+use ic_cdk::api::management_canister::bitcoin::{
+    bitcoin_get_utxos, BitcoinNetwork, GetUtxosRequest, Utxo, UtxoFilter,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use thiserror::Error;
+
+use super::chains::{ChainMetadata, ChainType};
+use crate::{
+    utils::{
+        bitcoin::{BitcoinError, Transaction},
+        json_rpc::{self, JsonRpcError},
+    },
+    STORAGE,
+};
+
+/// Transactions to the watched address fetched by a daemon in one round.
+pub const BITCOIN_TXS_PER_ROUND: usize = 5;
+pub const BITCOIN_DAEMON_HTTP_OUTCALLS_COUNT: u64 = BITCOIN_TXS_PER_ROUND as u64;
+const DEFAULT_MIN_CONFIRMATIONS: u32 = 6;
+const MAX_UTXO_PAGES: usize = 5;
+// fees of `bitcoin_get_utxos`, paid by ic-cdk from the canister's cycles
+const GET_UTXOS_MAINNET_CYCLES: u64 = 10_000_000_000;
+const GET_UTXOS_TESTNET_CYCLES: u64 = 4_000_000_000;
+
+const CCMP_PAYLOAD_MAGIC: &[u8; 4] = b"CCMP";
+
+#[derive(Error, Debug)]
+pub enum BitcoinChainError {
+    #[error("json rpc error: {0}")]
+    JsonRpc(#[from] JsonRpcError),
+    #[error("bitcoin error: {0}")]
+    Bitcoin(#[from] BitcoinError),
+    #[error("get utxos error: {0}")]
+    GetUtxos(String),
+    #[error("invalid transaction hex: {0}")]
+    InvalidHex(#[from] hex::FromHexError),
+    #[error("rpc returned transaction {actual}, expected {expected}")]
+    TxidMismatch { expected: String, actual: String },
+    #[error("rpc node is on {actual} network, expected {expected}")]
+    NetworkMismatch { expected: String, actual: String },
+    #[error("rpc node has no synced transaction index, it has to run with -txindex")]
+    MissingTxIndex,
+    #[error("bitcoin chain not found")]
+    BitcoinChainNotFound,
+}
+
+/// A Bitcoin network messages are read from. Unspent outputs of the watched
+/// address come from the IC bitcoin integration, the transactions themselves
+/// from the RPC node, checked against their txid.
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone)]
+pub struct BitcoinChain {
+    pub name: String,
+    pub network: BitcoinNetwork,
+    pub rpc: String,
+    pub min_confirmations: u32,
+}
+
+/// The `OP_RETURN` payload of a CCMP transaction:
+/// `"CCMP" || to_chain_id: u64 BE || receiver length: u8 || receiver || message`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BitcoinCcmpPayload {
+    pub to_chain_id: u64,
+    pub receiver: Vec<u8>,
+    pub message: Vec<u8>,
+}
+
+#[derive(Deserialize, Debug)]
+struct BlockchainInfo {
+    chain: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct IndexInfo {
+    synced: bool,
+}
+
+impl BitcoinChain {
+    pub async fn new(
+        name: String,
+        network: BitcoinNetwork,
+        rpc: String,
+        min_confirmations: Option<u32>,
+    ) -> Result<Self, BitcoinChainError> {
+        Self::verify_rpc(&rpc, network).await?;
+
+        Ok(Self {
+            name,
+            network,
+            rpc,
+            min_confirmations: min_confirmations.unwrap_or(DEFAULT_MIN_CONFIRMATIONS),
+        })
+    }
+
+    /// Checks the node is on the network and serves any transaction, which
+    /// `getrawtransaction` does for a node with `-txindex` only.
+    pub async fn verify_rpc(rpc: &str, network: BitcoinNetwork) -> Result<(), BitcoinChainError> {
+        let info: BlockchainInfo = json_rpc::request(rpc, "getblockchaininfo", json!([])).await?;
+
+        let expected = match network {
+            BitcoinNetwork::Mainnet => "main",
+            BitcoinNetwork::Testnet => "test",
+            BitcoinNetwork::Regtest => "regtest",
+        };
+        if info.chain != expected {
+            return Err(BitcoinChainError::NetworkMismatch {
+                expected: expected.to_string(),
+                actual: info.chain,
+            });
+        }
+
+        let indexes: HashMap<String, IndexInfo> =
+            json_rpc::request(rpc, "getindexinfo", json!(["txindex"])).await?;
+        if !indexes.get("txindex").map_or(false, |index| index.synced) {
+            return Err(BitcoinChainError::MissingTxIndex);
+        }
+
+        Ok(())
+    }
+
+    pub fn network_name(&self) -> &'static str {
+        match self.network {
+            BitcoinNetwork::Mainnet => "mainnet",
+            BitcoinNetwork::Testnet => "testnet",
+            BitcoinNetwork::Regtest => "regtest",
+        }
+    }
+
+    pub fn get_utxos_cycles(&self) -> u64 {
+        match self.network {
+            BitcoinNetwork::Mainnet => GET_UTXOS_MAINNET_CYCLES,
+            BitcoinNetwork::Testnet => GET_UTXOS_TESTNET_CYCLES,
+            BitcoinNetwork::Regtest => 0,
+        }
+    }
+
+    /// Returns the confirmed unspent outputs of the address and the number of
+    /// `bitcoin_get_utxos` calls made.
+    pub async fn get_utxos(&self, address: &str) -> Result<(Vec<Utxo>, u64), BitcoinChainError> {
+        let mut utxos = vec![];
+        let mut filter = Some(UtxoFilter::MinConfirmations(self.min_confirmations));
+        let mut calls = 0;
+
+        for _ in 0..MAX_UTXO_PAGES {
+            let (response,) = bitcoin_get_utxos(GetUtxosRequest {
+                address: address.to_string(),
+                network: self.network,
+                filter,
+            })
+            .await
+            .map_err(|(_, msg)| BitcoinChainError::GetUtxos(msg))?;
+            calls += 1;
+
+            utxos.extend(response.utxos);
+
+            let Some(page) = response.next_page else {
+                break;
+            };
+            filter = Some(UtxoFilter::Page(page));
+        }
+
+        Ok((utxos, calls))
+    }
+
+    pub async fn get_transaction(&self, txid: &[u8]) -> Result<Transaction, BitcoinChainError> {
+        let display_txid = Transaction::display_txid(txid);
+
+        let raw: String =
+            json_rpc::request(&self.rpc, "getrawtransaction", json!([display_txid, false])).await?;
+        let transaction = Transaction::parse(&hex::decode(raw)?)?;
+
+        // the node is not trusted with the contents of the transaction
+        if transaction.txid.as_slice() != txid {
+            return Err(BitcoinChainError::TxidMismatch {
+                expected: display_txid,
+                actual: Transaction::display_txid(&transaction.txid),
+            });
+        }
+
+        Ok(transaction)
+    }
+}
+
+impl BitcoinCcmpPayload {
+    pub fn parse(payload: &[u8]) -> Option<Self> {
+        let data = payload.strip_prefix(CCMP_PAYLOAD_MAGIC.as_slice())?;
+
+        if data.len() < 9 {
+            return None;
+        }
+        let (to_chain_id, data) = data.split_at(8);
+        let (receiver_length, data) = data.split_at(1);
+        let receiver_length = receiver_length[0] as usize;
+
+        if data.len() < receiver_length {
+            return None;
+        }
+        let (receiver, message) = data.split_at(receiver_length);
+
+        Some(Self {
+            to_chain_id: u64::from_be_bytes(to_chain_id.try_into().ok()?),
+            receiver: receiver.to_vec(),
+            message: message.to_vec(),
+        })
+    }
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Default, Clone)]
+pub struct BitcoinChainsStorage(pub HashMap<u64, BitcoinChain>);
+
+impl BitcoinChainsStorage {
+    pub fn add(bitcoin_chain: BitcoinChain) -> u64 {
+        STORAGE.with(|storage| {
+            let mut storage = storage.borrow_mut();
+
+            let index = storage.chains_storage.chains_count;
+            storage.chains_storage.chains_metadata.insert(
+                index,
+                ChainMetadata::new(
+                    index,
+                    bitcoin_chain.name.clone(),
+                    ChainType::Bitcoin,
+                    Some(bitcoin_chain.network_name().to_string()),
+                ),
+            );
+            storage
+                .chains_storage
+                .bitcoin_chains_storage
+                .0
+                .insert(index, bitcoin_chain);
+            storage.chains_storage.chains_count += 1;
+
+            index
+        })
+    }
+
+    pub fn get_chain(id: u64) -> Option<BitcoinChain> {
+        STORAGE.with(|storage| {
+            storage
+                .borrow()
+                .chains_storage
+                .bitcoin_chains_storage
+                .0
+                .get(&id)
+                .cloned()
+        })
+    }
+
+    pub fn update_rpc(id: u64, rpc: String) -> Result<(), BitcoinChainError> {
+        Self::update(id, |chain| chain.rpc = rpc)
+    }
+
+    pub fn set_min_confirmations(id: u64, min_confirmations: u32) -> Result<(), BitcoinChainError> {
+        Self::update(id, |chain| chain.min_confirmations = min_confirmations)
+    }
+
+    fn update<F>(id: u64, f: F) -> Result<(), BitcoinChainError>
+    where
+        F: FnOnce(&mut BitcoinChain),
+    {
+        STORAGE.with(|storage| {
+            let mut storage = storage.borrow_mut();
+
+            let chain = storage
+                .chains_storage
+                .bitcoin_chains_storage
+                .0
+                .get_mut(&id)
+                .ok_or(BitcoinChainError::BitcoinChainNotFound)?;
+            f(chain);
+
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn payload(to_chain_id: u64, receiver: &[u8], message: &[u8]) -> Vec<u8> {
+        let mut payload = CCMP_PAYLOAD_MAGIC.to_vec();
+        payload.extend(to_chain_id.to_be_bytes());
+        payload.push(receiver.len() as u8);
+        payload.extend(receiver);
+        payload.extend(message);
+        payload
+    }
+
+    #[test]
+    fn parses_ccmp_payload() {
+        let parsed = BitcoinCcmpPayload::parse(&payload(258, &[0xaa; 20], b"hello"));
+
+        assert_eq!(
+            parsed,
+            Some(BitcoinCcmpPayload {
+                to_chain_id: 258,
+                receiver: vec![0xaa; 20],
+                message: b"hello".to_vec(),
+            })
+        );
+    }
+
+    #[test]
+    fn parses_payload_with_empty_message() {
+        let parsed = BitcoinCcmpPayload::parse(&payload(1, &[0x01, 0x02], &[])).unwrap();
+
+        assert_eq!(parsed.receiver, vec![0x01, 0x02]);
+        assert!(parsed.message.is_empty());
+    }
+
+    #[test]
+    fn rejects_malformed_payloads() {
+        let valid = payload(1, &[0xaa; 20], b"hello");
+
+        let mut wrong_magic = valid.clone();
+        wrong_magic[0] = b'X';
+        assert_eq!(BitcoinCcmpPayload::parse(&wrong_magic), None);
+
+        // no receiver length
+        assert_eq!(BitcoinCcmpPayload::parse(&valid[..12]), None);
+
+        // shorter than the receiver length
+        assert_eq!(BitcoinCcmpPayload::parse(&valid[..20]), None);
+
+        assert_eq!(BitcoinCcmpPayload::parse(&[]), None);
+    }
+}
@@ -1,12 +1,23 @@
 use std::collections::HashMap;
 
 use async_trait::async_trait;
-use candid::CandidType;
+use candid::{CandidType, Principal};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
-use super::{evm_chains::EvmChainsStorage, messages::Message};
-use crate::STORAGE;
+use super::{
+    bitcoin_chains::BitcoinChainsStorage,
+    dead_letters::FailedStage,
+    evm_chains::EvmChainsStorage,
+    messages::{Encoding, Message},
+    pending_tx::PendingTransaction,
+    scheduler::Task,
+    solana_chains::SolanaChainsStorage,
+    Storage,
+};
+use crate::{log, utils::signing::SigningAlgorithm, STORAGE};
+
+pub const CHAIN_REMOVED_ERROR: &str = "destination chain is removed";
 
 #[derive(Error, Debug)]
 pub enum ChainsStorageError {
@@ -14,6 +25,14 @@ pub enum ChainsStorageError {
     UnknownChainType,
     #[error("chain not found")]
     ChainNotFound,
+    #[error("encoding is not supported by the chain")]
+    UnsupportedEncoding,
+    #[error("eip712 domain is not set for the chain")]
+    MissingEip712Domain,
+    #[error("chain is already removed")]
+    ChainRemoved,
+    #[error("daemons listen to the chain: {0:?}")]
+    ActiveDaemons(Vec<u64>),
 }
 
 #[async_trait]
@@ -21,6 +40,8 @@ pub trait Chain {
     type Error;
 
     async fn write(&self, message: Message) -> Result<(), Self::Error>;
+
+    async fn write_batch(&self, messages: Vec<Message>) -> Result<(), Self::Error>;
 }
 
 #[derive(CandidType, Deserialize, Serialize, Debug, Default, Clone, PartialEq)]
@@ -28,17 +49,117 @@ pub enum ChainType {
     #[default]
     Unknown,
     Evm,
+    Solana,
+    Icp,
+    Bitcoin,
+}
+
+impl ChainType {
+    pub fn signing_algorithm(&self) -> SigningAlgorithm {
+        match self {
+            ChainType::Unknown | ChainType::Evm | ChainType::Icp | ChainType::Bitcoin => {
+                SigningAlgorithm::EcdsaSecp256k1
+            }
+            ChainType::Solana => SigningAlgorithm::SchnorrEd25519,
+        }
+    }
+
+    /// Canisters are called by this canister directly, so messages to them
+    /// carry no attestation.
+    pub fn is_attested(&self) -> bool {
+        *self != ChainType::Icp
+    }
+
+    /// Whether a batch of messages can be delivered to the chain in one transaction.
+    pub fn supports_batches(&self) -> bool {
+        *self == ChainType::Evm
+    }
 }
 
+/// Messages address chains by their local id, assigned on registration, while
+/// transactions are bound to the native id of the network: the chain id of EVM
+/// chains, the genesis hash of Solana clusters and the Bitcoin network name.
 #[derive(CandidType, Deserialize, Serialize, Debug, Default, Clone)]
 pub struct ChainMetadata {
+    pub id: u64,
     pub name: String,
     pub chain_type: ChainType,
+    pub native_id: Option<String>,
+    pub encoding: Encoding,
+    // digests are tagged with their encoding, the contracts of chains registered
+    // before tagging verify untagged ones
+    pub tagged_digest: bool,
+    pub state: ChainState,
+    pub pause: ChainPause,
+}
+
+/// A paused chain has its inbound or outbound side, or both, paused as set in
+/// its `pause`. A draining chain takes no new messages and becomes removed once
+/// the queued ones are delivered. A removed chain keeps its records for lookups.
+#[derive(CandidType, Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq)]
+pub enum ChainState {
+    #[default]
+    Active,
+    Paused,
+    Draining,
+    Removed,
+}
+
+impl ChainState {
+    /// Whether new messages to the chain are accepted.
+    pub fn accepts_messages(&self) -> bool {
+        matches!(self, ChainState::Active | ChainState::Paused)
+    }
+}
+
+/// The daemons listening to a chain skip their rounds while its inbound side
+/// is paused. Messages to a chain with a paused outbound side wait in the
+/// queues, in order, and are neither signed nor delivered.
+#[derive(CandidType, Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq)]
+pub struct ChainPause {
+    pub inbound: bool,
+    pub outbound: bool,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+pub enum PauseScope {
+    Inbound,
+    Outbound,
+    Both,
+}
+
+impl ChainPause {
+    fn set(&mut self, scope: PauseScope, paused: bool) {
+        if scope != PauseScope::Outbound {
+            self.inbound = paused;
+        }
+        if scope != PauseScope::Inbound {
+            self.outbound = paused;
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.inbound || self.outbound
+    }
 }
 
 impl ChainMetadata {
-    pub fn new(name: String, chain_type: ChainType) -> Self {
-        Self { name, chain_type }
+    pub fn new(id: u64, name: String, chain_type: ChainType, native_id: Option<String>) -> Self {
+        let encoding = match chain_type {
+            ChainType::Evm | ChainType::Solana => Encoding::AbiEncodePacked,
+            _ => Encoding::Plain,
+        };
+
+        Self {
+            id,
+            name,
+            chain_type,
+            native_id,
+            encoding,
+            tagged_digest: false,
+            state: ChainState::Active,
+            pause: ChainPause::default(),
+        }
     }
 }
 
@@ -47,30 +168,254 @@ pub struct ChainsStorage {
     pub chains_count: u64,
     pub chains_metadata: HashMap<u64, ChainMetadata>,
     pub evm_chains_storage: EvmChainsStorage,
+    pub solana_chains_storage: SolanaChainsStorage,
+    pub bitcoin_chains_storage: BitcoinChainsStorage,
 }
 
 impl ChainsStorage {
-    pub fn remove_chain(id: u64) -> Result<(), ChainsStorageError> {
+    /// Removes the chain, refusing while daemons listen to it unless `force` is
+    /// set. Without `force` the chain drains its queued messages and pending
+    /// transactions first, with it they are cancelled and returned.
+    pub fn remove_chain(
+        id: u64,
+        force: bool,
+    ) -> Result<(ChainState, Vec<(Principal, Message)>), ChainsStorageError> {
+        STORAGE.with(|storage| {
+            let storage = &mut *storage.borrow_mut();
+
+            let state = storage
+                .chains_storage
+                .chains_metadata
+                .get(&id)
+                .ok_or(ChainsStorageError::ChainNotFound)?
+                .state;
+            if state == ChainState::Removed {
+                return Err(ChainsStorageError::ChainRemoved);
+            }
+
+            let daemon_ids = storage
+                .daemon_storage
+                .daemons
+                .values()
+                .filter(|daemon| daemon.listen_chain_id == id && daemon.is_active)
+                .map(|daemon| daemon.id)
+                .collect::<Vec<_>>();
+            if !daemon_ids.is_empty() && !force {
+                return Err(ChainsStorageError::ActiveDaemons(daemon_ids));
+            }
+
+            for daemon_id in daemon_ids {
+                if let Some(daemon) = storage.daemon_storage.daemons.get_mut(&daemon_id) {
+                    daemon.is_active = false;
+                }
+                storage.scheduler.unschedule(Task::Daemon(daemon_id));
+            }
+
+            let mut cancelled = vec![];
+            if force {
+                cancelled.extend(storage.listened_messages.extract(|m| m.to_chain_id == id));
+                cancelled.extend(storage.signed_messages.extract(|m| m.to_chain_id == id));
+
+                // the outcome of a pending transaction is not checked anymore
+                let (pending_txs, kept): (Vec<PendingTransaction>, Vec<_>) =
+                    std::mem::take(&mut storage.pending_txs_storage.0)
+                        .into_iter()
+                        .partition(|pending_tx| pending_tx.message.to_chain_id == id);
+                storage.pending_txs_storage.0 = kept;
+
+                cancelled.extend(pending_txs.into_iter().map(|pending_tx| {
+                    let owner = storage
+                        .daemon_storage
+                        .daemons
+                        .get(&pending_tx.message.daemon_id)
+                        .map_or(Principal::anonymous(), |daemon| daemon.creator);
+
+                    (owner, pending_tx.message)
+                }));
+            }
+
+            let state = if Self::has_pending_work(storage, id) {
+                ChainState::Draining
+            } else {
+                ChainState::Removed
+            };
+            storage
+                .chains_storage
+                .chains_metadata
+                .get_mut(&id)
+                .expect("chain metadata should exist")
+                .state = state;
+
+            Ok((state, cancelled))
+        })
+    }
+
+    pub fn pause_chain(id: u64, scope: PauseScope) -> Result<ChainPause, ChainsStorageError> {
+        Self::set_pause(id, scope, true)
+    }
+
+    pub fn resume_chain(id: u64, scope: PauseScope) -> Result<ChainPause, ChainsStorageError> {
+        Self::set_pause(id, scope, false)
+    }
+
+    fn set_pause(
+        id: u64,
+        scope: PauseScope,
+        paused: bool,
+    ) -> Result<ChainPause, ChainsStorageError> {
         STORAGE.with(|storage| {
             let mut storage = storage.borrow_mut();
+
             let chain_metadata = storage
                 .chains_storage
                 .chains_metadata
-                .get(&id)
+                .get_mut(&id)
                 .ok_or(ChainsStorageError::ChainNotFound)?;
+            if chain_metadata.state == ChainState::Removed {
+                return Err(ChainsStorageError::ChainRemoved);
+            }
+
+            chain_metadata.pause.set(scope, paused);
+
+            // a draining chain stays draining, its pause holds back the delivery
+            // of the remaining messages only
+            if chain_metadata.state != ChainState::Draining {
+                chain_metadata.state = if chain_metadata.pause.is_paused() {
+                    ChainState::Paused
+                } else {
+                    ChainState::Active
+                };
+            }
 
-            match chain_metadata.chain_type {
-                ChainType::Evm => {
-                    storage.chains_storage.chains_metadata.remove(&id);
-                    storage.chains_storage.evm_chains_storage.0.remove(&id);
+            Ok(chain_metadata.pause)
+        })
+    }
 
-                    Ok(())
+    /// Marks draining chains without queued messages and pending transactions
+    /// as removed, called at the end of every run of the writer and the checker.
+    pub fn finish_draining() {
+        STORAGE.with(|storage| {
+            let mut storage = storage.borrow_mut();
+
+            let drained = storage
+                .chains_storage
+                .chains_metadata
+                .values()
+                .filter(|chain_metadata| chain_metadata.state == ChainState::Draining)
+                .map(|chain_metadata| chain_metadata.id)
+                .filter(|id| !Self::has_pending_work(&storage, *id))
+                .collect::<Vec<_>>();
+
+            for id in drained {
+                if let Some(chain_metadata) = storage.chains_storage.chains_metadata.get_mut(&id) {
+                    chain_metadata.state = ChainState::Removed;
                 }
-                _ => Err(ChainsStorageError::UnknownChainType),
+
+                log!("[CHAINS] chain drained and removed, id: {}", id);
             }
         })
     }
 
+    fn has_pending_work(storage: &Storage, id: u64) -> bool {
+        storage
+            .listened_messages
+            .iter()
+            .chain(storage.signed_messages.iter())
+            .any(|(_, message)| message.to_chain_id == id)
+            || storage
+                .pending_txs_storage
+                .0
+                .iter()
+                .any(|pending_tx| pending_tx.message.to_chain_id == id)
+    }
+
+    /// The state of the chain, for use while the storage is borrowed.
+    pub fn state_of(&self, id: u64) -> Option<ChainState> {
+        self.chains_metadata
+            .get(&id)
+            .map(|chain_metadata| chain_metadata.state)
+    }
+
+    /// Whether delivery to the chain is paused, for use while the storage is borrowed.
+    pub fn is_outbound_paused(&self, id: u64) -> bool {
+        self.chains_metadata
+            .get(&id)
+            .map_or(false, |chain_metadata| chain_metadata.pause.outbound)
+    }
+
+    /// Selects the way messages to the chain are encoded for signing and delivery,
+    /// EIP-712 requires the domain of the chain to be set first. Both are set at
+    /// once, as the contract of the chain verifies one encoding and digest.
+    pub fn set_encoding(
+        id: u64,
+        encoding: Encoding,
+        tagged_digest: bool,
+    ) -> Result<(), ChainsStorageError> {
+        STORAGE.with(|storage| {
+            let storage = &mut *storage.borrow_mut();
+            let chains_storage = &mut storage.chains_storage;
+
+            let chain_metadata = chains_storage
+                .chains_metadata
+                .get_mut(&id)
+                .ok_or(ChainsStorageError::ChainNotFound)?;
+
+            match (&chain_metadata.chain_type, encoding) {
+                (ChainType::Evm, Encoding::Eip712) => {
+                    let has_domain = chains_storage
+                        .evm_chains_storage
+                        .0
+                        .get(&id)
+                        .map_or(false, |chain| chain.eip712_domain.is_some());
+
+                    if !has_domain {
+                        return Err(ChainsStorageError::MissingEip712Domain);
+                    }
+                }
+                (ChainType::Evm, _) => {}
+                // the receiver program checks an ed25519 signature of the digest,
+                // typed data is EVM specific
+                (ChainType::Solana, Encoding::Eip712) => {
+                    return Err(ChainsStorageError::UnsupportedEncoding)
+                }
+                (ChainType::Solana, _) => {}
+                _ => return Err(ChainsStorageError::UnsupportedEncoding),
+            }
+
+            if chain_metadata.encoding == encoding && chain_metadata.tagged_digest == tagged_digest
+            {
+                return Ok(());
+            }
+
+            chain_metadata.encoding = encoding;
+            chain_metadata.tagged_digest = tagged_digest;
+
+            // messages signed for the previous digest would be rejected, they are
+            // signed again
+            let signed = storage
+                .signed_messages
+                .extract(|message| message.to_chain_id == id);
+            for (owner, mut message) in signed {
+                message.clear_signatures();
+                storage.listened_messages.insert_ordered(owner, message);
+            }
+
+            for dead_letter in storage.dead_letters_storage.dead_letters.values_mut() {
+                if dead_letter.stage == FailedStage::Write && dead_letter.message.to_chain_id == id
+                {
+                    dead_letter.stage = FailedStage::Sign;
+                    dead_letter.message.clear_signatures();
+                }
+            }
+
+            if !storage.listened_messages.is_empty() {
+                storage.signer_job.start(&mut storage.scheduler);
+            }
+
+            Ok(())
+        })
+    }
+
     pub fn get_chain_metadata(id: u64) -> Option<ChainMetadata> {
         STORAGE.with(|storage| {
             storage
@@ -82,6 +427,23 @@ impl ChainsStorage {
         })
     }
 
+    /// The local id of the chain of the type registered under the native id.
+    pub fn get_local_chain_id(chain_type: ChainType, native_id: &str) -> Option<u64> {
+        STORAGE.with(|storage| {
+            storage
+                .borrow()
+                .chains_storage
+                .chains_metadata
+                .values()
+                .find(|chain_metadata| {
+                    chain_metadata.chain_type == chain_type
+                        && chain_metadata.native_id.as_deref() == Some(native_id)
+                        && chain_metadata.state != ChainState::Removed
+                })
+                .map(|chain_metadata| chain_metadata.id)
+        })
+    }
+
     pub fn get_chains_metadata() -> Result<HashMap<u64, ChainMetadata>, ChainsStorageError> {
         STORAGE.with(|storage| {
             let storage = storage.borrow();
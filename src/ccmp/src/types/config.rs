@@ -1,61 +1,245 @@
 use candid::CandidType;
 use serde::{Deserialize, Serialize};
+use thiserror::Error;
 
-use crate::{storage_get, storage_set, STORAGE};
+use crate::{storage_get, STORAGE};
 
+// a job scheduled with no delay would run again within the same tick
+pub const MIN_JOB_INTERVAL_SECS: u64 = 1;
+pub const MAX_JOB_INTERVAL_SECS: u64 = 24 * 60 * 60;
+pub const MAX_BATCH_SIZE: u64 = 100;
+pub const MAX_CONCURRENCY_PER_CHAIN: u64 = 50;
+
+const INTERVAL_BOUNDS: (u64, u64) = (MIN_JOB_INTERVAL_SECS, MAX_JOB_INTERVAL_SECS);
+const BATCH_SIZE_BOUNDS: (u64, u64) = (1, MAX_BATCH_SIZE);
+const CONCURRENCY_BOUNDS: (u64, u64) = (1, MAX_CONCURRENCY_PER_CHAIN);
+
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error("{0} must be between {1} and {2}, got {3}")]
+    OutOfBounds(&'static str, u64, u64, u64),
+}
+
+/// The batch sizes are the largest batches the jobs take, the jobs shrink them
+/// when their runs get too heavy or slow. At most `max_concurrency_per_chain`
+/// calls to one chain are made at a time by the signer and the checker, and
+/// the writer delivers to a chain for as many principals at a time, the
+/// transactions of one principal one at a time to keep the order.
 #[derive(CandidType, Deserialize, Serialize, Debug, Default, Clone)]
 pub struct Config {
     pub key: String,
     pub signer_interval_secs: u64,
     pub writer_interval_secs: u64,
     pub checker_interval_secs: u64,
+    pub signer_batch_size: u64,
+    pub writer_batch_size: u64,
+    pub checker_batch_size: u64,
+    pub max_concurrency_per_chain: u64,
 }
 
 impl Config {
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        for (field, bounds, value) in self.fields() {
+            check_bounds(field, bounds, value)?;
+        }
+
+        Ok(())
+    }
+
     pub fn get() -> Config {
         Config {
             key: storage_get!(key),
             signer_interval_secs: storage_get!(signer_job).interval_secs,
             writer_interval_secs: storage_get!(writer_job).interval_secs,
             checker_interval_secs: storage_get!(checker_job).interval_secs,
+            signer_batch_size: storage_get!(signer_job).max_batch_size,
+            writer_batch_size: storage_get!(writer_job).max_batch_size,
+            checker_batch_size: storage_get!(checker_job).max_batch_size,
+            max_concurrency_per_chain: storage_get!(max_concurrency_per_chain),
         }
     }
+
+    fn fields(&self) -> [(&'static str, (u64, u64), u64); 7] {
+        [
+            (
+                "signer_interval_secs",
+                INTERVAL_BOUNDS,
+                self.signer_interval_secs,
+            ),
+            (
+                "writer_interval_secs",
+                INTERVAL_BOUNDS,
+                self.writer_interval_secs,
+            ),
+            (
+                "checker_interval_secs",
+                INTERVAL_BOUNDS,
+                self.checker_interval_secs,
+            ),
+            (
+                "signer_batch_size",
+                BATCH_SIZE_BOUNDS,
+                self.signer_batch_size,
+            ),
+            (
+                "writer_batch_size",
+                BATCH_SIZE_BOUNDS,
+                self.writer_batch_size,
+            ),
+            (
+                "checker_batch_size",
+                BATCH_SIZE_BOUNDS,
+                self.checker_batch_size,
+            ),
+            (
+                "max_concurrency_per_chain",
+                CONCURRENCY_BOUNDS,
+                self.max_concurrency_per_chain,
+            ),
+        ]
+    }
 }
 
+/// The key is not a part of the update, the attestation key is changed through
+/// a key rotation only.
 #[derive(CandidType, Deserialize, Serialize, Debug, Default, Clone)]
 pub struct ConfigUpdate {
-    key: Option<String>,
     signer_interval_secs: Option<u64>,
     writer_interval_secs: Option<u64>,
     checker_interval_secs: Option<u64>,
+    signer_batch_size: Option<u64>,
+    writer_batch_size: Option<u64>,
+    checker_batch_size: Option<u64>,
+    max_concurrency_per_chain: Option<u64>,
+}
+
+/// A field the update changes, with its values in their debug representation.
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone)]
+pub struct ConfigChange {
+    pub field: String,
+    pub before: String,
+    pub after: String,
 }
 
 impl ConfigUpdate {
-    pub fn apply(&self) {
-        if let Some(key) = &self.key {
-            storage_set!(key, key.clone());
+    /// Checks every field of the update, nothing is applied unless all are valid.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        for (field, bounds, value) in self.fields() {
+            if let Some(value) = value {
+                check_bounds(field, bounds, value)?;
+            }
         }
 
+        Ok(())
+    }
+
+    /// The fields whose values differ from the current config.
+    pub fn changes(&self) -> Vec<ConfigChange> {
+        Config::get()
+            .fields()
+            .into_iter()
+            .zip(self.fields())
+            .filter_map(|((field, _, before), (_, _, after))| match after {
+                Some(after) if after != before => Some(ConfigChange {
+                    field: field.to_string(),
+                    before: format!("{:?}", before),
+                    after: format!("{:?}", after),
+                }),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn apply(&self) {
         STORAGE.with(|storage| {
-            let mut storage = storage.borrow_mut();
+            let storage = &mut *storage.borrow_mut();
 
             if let Some(signer_interval_secs) = &self.signer_interval_secs {
                 storage
                     .signer_job
-                    .update_interval_secs(*signer_interval_secs);
+                    .update_interval_secs(*signer_interval_secs, &mut storage.scheduler);
             }
 
             if let Some(writer_interval_secs) = &self.writer_interval_secs {
                 storage
                     .writer_job
-                    .update_interval_secs(*writer_interval_secs);
+                    .update_interval_secs(*writer_interval_secs, &mut storage.scheduler);
             }
 
             if let Some(checker_interval_secs) = &self.checker_interval_secs {
                 storage
                     .checker_job
-                    .update_interval_secs(*checker_interval_secs);
+                    .update_interval_secs(*checker_interval_secs, &mut storage.scheduler);
+            }
+
+            if let Some(signer_batch_size) = &self.signer_batch_size {
+                storage.signer_job.set_max_batch_size(*signer_batch_size);
+            }
+
+            if let Some(writer_batch_size) = &self.writer_batch_size {
+                storage.writer_job.set_max_batch_size(*writer_batch_size);
+            }
+
+            if let Some(checker_batch_size) = &self.checker_batch_size {
+                storage.checker_job.set_max_batch_size(*checker_batch_size);
+            }
+
+            if let Some(max_concurrency_per_chain) = &self.max_concurrency_per_chain {
+                storage.max_concurrency_per_chain = *max_concurrency_per_chain;
             }
         });
     }
+
+    // in the order of `Config::fields`
+    fn fields(&self) -> [(&'static str, (u64, u64), Option<u64>); 7] {
+        [
+            (
+                "signer_interval_secs",
+                INTERVAL_BOUNDS,
+                self.signer_interval_secs,
+            ),
+            (
+                "writer_interval_secs",
+                INTERVAL_BOUNDS,
+                self.writer_interval_secs,
+            ),
+            (
+                "checker_interval_secs",
+                INTERVAL_BOUNDS,
+                self.checker_interval_secs,
+            ),
+            (
+                "signer_batch_size",
+                BATCH_SIZE_BOUNDS,
+                self.signer_batch_size,
+            ),
+            (
+                "writer_batch_size",
+                BATCH_SIZE_BOUNDS,
+                self.writer_batch_size,
+            ),
+            (
+                "checker_batch_size",
+                BATCH_SIZE_BOUNDS,
+                self.checker_batch_size,
+            ),
+            (
+                "max_concurrency_per_chain",
+                CONCURRENCY_BOUNDS,
+                self.max_concurrency_per_chain,
+            ),
+        ]
+    }
+}
+
+fn check_bounds(
+    field: &'static str,
+    (min, max): (u64, u64),
+    value: u64,
+) -> Result<(), ConfigError> {
+    if !(min..=max).contains(&value) {
+        return Err(ConfigError::OutOfBounds(field, min, max, value));
+    }
+
+    Ok(())
 }
@@ -6,29 +6,46 @@ use ethabi::{
     Error as EthabiError, Event, EventParam, ParamType, RawLog,
 };
 use ic_cdk::api::instruction_counter;
-use ic_cdk_timers::{clear_timer, set_timer, TimerId};
 use ic_web3_rs::{
-    transports::ICHttp,
     types::{BlockNumber, FilterBuilder},
     Error as Web3Error, Web3,
 };
+use itertools::Itertools;
 use lazy_static::lazy_static;
 use scopeguard::defer;
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    log, storage_get,
-    types::chains::{ChainType, ChainsStorage},
-    utils::transform_processors::call_options,
+    log,
+    types::chains::{ChainState, ChainType, ChainsStorage, CHAIN_REMOVED_ERROR},
+    utils::{
+        bitcoin::Transaction,
+        transform_processors::{call_options, Transform},
+    },
     STORAGE,
 };
 
 use super::{
-    balances::BalancesStorage, evm_chains::EvmChainsStorage, messages::Message,
+    balances::BalancesStorage,
+    bitcoin_chains::{
+        BitcoinCcmpPayload, BitcoinChainError, BitcoinChainsStorage,
+        BITCOIN_DAEMON_HTTP_OUTCALLS_COUNT, BITCOIN_TXS_PER_ROUND,
+    },
+    dead_letters::{DeadLettersStorage, FailedStage, DEFAULT_MAX_RETRIES},
+    evm_chains::EvmChainsStorage,
+    keys::KeyDerivation,
+    messages::Message,
+    quotas::QuotasStorage,
+    scheduler::{Scheduler, Task},
+    solana_chains::{
+        parse_ccmp_events, SolanaChainError, SolanaChainsStorage,
+        SOLANA_DAEMON_HTTP_OUTCALLS_COUNT, SOLANA_SIGNATURES_LIMIT,
+        SOLANA_SIGNATURE_PAGES_PER_ROUND, SOLANA_TXS_PER_ROUND,
+    },
     HTTP_OUTCALL_CYCLES_COST, MINIMUM_CYCLES,
 };
 
-const DAEMON_HTTP_OUTCALLS_COUNT: u64 = 2;
+pub const DAEMON_HTTP_OUTCALLS_COUNT: u64 = 2;
 const DAEMON_JOB_CYCLES_COST: u64 = 2_000_000;
 
 lazy_static! {
@@ -72,6 +89,20 @@ pub enum DaemonsError {
     Web3(#[from] Web3Error),
     #[error("ethabi error: {0}")]
     Ethabi(#[from] EthabiError),
+    #[error("solana chain error: {0}")]
+    SolanaChain(#[from] SolanaChainError),
+    #[error("bitcoin chain error: {0}")]
+    BitcoinChain(#[from] BitcoinChainError),
+}
+
+/// How the daemon's messages are delivered: one transaction per message, or
+/// messages to the same receiver packed into one transaction under a signed
+/// Merkle root.
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq)]
+pub enum DeliveryMode {
+    #[default]
+    Single,
+    Batched,
 }
 
 #[derive(CandidType, Deserialize, Serialize, Debug, Clone)]
@@ -82,7 +113,10 @@ pub struct Daemon {
     pub ccmp_contract: String,
     pub interval: Duration,
     pub is_active: bool,
-    pub timer_id: String,
+    pub max_retries: u64,
+    pub ordered: bool,
+    pub delivery_mode: DeliveryMode,
+    pub key_derivation: KeyDerivation,
 }
 
 impl Default for Daemon {
@@ -94,7 +128,10 @@ impl Default for Daemon {
             ccmp_contract: "".to_string(),
             interval: Duration::from_secs(0),
             is_active: false,
-            timer_id: "".to_string(),
+            max_retries: DEFAULT_MAX_RETRIES,
+            ordered: false,
+            delivery_mode: DeliveryMode::Single,
+            key_derivation: KeyDerivation::Shared,
         }
     }
 }
@@ -106,26 +143,13 @@ pub struct DaemonsStorage {
 }
 
 impl DaemonsStorage {
-    pub fn add_daemon(
-        listen_chain_id: u64,
-        ccmp_contract: String,
-        interval: Duration,
-        creator: Principal,
-    ) -> u64 {
+    /// Stores the daemon under the next id, the id of `daemon` is ignored.
+    pub fn add_daemon(mut daemon: Daemon) -> u64 {
         STORAGE.with(|storage| {
             let mut storage = storage.borrow_mut();
 
             let id = storage.daemon_storage.daemon_count;
-
-            let daemon = Daemon {
-                id,
-                creator,
-                listen_chain_id,
-                ccmp_contract,
-                interval,
-                is_active: true,
-                ..Default::default()
-            };
+            daemon.id = id;
 
             storage.daemon_storage.daemons.insert(id, daemon);
             storage.daemon_storage.daemon_count += 1;
@@ -142,31 +166,123 @@ impl DaemonsStorage {
         })
     }
 
-    pub fn start_active_daemons() {
-        for (id, daemon) in storage_get!(daemon_storage).daemons.iter() {
-            if daemon.is_active {
-                Daemon::start(*id);
+    pub fn is_ordered(id: u64) -> bool {
+        STORAGE.with(|storage| {
+            storage
+                .borrow()
+                .daemon_storage
+                .daemons
+                .get(&id)
+                .map_or(false, |daemon| daemon.ordered)
+        })
+    }
+
+    pub fn key_derivation(id: u64) -> KeyDerivation {
+        STORAGE.with(|storage| {
+            storage
+                .borrow()
+                .daemon_storage
+                .daemons
+                .get(&id)
+                .map_or(KeyDerivation::Shared, |daemon| daemon.key_derivation)
+        })
+    }
+
+    pub fn delivery_mode(id: u64) -> DeliveryMode {
+        STORAGE.with(|storage| {
+            storage
+                .borrow()
+                .daemon_storage
+                .daemons
+                .get(&id)
+                .map_or(DeliveryMode::Single, |daemon| daemon.delivery_mode)
+        })
+    }
+
+    /// Puts active daemons that are missing from the scheduler queue back into it,
+    /// e.g. after an upgrade from a state that was still using per daemon timers.
+    pub fn resume_active_daemons() {
+        STORAGE.with(|storage| {
+            let storage = &mut *storage.borrow_mut();
+
+            for daemon in storage.daemon_storage.daemons.values() {
+                if daemon.is_active && !storage.scheduler.is_scheduled(Task::Daemon(daemon.id)) {
+                    Daemon::schedule(daemon, &mut storage.scheduler);
+                }
             }
-        }
+        })
     }
 }
 
 impl Daemon {
     pub async fn listen(id: u64) -> Result<(), DaemonsError> {
         let daemon = DaemonsStorage::get_daemon(id).expect("Daemon not found");
-        defer! {
-            Self::start(id);
-            Self::collect_listening_cycles(id, daemon.creator);
-        };
+
+        // the chain is not read any further until the creator's queue is drained,
+        // so no messages are lost and nothing is charged for a skipped round
+        let queue_room = QuotasStorage::queue_room(&daemon.creator);
+        if queue_room == 0 {
+            log!(
+                "[DAEMONS] queued messages limit reached, daemon id: {}, principal: {}",
+                id,
+                daemon.creator
+            );
+            Self::schedule_next(id);
+            return Ok(());
+        }
 
         let chain_metadata = ChainsStorage::get_chain_metadata(daemon.listen_chain_id)
             .expect("Chain metadata not found");
 
-        let mut messages = match chain_metadata.chain_type {
-            ChainType::Evm => Self::listen_evm_chain(&daemon).await?,
+        match chain_metadata.state {
+            ChainState::Paused if chain_metadata.pause.inbound => {
+                Self::schedule_next(id);
+                return Ok(());
+            }
+            ChainState::Active | ChainState::Paused => {}
+            ChainState::Draining | ChainState::Removed => {
+                log!(
+                    "[DAEMONS] listened chain is removed, daemon id: {}, chain id: {}",
+                    id,
+                    daemon.listen_chain_id
+                );
+                Self::stop(id);
+                return Ok(());
+            }
+        }
+
+        let outcalls = match chain_metadata.chain_type {
+            ChainType::Evm => {
+                let evm_chain = EvmChainsStorage::get_chain(daemon.listen_chain_id)
+                    .expect("EVM chain not found");
+
+                DAEMON_HTTP_OUTCALLS_COUNT * evm_chain.provider.outcalls_per_request()
+            }
+            ChainType::Solana => SOLANA_DAEMON_HTTP_OUTCALLS_COUNT,
+            ChainType::Bitcoin => BITCOIN_DAEMON_HTTP_OUTCALLS_COUNT,
+            _ => DAEMON_HTTP_OUTCALLS_COUNT,
+        };
+
+        defer! {
+            Self::collect_listening_cycles(id, daemon.creator, outcalls);
+            Self::schedule_next(id);
+        };
+
+        let messages = match chain_metadata.chain_type {
+            ChainType::Evm => Self::listen_evm_chain(&daemon, queue_room).await?,
+            ChainType::Solana => Self::listen_solana_chain(&daemon, queue_room).await?,
+            ChainType::Bitcoin => Self::listen_bitcoin_chain(&daemon, queue_room).await?,
             _ => panic!("Unsupported chain type"),
         };
 
+        if messages.len() as u64 >= queue_room {
+            log!(
+                "[DAEMONS] queued messages limit reached, daemon id: {}, principal: {}",
+                id,
+                daemon.creator
+            );
+        }
+
         if messages.is_empty() {
             return Ok(());
         }
@@ -177,20 +293,43 @@ impl Daemon {
             messages.len()
         );
 
+        let (mut messages, rejected): (Vec<_>, Vec<_>) =
+            messages.into_iter().partition(|message| {
+                ChainsStorage::get_chain_metadata(message.to_chain_id)
+                    .map_or(true, |chain_metadata| {
+                        chain_metadata.state.accepts_messages()
+                    })
+            });
+        for message in rejected {
+            DeadLettersStorage::reject(
+                daemon.creator,
+                message,
+                FailedStage::Sign,
+                CHAIN_REMOVED_ERROR.to_string(),
+            );
+        }
+
         STORAGE.with(|storage| {
             let mut storage = storage.borrow_mut();
-            storage.listened_messages.append(&mut messages)
+            storage
+                .listened_messages
+                .append(daemon.creator, &mut messages)
         });
 
         STORAGE.with(|storage| {
-            let mut storage = storage.borrow_mut();
-            storage.signer_job.start();
+            let storage = &mut *storage.borrow_mut();
+            storage.signer_job.start(&mut storage.scheduler);
         });
 
         Ok(())
     }
 
-    pub async fn listen_evm_chain(daemon: &Daemon) -> Result<Vec<Message>, DaemonsError> {
+    /// Reads the blocks since the last one read. Once `queue_room` messages are
+    /// read the round stops at the end of a block, the next round goes on from it.
+    pub async fn listen_evm_chain(
+        daemon: &Daemon,
+        queue_room: u64,
+    ) -> Result<Vec<Message>, DaemonsError> {
         let evm_chain =
             EvmChainsStorage::get_chain(daemon.listen_chain_id).expect("EVM chain not found");
         let balance = BalancesStorage::get_balance(&daemon.creator).expect("Balance not found");
@@ -199,12 +338,12 @@ impl Daemon {
             .get(&daemon.listen_chain_id)
             .expect("Chain data not found");
 
-        let w3 = Web3::new(ICHttp::new(&evm_chain.rpc, None).unwrap());
+        let w3 = Web3::new(evm_chain.transport(None, Some(daemon.creator)));
 
         let from_block = chain_data.last_block + 1;
         let to_block = w3
             .eth()
-            .block_number(call_options("transform".to_string()))
+            .block_number(call_options(Transform::Default))
             .await?
             .as_u64();
 
@@ -221,10 +360,7 @@ impl Daemon {
             daemon.id
         );
 
-        let logs = w3
-            .eth()
-            .logs(filter, call_options("transform".to_string()))
-            .await?;
+        let logs = w3.eth().logs(filter, call_options(Transform::Default)).await?;
 
         if logs.is_empty() {
             log!(
@@ -235,35 +371,265 @@ impl Daemon {
             return Ok(vec![]);
         }
 
-        let parsed_logs = logs
+        let mut messages = vec![];
+        let mut last_block = to_block;
+        let mut current_block = None;
+        for log in logs
             .into_iter()
             .filter(|log| log.topics[0] == *MESSAGE_EVENT_SIGNATURE)
-            .map(|log| {
-                MESSAGE_EVENT.parse_log(RawLog {
-                    topics: log.topics,
-                    data: log.data.0,
-                })
-            })
-            .collect::<Vec<_>>()
-            .into_iter()
-            .collect::<Result<Vec<_>, _>>()?;
+        {
+            let block = log.block_number.expect("block number not found").as_u64();
+            if current_block != Some(block) {
+                if messages.len() as u64 >= queue_room {
+                    last_block = block - 1;
+                    break;
+                }
+                current_block = Some(block);
+            }
+
+            let log = MESSAGE_EVENT.parse_log(RawLog {
+                topics: log.topics,
+                data: log.data.0,
+            })?;
 
-        let mut messages = vec![];
-        for log in parsed_logs {
             if let Some(message) = Message::new(log, daemon.listen_chain_id, daemon.id) {
                 messages.push(message);
-                continue;
             }
         }
 
-        BalancesStorage::update_last_block(&daemon.creator, daemon.listen_chain_id, to_block);
+        BalancesStorage::update_last_block(&daemon.creator, daemon.listen_chain_id, last_block);
 
         Ok(messages)
     }
 
-    pub fn collect_listening_cycles(id: u64, principal: Principal) {
+    /// Processes the transactions of the CCMP program oldest first, at most
+    /// `SOLANA_TXS_PER_ROUND` of them per round. The first round only takes the
+    /// newest transaction as the starting point. A backlog longer than a page is
+    /// paged back to its oldest page, the page's upper bound is kept as a cursor
+    /// until the page is processed. The round stops after the transaction which
+    /// brings the messages read to `queue_room`.
+    pub async fn listen_solana_chain(
+        daemon: &Daemon,
+        queue_room: u64,
+    ) -> Result<Vec<Message>, DaemonsError> {
+        let solana_chain =
+            SolanaChainsStorage::get_chain(daemon.listen_chain_id).expect("Solana chain not found");
+        let balance = BalancesStorage::get_balance(&daemon.creator).expect("Balance not found");
+        let chain_data = balance
+            .chains_data
+            .get(&daemon.listen_chain_id)
+            .cloned()
+            .unwrap_or_default();
+
+        let Some(last_signature) = chain_data.last_signature else {
+            let signatures = solana_chain
+                .get_signatures_for_address(&daemon.ccmp_contract, None, None)
+                .await?;
+
+            if let Some(newest) = signatures.first() {
+                BalancesStorage::update_last_signature(
+                    &daemon.creator,
+                    daemon.listen_chain_id,
+                    newest.signature.clone(),
+                );
+            }
+            return Ok(vec![]);
+        };
+
+        let mut before = chain_data.signatures_cursor.clone();
+        // the oldest non-empty page read and its upper bound
+        let mut page = vec![];
+        let mut page_before = None;
+        let mut is_until_reached = false;
+        for _ in 0..SOLANA_SIGNATURE_PAGES_PER_ROUND {
+            let signatures = solana_chain
+                .get_signatures_for_address(
+                    &daemon.ccmp_contract,
+                    before.clone(),
+                    Some(last_signature.clone()),
+                )
+                .await?;
+
+            is_until_reached = signatures.len() < SOLANA_SIGNATURES_LIMIT;
+            let Some(oldest) = signatures
+                .last()
+                .map(|signature| signature.signature.clone())
+            else {
+                break;
+            };
+
+            page = signatures;
+            page_before = before;
+            before = Some(oldest);
+
+            if is_until_reached {
+                break;
+            }
+        }
+
+        // older transactions are left, the next round goes on paging back from the last page
+        if !is_until_reached {
+            BalancesStorage::update_signatures_cursor(
+                &daemon.creator,
+                daemon.listen_chain_id,
+                before,
+            );
+            return Ok(vec![]);
+        }
+
+        if page.is_empty() {
+            if chain_data.signatures_cursor.is_some() {
+                BalancesStorage::update_signatures_cursor(
+                    &daemon.creator,
+                    daemon.listen_chain_id,
+                    None,
+                );
+            }
+
+            log!(
+                "[DAEMONS] daemon listening finished, daemon id: {}, no messages",
+                daemon.id
+            );
+            return Ok(vec![]);
+        }
+
+        let mut messages = vec![];
+        let mut processed = 0;
+        for signature in page.iter().rev().take(SOLANA_TXS_PER_ROUND) {
+            if messages.len() as u64 >= queue_room {
+                break;
+            }
+
+            // failed transactions emit no events
+            if signature.err.is_none() {
+                // messages of the transactions read so far are kept, the cursor is behind them
+                let logs = match solana_chain
+                    .get_transaction_logs(&signature.signature)
+                    .await
+                {
+                    Ok(logs) => logs,
+                    Err(err) => {
+                        log!(
+                            "[DAEMONS] reading solana transaction failed, daemon id: {}, signature: {}, error: {}",
+                            daemon.id,
+                            signature.signature,
+                            err
+                        );
+                        break;
+                    }
+                };
+
+                messages.extend(
+                    parse_ccmp_events(&logs, &daemon.ccmp_contract)
+                        .into_iter()
+                        .filter_map(|event| {
+                            Message::from_solana_event(event, daemon.listen_chain_id, daemon.id)
+                        }),
+                );
+            }
+
+            BalancesStorage::update_last_signature(
+                &daemon.creator,
+                daemon.listen_chain_id,
+                signature.signature.clone(),
+            );
+            processed += 1;
+        }
+
+        // the newer transactions are read from the top once the page is done
+        let cursor = if processed == page.len() {
+            None
+        } else {
+            page_before
+        };
+        BalancesStorage::update_signatures_cursor(&daemon.creator, daemon.listen_chain_id, cursor);
+
+        Ok(messages)
+    }
+
+    /// Reads transactions paying the watched address once they have the chain's
+    /// confirmations, ordered by height and txid, at most `BITCOIN_TXS_PER_ROUND`
+    /// of them per round, or fewer once `queue_room` messages are read. Outputs
+    /// spent before they are read are missed.
+    pub async fn listen_bitcoin_chain(
+        daemon: &Daemon,
+        queue_room: u64,
+    ) -> Result<Vec<Message>, DaemonsError> {
+        let bitcoin_chain = BitcoinChainsStorage::get_chain(daemon.listen_chain_id)
+            .expect("Bitcoin chain not found");
+        let balance = BalancesStorage::get_balance(&daemon.creator).expect("Balance not found");
+        let chain_data = balance
+            .chains_data
+            .get(&daemon.listen_chain_id)
+            .cloned()
+            .unwrap_or_default();
+
+        let (utxos, calls) = bitcoin_chain.get_utxos(&daemon.ccmp_contract).await?;
+        BalancesStorage::reduce_cycles(
+            &daemon.creator,
+            Nat::from(bitcoin_chain.get_utxos_cycles() * calls),
+        );
+
+        let cursor = (chain_data.last_block, chain_data.last_txid);
+        let transactions = utxos
+            .into_iter()
+            .map(|utxo| (utxo.height as u64, utxo.outpoint.txid))
+            .filter(|(height, txid)| (*height, Some(Transaction::display_txid(txid))) > cursor)
+            .sorted()
+            .dedup()
+            .take(BITCOIN_TXS_PER_ROUND)
+            .collect::<Vec<_>>();
+
+        let mut messages = vec![];
+        for (height, txid) in transactions {
+            if messages.len() as u64 >= queue_room {
+                break;
+            }
+
+            let display_txid = Transaction::display_txid(&txid);
+            // messages of the transactions read so far are kept, the cursor is behind them
+            let transaction = match bitcoin_chain.get_transaction(&txid).await {
+                Ok(transaction) => transaction,
+                Err(err) => {
+                    log!(
+                        "[DAEMONS] reading bitcoin transaction failed, daemon id: {}, txid: {}, error: {}",
+                        daemon.id,
+                        display_txid,
+                        err
+                    );
+                    break;
+                }
+            };
+
+            for payload in transaction
+                .op_return_payloads()
+                .iter()
+                .filter_map(|payload| BitcoinCcmpPayload::parse(payload))
+            {
+                let Some(mut message) = Message::from_bitcoin_payload(payload, &txid, daemon)
+                else {
+                    continue;
+                };
+                message.index =
+                    BalancesStorage::next_message_index(&daemon.creator, daemon.listen_chain_id);
+
+                messages.push(message);
+            }
+
+            BalancesStorage::update_last_txid(
+                &daemon.creator,
+                daemon.listen_chain_id,
+                height,
+                display_txid,
+            );
+        }
+
+        Ok(messages)
+    }
+
+    pub fn collect_listening_cycles(id: u64, principal: Principal, outcalls: u64) {
         let mut used_cycles = (instruction_counter() / 10) * 4;
-        used_cycles += HTTP_OUTCALL_CYCLES_COST * DAEMON_HTTP_OUTCALLS_COUNT;
+        used_cycles += HTTP_OUTCALL_CYCLES_COST * outcalls;
         used_cycles += DAEMON_JOB_CYCLES_COST;
 
         BalancesStorage::reduce_cycles(&principal, Nat::from(used_cycles));
@@ -275,39 +641,54 @@ impl Daemon {
         }
     }
 
+    pub fn run(id: u64) {
+        log!("[DAEMONS] starting]");
+
+        ic_cdk::spawn(async move {
+            if let Err(err) = Self::listen(id).await {
+                log!("[DAEMONS] error: {}", err);
+            };
+        });
+    }
+
     pub fn start(id: u64) {
         STORAGE.with(|storage| {
-            let mut storage = storage.borrow_mut();
+            let storage = &mut *storage.borrow_mut();
             let daemon = storage.daemon_storage.daemons.get_mut(&id).unwrap();
 
             daemon.is_active = true;
 
-            let timer_id = set_timer(daemon.interval, move || {
-                log!("[DAEMONS] starting]");
-
-                ic_cdk::spawn(async move {
-                    if let Err(err) = Self::listen(id).await {
-                        log!("[DAEMONS] error: {}", err);
-                    };
-                });
-            });
-
-            let serialized_timer_id = serde_json::to_string(&timer_id).unwrap();
-
-            daemon.timer_id = serialized_timer_id;
+            Self::schedule(daemon, &mut storage.scheduler);
         });
     }
 
     pub fn stop(id: u64) {
         STORAGE.with(|storage| {
-            let mut storage = storage.borrow_mut();
+            let storage = &mut *storage.borrow_mut();
             let daemon = storage.daemon_storage.daemons.get_mut(&id).unwrap();
 
             daemon.is_active = false;
 
-            let timer_id: TimerId = serde_json::from_str(&daemon.timer_id).unwrap();
+            storage.scheduler.unschedule(Task::Daemon(id));
+        });
+    }
+
+    /// Schedules the next listening round unless the daemon has been stopped
+    /// while the current one was in progress.
+    fn schedule_next(id: u64) {
+        STORAGE.with(|storage| {
+            let storage = &mut *storage.borrow_mut();
+            let Some(daemon) = storage.daemon_storage.daemons.get(&id) else {
+                return;
+            };
 
-            clear_timer(timer_id);
+            if daemon.is_active {
+                Self::schedule(daemon, &mut storage.scheduler);
+            }
         });
     }
+
+    fn schedule(daemon: &Daemon, scheduler: &mut Scheduler) {
+        scheduler.schedule(Task::Daemon(daemon.id), daemon.interval);
+    }
 }
@@ -0,0 +1,248 @@
+use std::{collections::HashMap, time::Duration};
+
+use candid::{CandidType, Principal};
+use ic_cdk::api::time;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use super::{messages::Message, scheduler::Task};
+use crate::{log, STORAGE};
+
+pub const DEFAULT_MAX_RETRIES: u64 = 5;
+const RETRY_BACKOFF_BASE_SECS: u64 = 60;
+const RETRY_BACKOFF_MAX_SECS: u64 = 24 * 60 * 60;
+
+#[derive(Error, Debug)]
+pub enum DeadLettersError {
+    #[error("dead letter not found")]
+    DeadLetterNotFound,
+    #[error("message is already being retried")]
+    AlreadyRetrying,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+pub enum FailedStage {
+    Sign,
+    Write,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+pub enum DeadLetterStatus {
+    // waiting for an automatic retry at `next_retry_at`
+    Waiting,
+    // the message is back in the pipeline
+    Retrying,
+    // automatic retries are used up, only a manual retry or discard is possible
+    Exhausted,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone)]
+pub struct DeadLetter {
+    pub id: u64,
+    pub owner: Principal,
+    pub message: Message,
+    pub stage: FailedStage,
+    pub status: DeadLetterStatus,
+    pub error: String,
+    pub attempts: u64,
+    pub first_failed_at: u64,
+    pub last_failed_at: u64,
+    pub next_retry_at: Option<u64>,
+}
+
+impl DeadLetter {
+    fn backoff(attempts: u64) -> Duration {
+        let exponent = attempts.saturating_sub(1).min(32) as u32;
+        let secs = RETRY_BACKOFF_BASE_SECS
+            .saturating_mul(2u64.pow(exponent))
+            .min(RETRY_BACKOFF_MAX_SECS);
+
+        Duration::from_secs(secs)
+    }
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone, Default)]
+pub struct DeadLettersStorage {
+    pub dead_letters_count: u64,
+    pub dead_letters: HashMap<u64, DeadLetter>,
+}
+
+impl DeadLettersStorage {
+    /// Records a failed attempt to process the message, a message that is already
+    /// dead lettered keeps its record and gets its attempts counter increased.
+    pub fn add(owner: Principal, message: Message, stage: FailedStage, error: String) {
+        Self::record(owner, message, stage, error, true)
+    }
+
+    /// Records a message that can not succeed on a retry, e.g. one to a removed
+    /// chain. It is not retried automatically.
+    pub fn reject(owner: Principal, message: Message, stage: FailedStage, error: String) {
+        Self::record(owner, message, stage, error, false)
+    }
+
+    fn record(
+        owner: Principal,
+        message: Message,
+        stage: FailedStage,
+        error: String,
+        is_retryable: bool,
+    ) {
+        STORAGE.with(|storage| {
+            let storage = &mut *storage.borrow_mut();
+            let now = time();
+
+            let max_retries = storage
+                .daemon_storage
+                .daemons
+                .get(&message.daemon_id)
+                .map_or(0, |daemon| daemon.max_retries);
+
+            let dead_letters_storage = &mut storage.dead_letters_storage;
+
+            let existing_id = dead_letters_storage
+                .dead_letters
+                .values()
+                .find(|dead_letter| dead_letter.message.is_same(&message))
+                .map(|dead_letter| dead_letter.id);
+
+            let id = existing_id.unwrap_or_else(|| {
+                let id = dead_letters_storage.dead_letters_count;
+                dead_letters_storage.dead_letters_count += 1;
+                id
+            });
+
+            let dead_letter = dead_letters_storage
+                .dead_letters
+                .entry(id)
+                .or_insert_with(|| DeadLetter {
+                    id,
+                    owner,
+                    message: message.clone(),
+                    stage,
+                    status: DeadLetterStatus::Waiting,
+                    error: String::new(),
+                    attempts: 0,
+                    first_failed_at: now,
+                    last_failed_at: now,
+                    next_retry_at: None,
+                });
+
+            dead_letter.message = message;
+            dead_letter.stage = stage;
+            dead_letter.error = error;
+            dead_letter.attempts += 1;
+            dead_letter.last_failed_at = now;
+
+            if is_retryable && dead_letter.attempts <= max_retries {
+                let delay = DeadLetter::backoff(dead_letter.attempts);
+
+                dead_letter.status = DeadLetterStatus::Waiting;
+                dead_letter.next_retry_at = Some(now + delay.as_nanos() as u64);
+
+                storage.scheduler.schedule(Task::DeadLetter(id), delay);
+            } else {
+                dead_letter.status = DeadLetterStatus::Exhausted;
+                dead_letter.next_retry_at = None;
+            }
+
+            log!(
+                "[DEAD LETTERS] message dead lettered, id: {}, attempts: {}",
+                id,
+                dead_letter.attempts
+            );
+        })
+    }
+
+    pub fn get_dead_letter(id: u64) -> Option<DeadLetter> {
+        STORAGE.with(|storage| {
+            storage
+                .borrow()
+                .dead_letters_storage
+                .dead_letters
+                .get(&id)
+                .cloned()
+        })
+    }
+
+    /// Puts the message back into the queue of the stage it has failed on.
+    pub fn retry(id: u64) -> Result<(), DeadLettersError> {
+        STORAGE.with(|storage| {
+            let storage = &mut *storage.borrow_mut();
+
+            let dead_letter = storage
+                .dead_letters_storage
+                .dead_letters
+                .get_mut(&id)
+                .ok_or(DeadLettersError::DeadLetterNotFound)?;
+
+            if dead_letter.status == DeadLetterStatus::Retrying {
+                return Err(DeadLettersError::AlreadyRetrying);
+            }
+
+            dead_letter.status = DeadLetterStatus::Retrying;
+            dead_letter.next_retry_at = None;
+
+            storage.scheduler.unschedule(Task::DeadLetter(id));
+
+            let owner = dead_letter.owner;
+            let message = dead_letter.message.clone();
+            match dead_letter.stage {
+                FailedStage::Sign => {
+                    storage.listened_messages.insert_ordered(owner, message);
+                    storage.signer_job.start(&mut storage.scheduler);
+                }
+                FailedStage::Write => {
+                    storage.signed_messages.insert_ordered(owner, message);
+                    storage.writer_job.start(&mut storage.scheduler);
+                }
+            }
+
+            Ok(())
+        })
+    }
+
+    /// Drops the record of a dead lettered message. A message being retried is
+    /// already back in the queue, so it can not be discarded.
+    pub fn discard(id: u64) -> Result<DeadLetter, DeadLettersError> {
+        STORAGE.with(|storage| {
+            let storage = &mut *storage.borrow_mut();
+
+            let status = storage
+                .dead_letters_storage
+                .dead_letters
+                .get(&id)
+                .ok_or(DeadLettersError::DeadLetterNotFound)?
+                .status;
+
+            if status == DeadLetterStatus::Retrying {
+                return Err(DeadLettersError::AlreadyRetrying);
+            }
+
+            storage.scheduler.unschedule(Task::DeadLetter(id));
+
+            let dead_letter = storage
+                .dead_letters_storage
+                .dead_letters
+                .remove(&id)
+                .ok_or(DeadLettersError::DeadLetterNotFound)?;
+
+            // messages held behind the discarded one in an ordered lane may go now
+            if !storage.signed_messages.is_empty() {
+                storage.writer_job.start(&mut storage.scheduler);
+            }
+
+            Ok(dead_letter)
+        })
+    }
+
+    /// Drops the record of a dead lettered message once it has been delivered.
+    pub fn resolve(message: &Message) {
+        STORAGE.with(|storage| {
+            storage
+                .borrow_mut()
+                .dead_letters_storage
+                .dead_letters
+                .retain(|_, dead_letter| !dead_letter.message.is_same(message));
+        })
+    }
+}
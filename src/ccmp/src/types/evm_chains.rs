@@ -1,14 +1,14 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, str::FromStr};
 
 use async_trait::async_trait;
 use candid::{CandidType, Nat, Principal};
 use ethabi::{Error as EthabiError, Token};
+use ic_cdk::api::time;
 use ic_web3_rs::{
     contract::{Contract, Options},
     ic::pubkey_to_address,
     ic::KeyInfo,
-    transports::ICHttp,
-    types::{H160, U256},
+    types::{BlockId, BlockNumber, H160, H256, U256},
     Error as Web3Error, Web3,
 };
 use scopeguard::defer;
@@ -16,7 +16,7 @@ use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
 use super::{
-    chains::{Chain, ChainMetadata, ChainType},
+    chains::{Chain, ChainMetadata, ChainState, ChainType, ChainsStorage},
     ECDSA_SIGN_CYCLES, HTTP_OUTCALL_CYCLES_COST, MINIMUM_CYCLES,
 };
 use crate::{
@@ -24,18 +24,27 @@ use crate::{
     types::{
         balances::BalancesStorage,
         daemons::{Daemon, DaemonsStorage},
-        messages::Message,
-        pending_tx::{PendingTransaction, PendingTransactionsStorage},
+        messages::{Encoding, Message},
+        pending_tx::{PendingTransaction, PendingTransactionsStorage, ReceiverCall},
+    },
+    utils::{
+        eip712::{parse_address, Eip712Domain},
+        evm_transport::{EvmProvider, EvmProviderError, EvmTransport},
+        nat_to_u256,
+        transform_processors::{call_options, call_options_with_context, Transform},
+        u256_to_nat, UtilsError,
     },
-    utils::{transform_processors::call_options, u256_to_nat, UtilsError},
     STORAGE,
 };
 
 const DEFAULT_MAX_RESP: u64 = 500_000;
 const RECEIVER_ABI: &[u8] = include_bytes!("../assets/ReceiverABI.json");
 const CCMP_CONTRACT_RECEIVER_METHOD: &str = "receiveMessage";
+const CCMP_CONTRACT_ENCODED_RECEIVER_METHOD: &str = "receiveEncodedMessage";
+const CCMP_CONTRACT_RAW_RECEIVER_METHOD: &str = "receiveRawMessage";
+const CCMP_CONTRACT_BATCH_RECEIVER_METHOD: &str = "receiveMessageBatch";
 const EVM_ADDRESS_LENGTH: usize = 20;
-const EVM_WRITER_HTTP_OUTCALLS_COUNT: u64 = 4;
+pub const EVM_WRITER_HTTP_OUTCALLS_COUNT: u64 = 4;
 const WRITER_JOB_EXECTUTION_COST: u64 = 2_000_000;
 
 #[derive(Error, Debug)]
@@ -50,6 +59,18 @@ pub enum EvmChainError {
     Ethabi(#[from] EthabiError),
     #[error("evm chain not found")]
     EvmChainNotFound,
+    #[error("batched message without a proof")]
+    MissingBatchProof,
+    #[error("evm chain with chain id {0} is already registered")]
+    DuplicateChainId(u64),
+    #[error("rpc is on chain {actual}, expected {expected}")]
+    ChainIdMismatch { expected: u64, actual: u64 },
+    #[error("invalid genesis hash: {0}")]
+    InvalidGenesisHash(String),
+    #[error("rpc genesis hash is {actual}, expected {expected}")]
+    GenesisHashMismatch { expected: String, actual: String },
+    #[error("invalid provider: {0}")]
+    InvalidProvider(#[from] EvmProviderError),
 }
 
 #[derive(CandidType, Deserialize, Serialize, Debug, Default, Clone)]
@@ -57,15 +78,90 @@ pub struct EvmChain {
     pub name: String,
     pub id: u64,
     pub rpc: String,
+    pub eip712_domain: Option<Eip712Domain>,
+    pub provider: EvmProvider,
+    // hash of the first block, every rpc of the chain has to report it when set
+    pub genesis_hash: Option<String>,
 }
 
 impl EvmChain {
-    pub async fn new(name: String, rpc: String) -> Result<Self, EvmChainError> {
-        let w3 = Web3::new(ICHttp::new(&rpc, Some(DEFAULT_MAX_RESP)).unwrap());
+    pub async fn new(
+        name: String,
+        rpc: String,
+        genesis_hash: Option<String>,
+    ) -> Result<Self, EvmChainError> {
+        let genesis_hash = genesis_hash
+            .map(|hash| H256::from_str(&hash).map(|hash| format!("{hash:?}")))
+            .transpose()
+            .map_err(|e| EvmChainError::InvalidGenesisHash(e.to_string()))?;
+
+        let provider = EvmProvider::Http;
+        let transport = EvmTransport::new(&rpc, &provider, Some(DEFAULT_MAX_RESP), None);
+        let id = Self::get_chain_id(transport.clone()).await?;
+
+        let evm_chain = Self {
+            name,
+            id,
+            rpc,
+            eip712_domain: None,
+            provider,
+            genesis_hash,
+        };
+        evm_chain.check_genesis_hash(transport).await?;
+
+        Ok(evm_chain)
+    }
+
+    /// Checks the transport reaches this chain, used before switching the rpc
+    /// or the provider of the chain.
+    pub async fn verify(&self, transport: EvmTransport) -> Result<(), EvmChainError> {
+        let chain_id = Self::get_chain_id(transport.clone()).await?;
+        if chain_id != self.id {
+            return Err(EvmChainError::ChainIdMismatch {
+                expected: self.id,
+                actual: chain_id,
+            });
+        }
+
+        self.check_genesis_hash(transport).await
+    }
+
+    async fn check_genesis_hash(&self, transport: EvmTransport) -> Result<(), EvmChainError> {
+        let Some(expected) = &self.genesis_hash else {
+            return Ok(());
+        };
+
+        let actual = Web3::new(transport)
+            .eth()
+            .block(
+                BlockId::Number(BlockNumber::Number(0.into())),
+                call_options(Transform::Default),
+            )
+            .await?
+            .and_then(|block| block.hash)
+            .map(|hash| format!("{hash:?}"))
+            .unwrap_or_default();
+
+        if &actual != expected {
+            return Err(EvmChainError::GenesisHashMismatch {
+                expected: expected.clone(),
+                actual,
+            });
+        }
 
-        let chain_id = w3
+        Ok(())
+    }
+
+    /// The transport over the provider of the chain, charging `payer` for the
+    /// calls to the EVM RPC canister.
+    pub fn transport(&self, max_resp: Option<u64>, payer: Option<Principal>) -> EvmTransport {
+        EvmTransport::new(&self.rpc, &self.provider, max_resp, payer)
+    }
+
+    pub async fn get_chain_id(transport: EvmTransport) -> Result<u64, EvmChainError> {
+        let chain_id = Web3::new(transport)
             .eth()
-            .chain_id(call_options("transform".to_string()))
+            .chain_id(call_options(Transform::Default))
             .await?;
 
         if chain_id > U256::from(u64::MAX) {
@@ -74,16 +170,13 @@ impl EvmChain {
             ));
         }
 
-        Ok(Self {
-            name,
-            id: chain_id.as_u64(),
-            rpc,
-        })
+        Ok(chain_id.as_u64())
     }
 
-    pub fn collect_writing_cycles(id: u64, principal: Principal) {
+    pub fn collect_writing_cycles(id: u64, principal: Principal, outcalls_per_request: u64) {
         let mut used_cycles = 0;
-        used_cycles += HTTP_OUTCALL_CYCLES_COST * EVM_WRITER_HTTP_OUTCALLS_COUNT;
+        used_cycles +=
+            HTTP_OUTCALL_CYCLES_COST * EVM_WRITER_HTTP_OUTCALLS_COUNT * outcalls_per_request;
         used_cycles += WRITER_JOB_EXECTUTION_COST;
         used_cycles += ECDSA_SIGN_CYCLES;
 
@@ -102,84 +195,279 @@ impl Chain for EvmChain {
     type Error = EvmChainError;
 
     async fn write(&self, message: Message) -> Result<(), Self::Error> {
-        let daemon = DaemonsStorage::get_daemon(message.daemon_id).expect("daemon not found");
-        defer! {
-            Self::collect_writing_cycles(daemon.id, daemon.creator);
-        };
-        let balance = BalancesStorage::get_balance(&daemon.creator).expect("balance not found");
-        let from = pubkey_to_address(&hex::decode(balance.public_key).unwrap())
-            .expect("unable to get eth address from public key");
-        let address = format!("0x{}", hex::encode(from.0));
-
-        let w3 = Web3::new(ICHttp::new(&self.rpc, Some(DEFAULT_MAX_RESP)).unwrap());
-
         if message.receiver.len() != EVM_ADDRESS_LENGTH {
             return Ok(());
         }
 
         let receiver = H160::from_slice(&message.receiver);
 
-        let ccmp_contract = Contract::from_json(w3.eth(), receiver, RECEIVER_ABI)?;
+        let chain_metadata = ChainsStorage::get_chain_metadata(message.to_chain_id)
+            .ok_or(EvmChainError::EvmChainNotFound)?;
+        let signature = Token::Bytes(message.signature.clone().unwrap_or_default());
+
+        // the receiver gets the message in the form its signature is verified against
+        let (method, params) = match chain_metadata.encoding {
+            Encoding::Plain => (
+                CCMP_CONTRACT_RAW_RECEIVER_METHOD,
+                vec![Token::Bytes(message.message.clone()), signature],
+            ),
+            Encoding::AbiEncode => (
+                CCMP_CONTRACT_ENCODED_RECEIVER_METHOD,
+                vec![
+                    Token::Bytes(ethabi::encode(&Self::message_tokens(&message, receiver))),
+                    signature,
+                ],
+            ),
+            Encoding::AbiEncodePacked | Encoding::Eip712 => {
+                let mut params = Self::message_tokens(&message, receiver);
+                params.push(signature);
+
+                (CCMP_CONTRACT_RECEIVER_METHOD, params)
+            }
+        };
 
-        let mut gas_price = w3
-            .eth()
-            .gas_price(call_options("transform".to_string()))
-            .await?;
+        self.call_receiver(message, receiver, method, params).await
+    }
 
-        gas_price = (gas_price / 10) * 12;
+    async fn write_batch(&self, messages: Vec<Message>) -> Result<(), Self::Error> {
+        let Some(first) = messages.first().cloned() else {
+            return Ok(());
+        };
 
-        let mut options = Options::with(|op| {
-            op.gas_price = Some(gas_price);
-        });
+        if first.receiver.len() != EVM_ADDRESS_LENGTH {
+            return Ok(());
+        }
 
-        let key_info = KeyInfo {
-            derivation_path: vec![daemon.creator.as_slice().to_vec()],
-            key_name: storage_get!(key),
-            ecdsa_sign_cycles: Some(ECDSA_SIGN_CYCLES),
-        };
+        let receiver = H160::from_slice(&first.receiver);
+
+        let mut message_tokens = vec![];
+        let mut proof_tokens = vec![];
+        for message in messages.iter() {
+            let batch = message
+                .batch
+                .as_ref()
+                .ok_or(EvmChainError::MissingBatchProof)?;
+
+            message_tokens.push(Token::Tuple(Self::message_tokens(message, receiver)));
+            proof_tokens.push(Token::Array(
+                batch
+                    .proof
+                    .iter()
+                    .map(|hash| Token::FixedBytes(hash.clone()))
+                    .collect(),
+            ));
+        }
+
+        let root = first
+            .batch
+            .clone()
+            .ok_or(EvmChainError::MissingBatchProof)?
+            .root;
 
         let params = vec![
+            Token::Array(message_tokens),
+            Token::Array(proof_tokens),
+            Token::FixedBytes(root),
+            Token::Bytes(first.signature.clone().unwrap_or_default()),
+        ];
+
+        self.call_receiver(first, receiver, CCMP_CONTRACT_BATCH_RECEIVER_METHOD, params)
+            .await
+    }
+}
+
+impl EvmChain {
+    fn message_tokens(message: &Message, receiver: H160) -> Vec<Token> {
+        vec![
             Token::Uint(U256::from(message.index)),
             Token::Uint(U256::from(message.from_chain_id)),
             Token::Uint(U256::from(message.to_chain_id)),
             Token::Bytes(message.sender.clone()),
             Token::Bytes(message.message.clone()),
             Token::Address(receiver),
-            Token::Bytes(message.signature.clone().unwrap_or_default()),
-        ];
+        ]
+    }
 
-        let tx_hash = BalancesStorage::with_tx(&daemon.creator, message.to_chain_id, |tx_count| {
-            options.nonce = Some(tx_count.into());
-            ccmp_contract.signed_call(
-                CCMP_CONTRACT_RECEIVER_METHOD,
-                &params,
-                options,
-                address,
-                key_info,
-                self.id,
-            )
-        })
-        .await?;
+    /// Sends a transaction calling the receiver contract on behalf of the daemon's
+    /// creator, `message` is the one the pending transaction is tracked for.
+    async fn call_receiver(
+        &self,
+        message: Message,
+        receiver: H160,
+        method: &str,
+        params: Vec<Token>,
+    ) -> Result<(), EvmChainError> {
+        let daemon = DaemonsStorage::get_daemon(message.daemon_id).expect("daemon not found");
+        let outcalls_per_request = self.provider.outcalls_per_request();
+        defer! {
+            Self::collect_writing_cycles(daemon.id, daemon.creator, outcalls_per_request);
+        };
+
+        let w3 = Web3::new(self.transport(Some(DEFAULT_MAX_RESP), Some(daemon.creator)));
+
+        let mut gas_price = w3.eth().gas_price(call_options(Transform::Default)).await?;
+
+        gas_price = (gas_price / 10) * 12;
+
+        let call = ReceiverCall {
+            receiver: receiver.0.to_vec(),
+            method: method.to_string(),
+            params: ethabi::encode(&params),
+        };
 
-        let formatted_tx_hash = hex::encode(tx_hash.0);
+        // the nonce is given back only if nothing was signed, a signed transaction
+        // may reach the chain whatever its broadcast returns
+        let (nonce, tx_hash, raw_tx) =
+            BalancesStorage::with_tx(&daemon.creator, message.to_chain_id, |tx_count| {
+                let (w3, call, creator) = (&w3, &call, daemon.creator);
+                async move {
+                    let (tx_hash, raw_tx) = self
+                        .sign_call(w3, creator, call, tx_count, gas_price)
+                        .await?;
+
+                    Ok::<_, EvmChainError>((tx_count, tx_hash, raw_tx))
+                }
+            })
+            .await?;
 
-        log!(
-            "[WRITER] message sent to evm chain, id: {}, tx hash: 0x{}",
-            message.to_chain_id,
-            formatted_tx_hash
-        );
+        let to_chain_id = message.to_chain_id;
 
-        PendingTransactionsStorage::add(PendingTransaction::new(
-            formatted_tx_hash,
-            message,
-            u256_to_nat(gas_price),
-        ));
+        PendingTransactionsStorage::add(PendingTransaction {
+            raw_tx: Some(raw_tx.clone()),
+            nonce: Some(nonce),
+            call: Some(call),
+            sent_at: time(),
+            ..PendingTransaction::new(hex::encode(tx_hash.0), message, u256_to_nat(gas_price))
+        });
         STORAGE.with(|storage| {
-            let mut storage = storage.borrow_mut();
+            let storage = &mut *storage.borrow_mut();
 
-            storage.checker_job.start();
+            storage.checker_job.start(&mut storage.scheduler);
         });
 
+        match self
+            .send_raw_transaction(raw_tx, tx_hash, daemon.creator)
+            .await
+        {
+            Ok(()) => log!(
+                "[WRITER] message sent to evm chain, id: {}, tx hash: 0x{}",
+                to_chain_id,
+                hex::encode(tx_hash.0)
+            ),
+            Err(err) => log!(
+                "[WRITER] message may not be sent to evm chain, id: {}, tx hash: 0x{}, error: {}",
+                to_chain_id,
+                hex::encode(tx_hash.0),
+                err
+            ),
+        }
+
+        Ok(())
+    }
+
+    /// Signs the receiver call with the key of `creator`, returning the hash and
+    /// the raw signed transaction.
+    async fn sign_call(
+        &self,
+        w3: &Web3<EvmTransport>,
+        creator: Principal,
+        call: &ReceiverCall,
+        nonce: u64,
+        gas_price: U256,
+    ) -> Result<(H256, Vec<u8>), EvmChainError> {
+        let ccmp_contract =
+            Contract::from_json(w3.eth(), H160::from_slice(&call.receiver), RECEIVER_ABI)?;
+        let params = ethabi::Contract::load(RECEIVER_ABI)?
+            .function(&call.method)?
+            .decode_input(&call.params)?;
+
+        let options = Options::with(|op| {
+            op.gas_price = Some(gas_price);
+            op.nonce = Some(nonce.into());
+        });
+
+        let key_info = KeyInfo {
+            derivation_path: vec![creator.as_slice().to_vec()],
+            key_name: storage_get!(key),
+            ecdsa_sign_cycles: Some(ECDSA_SIGN_CYCLES),
+        };
+        let address = format!("0x{}", hex::encode(Self::sender_address(&creator).0));
+
+        let signed_tx = ccmp_contract
+            .sign(&call.method, &params, options, address, key_info, self.id)
+            .await?;
+
+        Ok((signed_tx.transaction_hash, signed_tx.raw_transaction.0))
+    }
+
+    /// Signs the call of a pending transaction again with its nonce and a higher
+    /// gas price, and broadcasts it in place of the pending one, so a transaction
+    /// stuck in the mempool does not hold back the nonces after it.
+    pub async fn replace_transaction(
+        &self,
+        pending_tx: &mut PendingTransaction,
+        creator: Principal,
+    ) -> Result<(), EvmChainError> {
+        let (Some(nonce), Some(call)) = (pending_tx.nonce, pending_tx.call.clone()) else {
+            return Ok(());
+        };
+
+        let w3 = Web3::new(self.transport(Some(DEFAULT_MAX_RESP), Some(creator)));
+
+        // nodes accept a replacement only if it pays noticeably more than the
+        // transaction it replaces
+        let network_gas_price = w3.eth().gas_price(call_options(Transform::Default)).await?;
+        let gas_price =
+            ((network_gas_price / 10) * 12).max((nat_to_u256(&pending_tx.gas_price) / 4) * 5);
+
+        let (tx_hash, raw_tx) = self
+            .sign_call(&w3, creator, &call, nonce, gas_price)
+            .await?;
+
+        let replaced_tx_hash = std::mem::replace(&mut pending_tx.tx_hash, hex::encode(tx_hash.0));
+        pending_tx.replaced_tx_hashes.push(replaced_tx_hash);
+        pending_tx.raw_tx = Some(raw_tx.clone());
+        pending_tx.gas_price = u256_to_nat(gas_price);
+        pending_tx.sent_at = time();
+        pending_tx.broadcasts = 0;
+
+        if let Err(err) = self.send_raw_transaction(raw_tx, tx_hash, creator).await {
+            log!(
+                "[CHECKER] replacement may not be sent, nonce: {}, tx hash: 0x{}, error: {}",
+                nonce,
+                pending_tx.tx_hash,
+                err
+            );
+        }
+
+        Ok(())
+    }
+
+    /// The address the transactions of `principal` are sent from.
+    pub fn sender_address(principal: &Principal) -> H160 {
+        let balance = BalancesStorage::get_balance(principal).expect("balance not found");
+
+        pubkey_to_address(&hex::decode(balance.public_key).unwrap())
+            .expect("unable to get eth address from public key")
+    }
+
+    /// Broadcasts a signed transaction, a node which already has it reports it
+    /// as sent, so the call can be repeated until the transaction is mined.
+    pub async fn send_raw_transaction(
+        &self,
+        raw_tx: Vec<u8>,
+        tx_hash: H256,
+        payer: Principal,
+    ) -> Result<(), EvmChainError> {
+        let w3 = Web3::new(self.transport(Some(DEFAULT_MAX_RESP), Some(payer)));
+
+        w3.eth()
+            .send_raw_transaction(
+                raw_tx.into(),
+                call_options_with_context(Transform::SendRawTransaction, tx_hash.0.to_vec()),
+            )
+            .await?;
+
         Ok(())
     }
 }
@@ -188,14 +476,28 @@ impl Chain for EvmChain {
 pub struct EvmChainsStorage(pub HashMap<u64, EvmChain>);
 
 impl EvmChainsStorage {
-    pub fn add(evm_chain: EvmChain) -> u64 {
+    pub fn add(evm_chain: EvmChain) -> Result<u64, EvmChainError> {
         STORAGE.with(|storage| {
             let mut storage = storage.borrow_mut();
 
+            // a removed chain keeps its entry, the chain may be added again
+            let chains = &storage.chains_storage;
+            let is_duplicate = chains.evm_chains_storage.0.iter().any(|(index, chain)| {
+                chain.id == evm_chain.id && chains.state_of(*index) != Some(ChainState::Removed)
+            });
+            if is_duplicate {
+                return Err(EvmChainError::DuplicateChainId(evm_chain.id));
+            }
+
             let index = storage.chains_storage.chains_count;
             storage.chains_storage.chains_metadata.insert(
                 index,
-                ChainMetadata::new(evm_chain.name.clone(), ChainType::Evm),
+                ChainMetadata::new(
+                    index,
+                    evm_chain.name.clone(),
+                    ChainType::Evm,
+                    Some(evm_chain.id.to_string()),
+                ),
             );
             storage
                 .chains_storage
@@ -204,7 +506,7 @@ impl EvmChainsStorage {
                 .insert(index, evm_chain);
             storage.chains_storage.chains_count += 1;
 
-            index
+            Ok(index)
         })
     }
 
@@ -236,4 +538,52 @@ impl EvmChainsStorage {
             Ok(())
         })
     }
+
+    pub fn set_provider(id: u64, provider: EvmProvider) -> Result<(), EvmChainError> {
+        STORAGE.with(|storage| {
+            let mut storage = storage.borrow_mut();
+
+            let chain = storage
+                .chains_storage
+                .evm_chains_storage
+                .0
+                .get_mut(&id)
+                .ok_or_else(|| EvmChainError::EvmChainNotFound)?;
+            chain.provider = provider;
+
+            Ok(())
+        })
+    }
+
+    /// Sets the domain messages to the chain are signed under in the EIP-712 mode,
+    /// the domain is bound to the native id of the chain.
+    pub fn set_eip712_domain(
+        id: u64,
+        name: String,
+        version: String,
+        verifying_contract: String,
+    ) -> Result<Eip712Domain, EvmChainError> {
+        parse_address(&verifying_contract)?;
+
+        STORAGE.with(|storage| {
+            let mut storage = storage.borrow_mut();
+
+            let chain = storage
+                .chains_storage
+                .evm_chains_storage
+                .0
+                .get_mut(&id)
+                .ok_or_else(|| EvmChainError::EvmChainNotFound)?;
+
+            let domain = Eip712Domain {
+                name,
+                version,
+                chain_id: chain.id,
+                verifying_contract,
+            };
+            chain.eip712_domain = Some(domain.clone());
+
+            Ok(domain)
+        })
+    }
 }
@@ -0,0 +1,222 @@
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use candid::{CandidType, Nat, Principal};
+use ic_cdk::api::call::{call, RejectionCode};
+use scopeguard::defer;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use super::{
+    balances::BalancesStorage,
+    chains::{Chain, ChainMetadata, ChainType},
+    daemons::{Daemon, DaemonsStorage},
+    messages::Message,
+    MINIMUM_CYCLES,
+};
+use crate::{log, STORAGE};
+
+const CCMP_RECEIVE_METHOD: &str = "ccmp_receive";
+const WRITER_JOB_EXECTUTION_COST: u64 = 2_000_000;
+const INTER_CANISTER_CALL_CYCLES_COST: u64 = 260_000;
+const INTER_CANISTER_CALL_BYTE_CYCLES_COST: u64 = 1_000;
+
+#[derive(Error, Debug)]
+pub enum IcpChainError {
+    #[error("receiver is not a principal: {0}")]
+    InvalidReceiver(String),
+    #[error("receiver rejected the message, code: {code:?}, message: {message}")]
+    Rejected {
+        code: RejectionCode,
+        message: String,
+    },
+    #[error("batched delivery is not supported by icp chains")]
+    BatchNotSupported,
+}
+
+/// Canisters receive messages through an inter-canister call of their
+/// `ccmp_receive : (IcpDelivery) -> ()` method. The caller is this canister, so
+/// messages are not signed and the delivery is done once the call returns.
+pub struct IcpChain;
+
+/// The message as passed to `ccmp_receive`, without its attestation.
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone)]
+pub struct IcpDelivery {
+    pub index: u64,
+    pub from_chain_id: u64,
+    pub to_chain_id: u64,
+    pub sender: Vec<u8>,
+    pub message: Vec<u8>,
+}
+
+impl From<Message> for IcpDelivery {
+    fn from(message: Message) -> Self {
+        Self {
+            index: message.index,
+            from_chain_id: message.from_chain_id,
+            to_chain_id: message.to_chain_id,
+            sender: message.sender,
+            message: message.message,
+        }
+    }
+}
+
+impl IcpChain {
+    pub fn add(name: String) -> u64 {
+        STORAGE.with(|storage| {
+            let mut storage = storage.borrow_mut();
+
+            let index = storage.chains_storage.chains_count;
+            storage
+                .chains_storage
+                .chains_metadata
+                .insert(index, ChainMetadata::new(index, name, ChainType::Icp, None));
+            storage.chains_storage.chains_count += 1;
+
+            index
+        })
+    }
+
+    /// The chain messages sent by canisters come from, the first ICP chain added.
+    pub fn chain_id() -> Option<u64> {
+        STORAGE.with(|storage| {
+            storage
+                .borrow()
+                .chains_storage
+                .chains_metadata
+                .iter()
+                .filter(|(_, chain_metadata)| chain_metadata.chain_type == ChainType::Icp)
+                .map(|(id, _)| *id)
+                .min()
+        })
+    }
+
+    pub fn collect_writing_cycles(id: u64, principal: Principal, message_size: usize) {
+        let mut used_cycles = 0;
+        used_cycles += INTER_CANISTER_CALL_CYCLES_COST;
+        used_cycles += INTER_CANISTER_CALL_BYTE_CYCLES_COST * message_size as u64;
+        used_cycles += WRITER_JOB_EXECTUTION_COST;
+
+        BalancesStorage::reduce_cycles(&principal, Nat::from(used_cycles));
+
+        let balance = BalancesStorage::get_balance(&principal).expect("Balance not found");
+        if balance.cycles < MINIMUM_CYCLES {
+            log!("[DAEMONS] insufficient cycles, principal: {}", principal);
+            Daemon::stop(id);
+        }
+    }
+}
+
+#[async_trait]
+impl Chain for IcpChain {
+    type Error = IcpChainError;
+
+    async fn write(&self, message: Message) -> Result<(), Self::Error> {
+        let receiver = Principal::try_from_slice(&message.receiver)
+            .map_err(|_| IcpChainError::InvalidReceiver(hex::encode(&message.receiver)))?;
+
+        let daemon = DaemonsStorage::get_daemon(message.daemon_id).expect("daemon not found");
+        let message_size = message.message.len() + message.sender.len();
+        defer! {
+            Self::collect_writing_cycles(daemon.id, daemon.creator, message_size);
+        };
+
+        let delivery = IcpDelivery::from(message);
+        let to_chain_id = delivery.to_chain_id;
+
+        call::<_, ()>(receiver, CCMP_RECEIVE_METHOD, (delivery,))
+            .await
+            .map_err(|(code, message)| IcpChainError::Rejected { code, message })?;
+
+        log!(
+            "[WRITER] message delivered to canister: {}, chain id: {}",
+            receiver,
+            to_chain_id
+        );
+
+        Ok(())
+    }
+
+    async fn write_batch(&self, _messages: Vec<Message>) -> Result<(), Self::Error> {
+        Err(IcpChainError::BatchNotSupported)
+    }
+}
+
+/// A canister sending messages with `send_message`. Its messages belong to a
+/// daemon that never listens, so they go through the pipeline like any other.
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone)]
+pub struct IcpSender {
+    pub daemon_id: u64,
+    pub next_index: u64,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone, Default)]
+pub struct IcpSendersStorage(pub HashMap<Principal, IcpSender>);
+
+impl IcpSendersStorage {
+    /// Returns the daemon of the sender, registering it on the first message.
+    pub fn get_or_add(principal: Principal, icp_chain_id: u64) -> u64 {
+        let sender = STORAGE.with(|storage| {
+            storage
+                .borrow()
+                .icp_senders_storage
+                .0
+                .get(&principal)
+                .cloned()
+        });
+        if let Some(sender) = sender {
+            return sender.daemon_id;
+        }
+
+        let daemon_id = DaemonsStorage::add_daemon(Daemon {
+            creator: principal,
+            listen_chain_id: icp_chain_id,
+            ccmp_contract: principal.to_text(),
+            ..Default::default()
+        });
+
+        STORAGE.with(|storage| {
+            storage.borrow_mut().icp_senders_storage.0.insert(
+                principal,
+                IcpSender {
+                    daemon_id,
+                    next_index: 0,
+                },
+            )
+        });
+
+        log!(
+            "[DAEMONS] registered sender daemon, id: {}, principal: {}",
+            daemon_id,
+            principal
+        );
+
+        daemon_id
+    }
+
+    pub fn next_index(principal: &Principal) -> u64 {
+        STORAGE.with(|storage| {
+            let mut storage = storage.borrow_mut();
+            let sender = storage
+                .icp_senders_storage
+                .0
+                .get_mut(principal)
+                .expect("sender not found");
+
+            sender.next_index += 1;
+
+            sender.next_index - 1
+        })
+    }
+
+    pub fn is_sender_daemon(daemon_id: u64) -> bool {
+        STORAGE.with(|storage| {
+            storage
+                .borrow()
+                .icp_senders_storage
+                .0
+                .values()
+                .any(|sender| sender.daemon_id == daemon_id)
+        })
+    }
+}
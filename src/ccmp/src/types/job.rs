@@ -1,13 +1,17 @@
 use std::time::Duration;
 
-use ic_cdk_timers::{clear_timer, set_timer, TimerId};
-
 use candid::CandidType;
 use serde::{Deserialize, Serialize};
 
-use crate::jobs::{checker, signer, writer};
+use super::scheduler::{Scheduler, Task};
+use crate::log;
 
-#[derive(CandidType, Deserialize, Serialize, Debug, Default, Clone)]
+// a run above either target halves the batch of the next run, the messages of
+// a run have to stay well below the instructions limit
+const TARGET_RUN_INSTRUCTIONS: u64 = 1_000_000_000;
+const TARGET_RUN_LATENCY_NANOS: u64 = 30_000_000_000;
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
 pub enum JobType {
     Signer,
     Writer,
@@ -19,62 +23,101 @@ pub enum JobType {
 #[derive(CandidType, Deserialize, Serialize, Debug, Default, Clone)]
 pub struct Job {
     pub interval_secs: u64,
-    timer_id: String,
+    pub max_batch_size: u64,
+    // adapted to the runs, between 1 and `max_batch_size`
+    batch_size: u64,
     is_active: bool,
     job_type: JobType,
 }
 
 impl Job {
-    pub fn new(interval_secs: u64, job_type: JobType) -> Self {
+    pub fn new(interval_secs: u64, job_type: JobType, max_batch_size: u64) -> Self {
         Self {
             interval_secs,
-            timer_id: String::new(),
+            max_batch_size,
+            batch_size: max_batch_size,
             is_active: false,
             job_type,
         }
     }
 
-    pub fn start(&mut self) {
+    /// The number of items the next run takes.
+    pub fn batch_size(&self) -> u64 {
+        self.batch_size.clamp(1, self.max_batch_size.max(1))
+    }
+
+    pub fn set_max_batch_size(&mut self, max_batch_size: u64) {
+        self.max_batch_size = max_batch_size;
+        self.batch_size = self.batch_size.min(max_batch_size);
+    }
+
+    /// Adapts the batch size to a run of `items`: halved when the heaviest
+    /// message of the run used more instructions or the run took longer than
+    /// the targets, grown by one after a full batch within them.
+    pub fn record_run(&mut self, items: u64, instructions: u64, latency_nanos: u64) {
+        let batch_size = self.batch_size();
+
+        self.batch_size =
+            if instructions > TARGET_RUN_INSTRUCTIONS || latency_nanos > TARGET_RUN_LATENCY_NANOS {
+                (batch_size / 2).max(1)
+            } else if items >= batch_size {
+                (batch_size + 1).min(self.max_batch_size.max(1))
+            } else {
+                batch_size
+            };
+
+        if self.batch_size != batch_size {
+            log!(
+                "[JOBS] {:?} batch size adapted: {} -> {}, instructions: {}, latency: {}ns",
+                self.job_type,
+                batch_size,
+                self.batch_size,
+                instructions,
+                latency_nanos
+            );
+        }
+    }
+
+    pub fn start(&mut self, scheduler: &mut Scheduler) {
         if self.is_active {
             return;
         }
 
-        self.run();
+        self.run(scheduler);
 
         self.is_active = true;
     }
 
-    pub fn run(&mut self) {
-        let func = match self.job_type {
-            JobType::Signer => signer::run,
-            JobType::Writer => writer::run,
-            JobType::Checker => checker::run,
-            _ => panic!("Unknown job type"),
-        };
-
-        let timer_id = set_timer(Duration::from_secs(self.interval_secs), func);
-        let serialized_timer_id = serde_json::to_string(&timer_id).unwrap();
+    pub fn run(&mut self, scheduler: &mut Scheduler) {
+        scheduler.schedule(
+            Task::Job(self.job_type),
+            Duration::from_secs(self.interval_secs),
+        );
+    }
 
-        self.timer_id = serialized_timer_id;
+    /// Puts an active job back into the queue if it got lost there, e.g. after
+    /// an upgrade from a state that was still using per job timers.
+    pub fn resume(&mut self, scheduler: &mut Scheduler) {
+        if self.is_active && !scheduler.is_scheduled(Task::Job(self.job_type)) {
+            self.run(scheduler);
+        }
     }
 
-    pub fn stop(&mut self) {
+    pub fn stop(&mut self, scheduler: &mut Scheduler) {
         if !self.is_active {
             return;
         }
 
-        let timer_id: TimerId = serde_json::from_str(&self.timer_id).unwrap();
-
-        clear_timer(timer_id);
+        scheduler.unschedule(Task::Job(self.job_type));
 
         self.is_active = false;
     }
 
-    pub fn update_interval_secs(&mut self, interval_secs: u64) {
-        self.stop();
+    pub fn update_interval_secs(&mut self, interval_secs: u64, scheduler: &mut Scheduler) {
+        self.stop(scheduler);
 
         self.interval_secs = interval_secs;
 
-        self.start();
+        self.start(scheduler);
     }
 }
@@ -0,0 +1,452 @@
+use std::collections::HashMap;
+
+use candid::CandidType;
+use ic_cdk::api::time;
+use ic_web3_rs::ic::pubkey_to_address;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use super::{dead_letters::FailedStage, Storage};
+use crate::{
+    log,
+    utils::signing::{self, SigningAlgorithm, SigningError},
+    STORAGE,
+};
+
+const DAEMON_DERIVATION_TAG: &[u8] = b"daemon";
+const CHAIN_DERIVATION_TAG: &[u8] = b"chain";
+
+#[derive(Error, Debug)]
+pub enum KeysError {
+    #[error("signing error: {0}")]
+    Signing(#[from] SigningError),
+    #[error("invalid public key: {0}")]
+    InvalidPublicKey(String),
+    #[error("key rotation is already in progress")]
+    RotationInProgress,
+    #[error("no key rotation in progress")]
+    NoRotationInProgress,
+    #[error("the key is already the attestation key")]
+    SameKey,
+    #[error("chain not found")]
+    ChainNotFound,
+    #[error("chains are not switched to the next key: {0:?}")]
+    ChainsNotSwitched(Vec<u64>),
+}
+
+/// Which attestation key signs the messages of a daemon, a derived key isolates
+/// the daemon or the destination chain from the others.
+#[derive(CandidType, Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq)]
+pub enum KeyDerivation {
+    // the canister key, shared by every daemon and chain
+    #[default]
+    Shared,
+    PerDaemon,
+    PerChain,
+}
+
+impl KeyDerivation {
+    /// Tags keep paths of a daemon and a chain with the same id apart.
+    pub fn derivation_path(&self, daemon_id: u64, to_chain_id: u64) -> Vec<Vec<u8>> {
+        match self {
+            KeyDerivation::Shared => vec![],
+            KeyDerivation::PerDaemon => vec![
+                DAEMON_DERIVATION_TAG.to_vec(),
+                daemon_id.to_be_bytes().to_vec(),
+            ],
+            KeyDerivation::PerChain => vec![
+                CHAIN_DERIVATION_TAG.to_vec(),
+                to_chain_id.to_be_bytes().to_vec(),
+            ],
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DerivedKey {
+    pub key_name: String,
+    pub derivation_path: Vec<Vec<u8>>,
+    pub algorithm: SigningAlgorithm,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+pub enum RotationStatus {
+    // the chain's `CcmpContract` still trusts the current key
+    Pending,
+    // the chain's `CcmpContract` trusts the next key, messages are delivered with it
+    Switched,
+}
+
+/// A signer of the next key the `CcmpContract` of a chain has to trust before
+/// it is switched, one for each key derivation in use.
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone)]
+pub struct NextSigner {
+    pub chain_id: u64,
+    pub derivation: KeyDerivation,
+    // the daemon of a per daemon key
+    pub daemon_id: Option<u64>,
+    pub signer: String,
+}
+
+/// A rotation of the attestation key, until it is completed every message is
+/// signed with both the current and the next key.
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone)]
+pub struct KeyRotation {
+    pub current_key: String,
+    pub next_key: String,
+    pub next_public_key: String,
+    pub next_address: String,
+    pub next_signers: Vec<NextSigner>,
+    pub started_at: u64,
+    pub chains: HashMap<u64, RotationStatus>,
+}
+
+/// Public keys of derived attestation keys are cached, they never change for
+/// a key name, a path and an algorithm, so they are fetched once.
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone, Default)]
+pub struct KeysStorage {
+    public_keys: HashMap<DerivedKey, Vec<u8>>,
+    // the key messages are attested with, the canister key if not rotated yet
+    attestation_key: Option<String>,
+    rotation: Option<KeyRotation>,
+}
+
+impl KeysStorage {
+    pub async fn get_public_key(key: DerivedKey) -> Result<Vec<u8>, KeysError> {
+        let cached_public_key =
+            STORAGE.with(|storage| storage.borrow().keys_storage.public_keys.get(&key).cloned());
+        if let Some(public_key) = cached_public_key {
+            return Ok(public_key);
+        }
+
+        let public_key = signing::public_key(&key).await?;
+
+        STORAGE.with(|storage| {
+            storage
+                .borrow_mut()
+                .keys_storage
+                .public_keys
+                .insert(key, public_key.clone())
+        });
+
+        Ok(public_key)
+    }
+
+    pub async fn get_evm_address(key: DerivedKey) -> Result<String, KeysError> {
+        let public_key = Self::get_public_key(key).await?;
+
+        let address = pubkey_to_address(&public_key).map_err(KeysError::InvalidPublicKey)?;
+
+        Ok(format!("0x{}", hex::encode(address.0)))
+    }
+
+    /// The identity a destination verifies signatures of the key against: the
+    /// address for ECDSA keys and the hex encoded public key for Schnorr ones.
+    pub async fn get_signer(key: DerivedKey) -> Result<String, KeysError> {
+        match key.algorithm {
+            SigningAlgorithm::EcdsaSecp256k1 => Self::get_evm_address(key).await,
+            _ => Ok(hex::encode(Self::get_public_key(key).await?)),
+        }
+    }
+
+    pub fn attestation_key() -> String {
+        STORAGE.with(|storage| {
+            let storage = storage.borrow();
+
+            storage
+                .keys_storage
+                .attestation_key
+                .clone()
+                .unwrap_or_else(|| storage.key.clone())
+        })
+    }
+
+    pub fn next_attestation_key() -> Option<String> {
+        STORAGE.with(|storage| {
+            storage
+                .borrow()
+                .keys_storage
+                .rotation
+                .as_ref()
+                .map(|rotation| rotation.next_key.clone())
+        })
+    }
+
+    pub fn get_rotation() -> Option<KeyRotation> {
+        STORAGE.with(|storage| storage.borrow().keys_storage.rotation.clone())
+    }
+
+    pub fn is_switched(chain_id: u64) -> bool {
+        STORAGE.with(|storage| {
+            storage
+                .borrow()
+                .keys_storage
+                .rotation
+                .as_ref()
+                .and_then(|rotation| rotation.chains.get(&chain_id))
+                .map_or(false, |status| *status == RotationStatus::Switched)
+        })
+    }
+
+    /// Starts signing with the next key next to the current one, the returned
+    /// rotation carries the signers of the next key to publish on each chain.
+    /// Daemons registered later get theirs from `get_next_signer_address`.
+    pub async fn start_rotation(next_key: String) -> Result<KeyRotation, KeysError> {
+        if Self::get_rotation().is_some() {
+            return Err(KeysError::RotationInProgress);
+        }
+
+        let current_key = Self::attestation_key();
+        if current_key == next_key {
+            return Err(KeysError::SameKey);
+        }
+
+        let key = DerivedKey {
+            key_name: next_key.clone(),
+            derivation_path: vec![],
+            algorithm: SigningAlgorithm::EcdsaSecp256k1,
+        };
+        let next_public_key = Self::get_public_key(key.clone()).await?;
+        let next_address = Self::get_evm_address(key).await?;
+        let next_signers = Self::next_signers(&next_key).await?;
+
+        STORAGE.with(|storage| {
+            let storage = &mut *storage.borrow_mut();
+
+            // checked again, another rotation could start while awaiting the key
+            if storage.keys_storage.rotation.is_some() {
+                return Err(KeysError::RotationInProgress);
+            }
+
+            let rotation = KeyRotation {
+                current_key,
+                next_key,
+                next_public_key: hex::encode(next_public_key),
+                next_address,
+                next_signers,
+                started_at: time(),
+                // chains without attestation have nothing to switch
+                chains: storage
+                    .chains_storage
+                    .chains_metadata
+                    .iter()
+                    .map(|(id, chain_metadata)| {
+                        let status = if chain_metadata.chain_type.is_attested() {
+                            RotationStatus::Pending
+                        } else {
+                            RotationStatus::Switched
+                        };
+
+                        (*id, status)
+                    })
+                    .collect(),
+            };
+
+            storage.keys_storage.rotation = Some(rotation.clone());
+            // left from a cancelled rotation to another key
+            clear_next_signatures(storage);
+
+            Ok(rotation)
+        })
+    }
+
+    /// The signers of the next key for every attested chain: the shared one, the
+    /// chain's one if a daemon derives keys per chain and one for each daemon
+    /// deriving its own key.
+    async fn next_signers(next_key: &str) -> Result<Vec<NextSigner>, KeysError> {
+        let (chains, derivations) = STORAGE.with(|storage| {
+            let storage = storage.borrow();
+
+            let chains = storage
+                .chains_storage
+                .chains_metadata
+                .iter()
+                .filter(|(_, chain_metadata)| chain_metadata.chain_type.is_attested())
+                .map(|(id, chain_metadata)| (*id, chain_metadata.chain_type.signing_algorithm()))
+                .collect::<Vec<_>>();
+
+            let mut derivations = vec![(KeyDerivation::Shared, None)];
+            let daemons = storage.daemon_storage.daemons.values();
+            if daemons
+                .clone()
+                .any(|daemon| daemon.key_derivation == KeyDerivation::PerChain)
+            {
+                derivations.push((KeyDerivation::PerChain, None));
+            }
+            derivations.extend(
+                daemons
+                    .filter(|daemon| daemon.key_derivation == KeyDerivation::PerDaemon)
+                    .map(|daemon| (KeyDerivation::PerDaemon, Some(daemon.id))),
+            );
+
+            (chains, derivations)
+        });
+
+        let mut next_signers = vec![];
+        for (chain_id, algorithm) in chains {
+            for (derivation, daemon_id) in derivations.iter().copied() {
+                let key = DerivedKey {
+                    key_name: next_key.to_string(),
+                    derivation_path: derivation
+                        .derivation_path(daemon_id.unwrap_or_default(), chain_id),
+                    algorithm,
+                };
+
+                next_signers.push(NextSigner {
+                    chain_id,
+                    derivation,
+                    daemon_id,
+                    signer: Self::get_signer(key).await?,
+                });
+            }
+        }
+
+        Ok(next_signers)
+    }
+
+    /// Marks the chain as trusting the next key. Messages to the chain signed
+    /// before the rotation have no signature of the next key and are signed again.
+    pub fn switch_chain(chain_id: u64) -> Result<(), KeysError> {
+        STORAGE.with(|storage| {
+            let storage = &mut *storage.borrow_mut();
+
+            if !storage
+                .chains_storage
+                .chains_metadata
+                .contains_key(&chain_id)
+            {
+                return Err(KeysError::ChainNotFound);
+            }
+
+            let rotation = storage
+                .keys_storage
+                .rotation
+                .as_mut()
+                .ok_or(KeysError::NoRotationInProgress)?;
+
+            rotation.chains.insert(chain_id, RotationStatus::Switched);
+
+            let unsigned = storage.signed_messages.extract(|message| {
+                message.to_chain_id == chain_id && message.next_signature.is_none()
+            });
+            for (owner, mut message) in unsigned {
+                message.signature = None;
+                storage.listened_messages.insert_ordered(owner, message);
+            }
+
+            for dead_letter in storage.dead_letters_storage.dead_letters.values_mut() {
+                let message = &mut dead_letter.message;
+                if dead_letter.stage == FailedStage::Write
+                    && message.to_chain_id == chain_id
+                    && message.next_signature.is_none()
+                {
+                    dead_letter.stage = FailedStage::Sign;
+                    message.signature = None;
+                }
+            }
+
+            if !storage.listened_messages.is_empty() {
+                storage.signer_job.start(&mut storage.scheduler);
+            }
+
+            Ok(())
+        })
+    }
+
+    /// Retires the current key once every chain trusts the next one.
+    pub fn complete_rotation() -> Result<(), KeysError> {
+        STORAGE.with(|storage| {
+            let storage = &mut *storage.borrow_mut();
+
+            let rotation = storage
+                .keys_storage
+                .rotation
+                .as_ref()
+                .ok_or(KeysError::NoRotationInProgress)?;
+
+            let pending_chains = storage
+                .chains_storage
+                .chains_metadata
+                .keys()
+                .filter(|id| rotation.chains.get(id) != Some(&RotationStatus::Switched))
+                .copied()
+                .collect::<Vec<_>>();
+
+            if !pending_chains.is_empty() {
+                return Err(KeysError::ChainsNotSwitched(pending_chains));
+            }
+
+            let next_key = rotation.next_key.clone();
+
+            let messages = storage
+                .signed_messages
+                .iter_mut()
+                .map(|(_, message)| message)
+                .chain(
+                    storage
+                        .dead_letters_storage
+                        .dead_letters
+                        .values_mut()
+                        .map(|dead_letter| &mut dead_letter.message),
+                );
+            for message in messages {
+                if let Some(next_signature) = message.next_signature.take() {
+                    message.signature = Some(next_signature);
+                }
+            }
+
+            storage.keys_storage.attestation_key = Some(next_key.clone());
+            storage.keys_storage.rotation = None;
+            // the cached canister public key belongs to the retired key
+            storage.public_key = String::new();
+
+            log!("[KEYS] key rotation completed, key: {}", next_key);
+
+            Ok(())
+        })
+    }
+
+    /// Drops the next key and the signatures made with it.
+    pub fn cancel_rotation() -> Result<KeyRotation, KeysError> {
+        STORAGE.with(|storage| {
+            let storage = &mut *storage.borrow_mut();
+
+            let rotation = storage
+                .keys_storage
+                .rotation
+                .take()
+                .ok_or(KeysError::NoRotationInProgress)?;
+
+            clear_next_signatures(storage);
+
+            Ok(rotation)
+        })
+    }
+}
+
+/// A next signature is only valid during the rotation it was made in.
+fn clear_next_signatures(storage: &mut Storage) {
+    let messages = storage
+        .listened_messages
+        .iter_mut()
+        .chain(storage.signed_messages.iter_mut())
+        .map(|(_, message)| message)
+        .chain(
+            storage
+                .dead_letters_storage
+                .dead_letters
+                .values_mut()
+                .map(|dead_letter| &mut dead_letter.message),
+        )
+        .chain(
+            storage
+                .pending_txs_storage
+                .0
+                .iter_mut()
+                .map(|pending_tx| &mut pending_tx.message),
+        );
+
+    for message in messages {
+        message.next_signature = None;
+    }
+}
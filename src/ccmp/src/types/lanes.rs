@@ -0,0 +1,113 @@
+use std::collections::HashMap;
+
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+
+use super::messages::Message;
+use crate::STORAGE;
+
+/// A source to destination route of an ordered daemon, messages of a lane are
+/// delivered strictly by their source index.
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Lane {
+    pub daemon_id: u64,
+    pub from_chain_id: u64,
+    pub to_chain_id: u64,
+    pub receiver: Vec<u8>,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone)]
+pub struct BlockedLane {
+    pub lane: Lane,
+    pub head_index: u64,
+    pub dead_letter_id: u64,
+    pub error: String,
+    pub held_messages: u64,
+}
+
+impl Lane {
+    pub fn of(message: &Message) -> Self {
+        Self {
+            daemon_id: message.daemon_id,
+            from_chain_id: message.from_chain_id,
+            to_chain_id: message.to_chain_id,
+            receiver: message.receiver.clone(),
+        }
+    }
+
+    /// A lane of an ordered daemon is blocked by its dead lettered message with
+    /// the lowest index until that message is delivered or discarded.
+    pub fn blocked_lanes() -> HashMap<Lane, BlockedLane> {
+        STORAGE.with(|storage| {
+            let storage = storage.borrow();
+
+            let mut blocked_lanes: HashMap<Lane, BlockedLane> = HashMap::new();
+            for dead_letter in storage.dead_letters_storage.dead_letters.values() {
+                let is_ordered = storage
+                    .daemon_storage
+                    .daemons
+                    .get(&dead_letter.message.daemon_id)
+                    .map_or(false, |daemon| daemon.ordered);
+
+                if !is_ordered {
+                    continue;
+                }
+
+                let lane = Lane::of(&dead_letter.message);
+                let head = BlockedLane {
+                    lane: lane.clone(),
+                    head_index: dead_letter.message.index,
+                    dead_letter_id: dead_letter.id,
+                    error: dead_letter.error.clone(),
+                    held_messages: 0,
+                };
+
+                match blocked_lanes.get(&lane) {
+                    Some(blocked) if blocked.head_index <= head.head_index => {}
+                    _ => {
+                        blocked_lanes.insert(lane, head);
+                    }
+                }
+            }
+
+            let queued_messages = storage
+                .listened_messages
+                .iter()
+                .chain(storage.signed_messages.iter());
+            for (_, message) in queued_messages {
+                if let Some(blocked) = blocked_lanes.get_mut(&Lane::of(message)) {
+                    if message.index > blocked.head_index {
+                        blocked.held_messages += 1;
+                    }
+                }
+            }
+
+            blocked_lanes
+        })
+    }
+
+    pub fn blocked_lanes_of(principal: &Principal) -> Vec<BlockedLane> {
+        let daemon_ids = STORAGE.with(|storage| {
+            storage
+                .borrow()
+                .daemon_storage
+                .daemons
+                .values()
+                .filter(|daemon| daemon.creator == *principal)
+                .map(|daemon| daemon.id)
+                .collect::<Vec<_>>()
+        });
+
+        Self::blocked_lanes()
+            .into_values()
+            .filter(|blocked| daemon_ids.contains(&blocked.lane.daemon_id))
+            .collect()
+    }
+
+    /// Only the head of a blocked lane may pass, the rest of the lane waits for it.
+    pub fn is_open(blocked_lanes: &HashMap<Lane, BlockedLane>, message: &Message) -> bool {
+        blocked_lanes
+            .get(&Lane::of(message))
+            .map_or(true, |blocked| message.index <= blocked.head_index)
+    }
+}
@@ -0,0 +1,319 @@
+use std::collections::{HashMap, VecDeque};
+
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+
+use super::messages::Message;
+
+/// Per-principal message queues drained with deficit round-robin, so a principal
+/// with many daemons can not starve the others. Every message costs one unit of
+/// deficit and a principal receives `weight` units per round.
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone, Default)]
+pub struct MessageQueue {
+    queues: HashMap<Principal, Vec<Message>>,
+    // principals with non-empty queues in round-robin order
+    round: Vec<Principal>,
+    deficits: HashMap<Principal, u64>,
+}
+
+impl MessageQueue {
+    pub fn push(&mut self, owner: Principal, message: Message) {
+        let queue = self.queues.entry(owner).or_default();
+        if queue.is_empty() {
+            self.round.push(owner);
+        }
+
+        queue.push(message);
+    }
+
+    /// Inserts the message in front of the messages of the same daemon with
+    /// a greater index, used to put retried messages back in their order.
+    pub fn insert_ordered(&mut self, owner: Principal, message: Message) {
+        let queue = self.queues.entry(owner).or_default();
+        if queue.is_empty() {
+            self.round.push(owner);
+        }
+
+        let position = queue
+            .iter()
+            .position(|m| m.daemon_id == message.daemon_id && m.index > message.index)
+            .unwrap_or(queue.len());
+
+        queue.insert(position, message);
+    }
+
+    pub fn append(&mut self, owner: Principal, messages: &mut Vec<Message>) {
+        for message in messages.drain(..) {
+            self.push(owner, message);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.queues.values().map(|queue| queue.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.round.is_empty()
+    }
+
+    pub fn len_of(&self, owner: &Principal) -> usize {
+        self.queues.get(owner).map_or(0, |queue| queue.len())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Principal, &Message)> {
+        self.queues
+            .iter()
+            .flat_map(|(owner, queue)| queue.iter().map(move |message| (owner, message)))
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (&Principal, &mut Message)> {
+        self.queues
+            .iter_mut()
+            .flat_map(|(owner, queue)| queue.iter_mut().map(move |message| (owner, message)))
+    }
+
+    /// Removes every message for which `predicate` holds regardless of the turns.
+    pub fn extract<P>(&mut self, predicate: P) -> Vec<(Principal, Message)>
+    where
+        P: Fn(&Message) -> bool,
+    {
+        let mut extracted = vec![];
+        for (owner, queue) in self.queues.iter_mut() {
+            let (matched, rest): (Vec<_>, Vec<_>) = queue.drain(..).partition(|m| predicate(m));
+
+            extracted.extend(matched.into_iter().map(|message| (*owner, message)));
+            *queue = rest;
+        }
+
+        self.queues.retain(|_, queue| !queue.is_empty());
+        self.round.retain(|owner| self.queues.contains_key(owner));
+        self.deficits
+            .retain(|owner, _| self.queues.contains_key(owner));
+
+        extracted
+    }
+
+    /// Drains up to `max` messages for which `eligible` holds, the others are
+    /// left in the queue in their order.
+    pub fn drain_fair<W, E>(
+        &mut self,
+        max: usize,
+        weight: W,
+        eligible: E,
+    ) -> Vec<(Principal, Message)>
+    where
+        W: Fn(&Principal) -> u64,
+        E: Fn(&Message) -> bool,
+    {
+        let mut drained = vec![];
+        // a full round of turns without drained messages means that only
+        // ineligible messages are left
+        let mut idle_turns = 0;
+
+        while drained.len() < max && idle_turns < self.round.len() {
+            let owner = self.round[0];
+
+            let deficit = self.deficits.entry(owner).or_default();
+            // a non-zero deficit means the previous drain stopped in the middle
+            // of this principal's turn, so the turn is resumed without a new quantum
+            if *deficit == 0 {
+                *deficit = weight(&owner).max(1);
+            }
+
+            let queue = self.queues.get_mut(&owner).expect("queue should exist");
+            let mut taken = 0;
+            let mut i = 0;
+            while *deficit > 0 && i < queue.len() && drained.len() < max {
+                if eligible(&queue[i]) {
+                    drained.push((owner, queue.remove(i)));
+                    *deficit -= 1;
+                    taken += 1;
+                } else {
+                    i += 1;
+                }
+            }
+
+            let is_empty = queue.is_empty();
+            let is_turn_over = *deficit == 0 || i >= queue.len();
+
+            if is_empty {
+                self.queues.remove(&owner);
+                self.deficits.remove(&owner);
+                self.round.remove(0);
+                idle_turns = 0;
+                continue;
+            }
+
+            if taken == 0 {
+                idle_turns += 1;
+            } else {
+                idle_turns = 0;
+            }
+
+            if is_turn_over {
+                *deficit = 0;
+                self.round.rotate_left(1);
+            }
+        }
+
+        drained
+    }
+
+    /// Returns the owner's messages with their positions in the order the queue
+    /// would be drained in if nothing else was pushed. The turns are replayed
+    /// on the queue lengths, the messages are not drained.
+    pub fn positions<F>(&self, owner: &Principal, weight: F) -> Vec<(u64, Message)>
+    where
+        F: Fn(&Principal) -> u64,
+    {
+        let Some(queue) = self.queues.get(owner) else {
+            return vec![];
+        };
+
+        let mut turns: VecDeque<(Principal, u64, u64)> = self
+            .round
+            .iter()
+            .map(|principal| {
+                let deficit = self.deficits.get(principal).copied().unwrap_or_default();
+
+                (*principal, self.len_of(principal) as u64, deficit)
+            })
+            .collect();
+
+        let mut positions = Vec::with_capacity(queue.len());
+        let mut position = 0;
+        while let Some((principal, left, deficit)) = turns.pop_front() {
+            let quantum = if deficit == 0 {
+                weight(&principal).max(1)
+            } else {
+                deficit
+            };
+            let taken = quantum.min(left);
+
+            if principal == *owner {
+                positions.extend(position..position + taken);
+
+                if taken == left {
+                    break;
+                }
+            }
+
+            position += taken;
+            if taken < left {
+                turns.push_back((principal, left - taken, 0));
+            }
+        }
+
+        positions.into_iter().zip(queue.iter().cloned()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn principal(id: u8) -> Principal {
+        Principal::from_slice(&[id])
+    }
+
+    fn queue(messages: &[(u8, u64)]) -> MessageQueue {
+        let mut queue = MessageQueue::default();
+        for (owner, index) in messages {
+            queue.push(
+                principal(*owner),
+                Message {
+                    index: *index,
+                    daemon_id: *owner as u64,
+                    ..Default::default()
+                },
+            );
+        }
+
+        queue
+    }
+
+    fn weight(owner: &Principal) -> u64 {
+        if *owner == principal(1) {
+            2
+        } else {
+            1
+        }
+    }
+
+    fn order(drained: Vec<(Principal, Message)>) -> Vec<(Principal, u64)> {
+        drained
+            .into_iter()
+            .map(|(owner, message)| (owner, message.index))
+            .collect()
+    }
+
+    #[test]
+    fn drain_fair_splits_turns_by_weight() {
+        let mut queue = queue(&[(1, 0), (1, 1), (1, 2), (1, 3), (2, 0), (2, 1), (2, 2)]);
+
+        let drained = queue.drain_fair(usize::MAX, weight, |_| true);
+
+        assert_eq!(
+            order(drained),
+            vec![
+                (principal(1), 0),
+                (principal(1), 1),
+                (principal(2), 0),
+                (principal(1), 2),
+                (principal(1), 3),
+                (principal(2), 1),
+                (principal(2), 2),
+            ]
+        );
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn drain_fair_resumes_an_interrupted_turn() {
+        let mut queue = queue(&[(1, 0), (1, 1), (1, 2), (2, 0)]);
+
+        let first = queue.drain_fair(1, weight, |_| true);
+        let second = queue.drain_fair(2, weight, |_| true);
+
+        assert_eq!(order(first), vec![(principal(1), 0)]);
+        assert_eq!(order(second), vec![(principal(1), 1), (principal(2), 0)]);
+        assert_eq!(queue.len(), 1);
+    }
+
+    #[test]
+    fn drain_fair_keeps_ineligible_messages_in_order() {
+        let mut queue = queue(&[(1, 0), (1, 1), (1, 2), (2, 0)]);
+
+        let drained = queue.drain_fair(usize::MAX, weight, |m| m.index != 1);
+
+        assert_eq!(
+            order(drained),
+            vec![(principal(1), 0), (principal(1), 2), (principal(2), 0)]
+        );
+        assert_eq!(queue.len_of(&principal(1)), 1);
+        assert!(queue.drain_fair(usize::MAX, weight, |_| false).is_empty());
+        assert_eq!(queue.len(), 1);
+    }
+
+    #[test]
+    fn positions_follow_the_drain_order() {
+        let mut queue = queue(&[(1, 0), (1, 1), (1, 2), (2, 0), (2, 1), (3, 0)]);
+        queue.drain_fair(1, weight, |_| true);
+
+        let positions = queue.positions(&principal(2), weight);
+        let drained = queue.drain_fair(usize::MAX, weight, |_| true);
+
+        let expected: Vec<(u64, u64)> = drained
+            .into_iter()
+            .enumerate()
+            .filter(|(_, (owner, _))| *owner == principal(2))
+            .map(|(position, (_, message))| (position as u64, message.index))
+            .collect();
+        let positions: Vec<(u64, u64)> = positions
+            .into_iter()
+            .map(|(position, message)| (position, message.index))
+            .collect();
+        assert_eq!(positions, expected);
+        assert_eq!(positions, vec![(1, 0), (4, 1)]);
+    }
+}
@@ -1,8 +1,5 @@
-use std::str::FromStr;
-
 use candid::{CandidType, Nat};
-use ethabi::{Address, Log, Token};
-use ic_cdk::api::management_canister::ecdsa::{EcdsaCurve, EcdsaKeyId, SignWithEcdsaArgument};
+use ethabi::{Log, Token};
 use ic_web3_rs::signing::keccak256;
 use scopeguard::defer;
 use serde::{Deserialize, Serialize};
@@ -10,30 +7,42 @@ use thiserror::Error;
 
 use super::{
     balances::BalancesStorage,
-    chains::{Chain, ChainType},
+    bitcoin_chains::BitcoinCcmpPayload,
+    chains::{Chain, ChainMetadata, ChainType, ChainsStorage},
     daemons::DaemonsStorage,
-    evm_chains::EvmChainError,
-    ECDSA_SIGN_CYCLES, MINIMUM_CYCLES,
+    evm_chains::{EvmChainError, EvmChainsStorage},
+    icp_chains::{IcpChain, IcpChainError},
+    keys::{DerivedKey, KeysError, KeysStorage},
+    solana_chains::{SolanaCcmpEvent, SolanaChainError, SolanaChainsStorage},
+    MINIMUM_CYCLES,
 };
 use crate::{
-    log, storage_get,
+    log,
     types::daemons::Daemon,
     utils::{
-        encoding, format_evm_address,
-        signing::{self, get_eth_v},
+        eip712::{parse_address, CcmpMessage},
+        encoding,
+        merkle::MerkleTree,
+        signing::{self, to_eth_signature, SignatureError, SigningError},
         UtilsError,
     },
     STORAGE,
 };
 
 const SIGNER_JOB_CYCLES_COST: u64 = 2_000_000;
+const ED25519_SIGNATURE_LENGTH: usize = 64;
+const SOLANA_ADDRESS_LENGTH: usize = 32;
+
+// `0x19` as in EIP-191 followed by a byte that is not an EIP-191 version
+const DIGEST_PREFIX: &[u8] = b"\x19CCMP";
+const BATCH_ROOT_TAG: u8 = 0xff;
 
 #[derive(Error, Debug)]
 pub enum MessageError {
     #[error("encoding error: {0}")]
     Encoding(#[from] encoding::EncodingError),
-    #[error("sign with ecdsa error: {0}")]
-    SignWithECDSAError(String),
+    #[error("signing error: {0}")]
+    Signing(#[from] SigningError),
     #[error("utils error: {0}")]
     Utils(#[from] UtilsError),
     #[error("chain does not exist")]
@@ -42,13 +51,55 @@ pub enum MessageError {
     UnknownChainType,
     #[error("evm chain error: {0}")]
     EvmChain(#[from] EvmChainError),
+    #[error("eip712 domain is not set for the chain")]
+    MissingEip712Domain,
+    #[error("keys error: {0}")]
+    Keys(#[from] KeysError),
+    #[error("signature error: {0}")]
+    Signature(#[from] SignatureError),
+    #[error("solana chain error: {0}")]
+    SolanaChain(#[from] SolanaChainError),
+    #[error("icp chain error: {0}")]
+    IcpChain(#[from] IcpChainError),
+    #[error("invalid ed25519 signature length: {0}")]
+    InvalidEd25519Signature(usize),
+    #[error("batched messages have to be abi.encodePacked")]
+    UnsupportedBatchEncoding,
 }
 
-#[derive(CandidType, Deserialize, Serialize, Debug, Default, Clone)]
+/// The way a message is encoded for a destination, the signed digest is the
+/// keccak256 of the encoded message, tagged with its encoding for chains that
+/// opted in to tagged digests.
+#[derive(CandidType, Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq)]
 pub enum Encoding {
+    // the raw message payload
     #[default]
     Plain,
+    // `abi.encodePacked` of the message fields
     AbiEncodePacked,
+    // `abi.encode` of the message fields
+    AbiEncode,
+    // EIP-712 typed data under the domain of the destination chain
+    Eip712,
+}
+
+impl Encoding {
+    /// EIP-712 typed data already starts with its own `\x19\x01` prefix and domain.
+    pub fn digest_tag(&self) -> Option<u8> {
+        match self {
+            Encoding::Plain => Some(0),
+            Encoding::AbiEncodePacked => Some(1),
+            Encoding::AbiEncode => Some(2),
+            Encoding::Eip712 => None,
+        }
+    }
+}
+
+/// `keccak256(DIGEST_PREFIX || tag || hash)`, the digest of a payload hash the
+/// attestation key signs. No payload can make it an Ethereum transaction or
+/// message hash, nor the digest of another encoding.
+fn tagged_digest(tag: u8, hash: &[u8; 32]) -> [u8; 32] {
+    keccak256(&[DIGEST_PREFIX, &[tag], hash.as_slice()].concat())
 }
 
 #[derive(CandidType, Deserialize, Serialize, Debug, Default, Clone)]
@@ -61,6 +112,17 @@ pub struct Message {
     pub receiver: Vec<u8>,
     pub signature: Option<Vec<u8>>,
     pub daemon_id: u64,
+    pub batch: Option<BatchProof>,
+    // signature of the next attestation key while a key rotation is in progress
+    pub next_signature: Option<Vec<u8>>,
+}
+
+/// Inclusion proof of a message in a batch, the signature of a batched
+/// message is the signature of the batch root.
+#[derive(CandidType, Deserialize, Serialize, Debug, Default, Clone)]
+pub struct BatchProof {
+    pub root: Vec<u8>,
+    pub proof: Vec<Vec<u8>>,
 }
 
 impl Message {
@@ -100,82 +162,308 @@ impl Message {
         })
     }
 
+    /// Returns `None` for events to chains that are not registered.
+    pub fn from_solana_event(
+        event: SolanaCcmpEvent,
+        from_chain_id: u64,
+        daemon_id: u64,
+    ) -> Option<Self> {
+        ChainsStorage::get_chain_metadata(event.to_chain_id)?;
+
+        Some(Message {
+            index: event.index,
+            from_chain_id,
+            to_chain_id: event.to_chain_id,
+            sender: event.sender,
+            message: event.message,
+            receiver: event.receiver,
+            daemon_id,
+            ..Default::default()
+        })
+    }
+
+    /// The sender is the txid, Bitcoin transactions have no single sender. The
+    /// index is left to the caller, it is assigned only to messages kept.
+    pub fn from_bitcoin_payload(
+        payload: BitcoinCcmpPayload,
+        txid: &[u8],
+        daemon: &Daemon,
+    ) -> Option<Self> {
+        ChainsStorage::get_chain_metadata(payload.to_chain_id)?;
+
+        Some(Message {
+            from_chain_id: daemon.listen_chain_id,
+            to_chain_id: payload.to_chain_id,
+            sender: txid.iter().rev().copied().collect(),
+            message: payload.message,
+            receiver: payload.receiver,
+            daemon_id: daemon.id,
+            ..Default::default()
+        })
+    }
+
+    /// Drops the signatures and the batch proof, for the message to be signed again.
+    pub fn clear_signatures(&mut self) {
+        self.signature = None;
+        self.next_signature = None;
+        self.batch = None;
+    }
+
+    /// Messages are identified by their daemon and the index assigned on the source chain.
+    pub fn is_same(&self, other: &Message) -> bool {
+        self.daemon_id == other.daemon_id && self.index == other.index
+    }
+
     pub fn encode(&self, encoding: Encoding) -> Result<Vec<u8>, MessageError> {
         match encoding {
-            Encoding::AbiEncodePacked => {
-                let receiver = Address::from_str(&format_evm_address(hex::encode(&self.receiver))?)
-                    .expect("invalid receiver address");
-
-                let tokens = vec![
-                    Token::Uint(self.index.into()),
-                    Token::Uint(self.from_chain_id.into()),
-                    Token::Uint(self.to_chain_id.into()),
-                    Token::Bytes(self.sender.clone()),
-                    Token::Bytes(self.message.clone()),
-                    Token::Address(receiver),
-                ];
-
-                Ok(encoding::encode_packed(&tokens)?)
+            Encoding::Plain => Ok(self.message.clone()),
+            Encoding::AbiEncodePacked => Ok(encoding::encode_packed(&self.tokens()?)?),
+            Encoding::AbiEncode => Ok(ethabi::encode(&self.tokens()?)),
+            Encoding::Eip712 => {
+                let domain = EvmChainsStorage::get_chain(self.to_chain_id)
+                    .ok_or(MessageError::ChainDoesNotExist)?
+                    .eip712_domain
+                    .ok_or(MessageError::MissingEip712Domain)?;
+
+                let typed_message = CcmpMessage {
+                    index: self.index,
+                    from_chain_id: self.from_chain_id,
+                    to_chain_id: self.to_chain_id,
+                    sender: &self.sender,
+                    message: &self.message,
+                    receiver: parse_address(&hex::encode(&self.receiver))?,
+                };
+
+                Ok(typed_message.encode(&domain)?)
             }
-            _ => Ok(self.message.clone()),
         }
     }
 
+    fn tokens(&self) -> Result<Vec<Token>, MessageError> {
+        // Solana receivers are 32-byte program ids, not addresses
+        let receiver = match self.receiver.len() {
+            SOLANA_ADDRESS_LENGTH => Token::FixedBytes(self.receiver.clone()),
+            _ => Token::Address(parse_address(&hex::encode(&self.receiver))?),
+        };
+
+        Ok(vec![
+            Token::Uint(self.index.into()),
+            Token::Uint(self.from_chain_id.into()),
+            Token::Uint(self.to_chain_id.into()),
+            Token::Bytes(self.sender.clone()),
+            Token::Bytes(self.message.clone()),
+            receiver,
+        ])
+    }
+
     pub async fn sign(self) -> Result<Self, MessageError> {
-        defer! {
-            Self::collect_signing_cycles(self.daemon_id);
+        let chain_metadata = Self::chain_metadata(self.to_chain_id)?;
+
+        if !chain_metadata.chain_type.is_attested() {
+            return Ok(self);
+        }
+
+        let message_hash = self.hash(&chain_metadata)?;
+        let (signature, next_signature) = Self::attest(
+            self.daemon_id,
+            self.to_chain_id,
+            &chain_metadata,
+            message_hash,
+        )
+        .await?;
+
+        let mut message = self;
+        message.signature = Some(signature);
+        message.next_signature = next_signature;
+
+        Ok(message)
+    }
+
+    /// Signs a single Merkle root over all messages, which have to share the daemon
+    /// and the destination, and attaches a proof of inclusion to every message.
+    /// The leaves are the `abi.encodePacked` hashes the receivers rebuild, the
+    /// root is signed under its own tag.
+    pub async fn sign_batch(messages: Vec<Self>) -> Result<Vec<Self>, MessageError> {
+        let Some(first) = messages.first() else {
+            return Ok(messages);
         };
 
-        let chain_metadata = STORAGE.with(|storage| {
+        let daemon_id = first.daemon_id;
+        let to_chain_id = first.to_chain_id;
+        let chain_metadata = Self::chain_metadata(first.to_chain_id)?;
+        if chain_metadata.encoding != Encoding::AbiEncodePacked {
+            return Err(MessageError::UnsupportedBatchEncoding);
+        }
+
+        let leaves = messages
+            .iter()
+            .map(|message| message.payload_hash(&chain_metadata))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let tree = MerkleTree::new(leaves);
+        let root = tree.root();
+
+        let root_digest = if chain_metadata.tagged_digest {
+            tagged_digest(BATCH_ROOT_TAG, &root)
+        } else {
+            root
+        };
+        let (signature, next_signature) = Self::attest(
+            daemon_id,
+            to_chain_id,
+            &chain_metadata,
+            root_digest.to_vec(),
+        )
+        .await?;
+
+        let messages = messages
+            .into_iter()
+            .enumerate()
+            .map(|(i, mut message)| {
+                message.signature = Some(signature.clone());
+                message.next_signature = next_signature.clone();
+                message.batch = Some(BatchProof {
+                    root: root.to_vec(),
+                    proof: tree.proof(i).into_iter().map(|h| h.to_vec()).collect(),
+                });
+                message
+            })
+            .collect();
+
+        Ok(messages)
+    }
+
+    fn chain_metadata(chain_id: u64) -> Result<ChainMetadata, MessageError> {
+        STORAGE.with(|storage| {
             storage
                 .borrow()
                 .chains_storage
                 .chains_metadata
-                .get(&self.to_chain_id)
+                .get(&chain_id)
                 .ok_or(MessageError::ChainDoesNotExist)
                 .cloned()
-        })?;
+        })
+    }
 
-        let message_hash = match chain_metadata.chain_type {
-            ChainType::Evm => {
-                let message = self.encode(Encoding::AbiEncodePacked)?;
-                keccak256(&message).to_vec()
+    /// The digest the attestation key signs, the one the destination verifies.
+    pub fn digest(&self) -> Result<Vec<u8>, MessageError> {
+        self.hash(&Self::chain_metadata(self.to_chain_id)?)
+    }
+
+    fn hash(&self, chain_metadata: &ChainMetadata) -> Result<Vec<u8>, MessageError> {
+        let payload_hash = self.payload_hash(chain_metadata)?;
+
+        let digest = match chain_metadata.encoding.digest_tag() {
+            Some(tag) if chain_metadata.tagged_digest => tagged_digest(tag, &payload_hash),
+            _ => payload_hash,
+        };
+
+        Ok(digest.to_vec())
+    }
+
+    fn payload_hash(&self, chain_metadata: &ChainMetadata) -> Result<[u8; 32], MessageError> {
+        match chain_metadata.chain_type {
+            ChainType::Evm | ChainType::Solana => {
+                Ok(keccak256(&self.encode(chain_metadata.encoding)?))
             }
-            _ => return Err(MessageError::UnknownChainType),
+            _ => Err(MessageError::UnknownChainType),
+        }
+    }
+
+    /// Signs the hash with the attestation key and, during a key rotation, with
+    /// the next attestation key as well.
+    async fn attest(
+        daemon_id: u64,
+        to_chain_id: u64,
+        chain_metadata: &ChainMetadata,
+        message_hash: Vec<u8>,
+    ) -> Result<(Vec<u8>, Option<Vec<u8>>), MessageError> {
+        let key = Self::attestation_key(daemon_id, to_chain_id);
+        let signature =
+            Self::sign_hash(daemon_id, key, chain_metadata, message_hash.clone()).await?;
+
+        let next_signature = match Self::next_attestation_key(daemon_id, to_chain_id) {
+            Some(next_key) => {
+                Some(Self::sign_hash(daemon_id, next_key, chain_metadata, message_hash).await?)
+            }
+            None => None,
         };
 
-        let sign_args = SignWithEcdsaArgument {
-            message_hash: message_hash.clone(),
-            derivation_path: vec![],
-            key_id: EcdsaKeyId {
-                curve: EcdsaCurve::Secp256k1,
-                name: storage_get!(key),
-            },
+        Ok((signature, next_signature))
+    }
+
+    async fn sign_hash(
+        daemon_id: u64,
+        key: DerivedKey,
+        chain_metadata: &ChainMetadata,
+        message_hash: Vec<u8>,
+    ) -> Result<Vec<u8>, MessageError> {
+        let sign_cycles = key.algorithm.sign_cycles();
+        defer! {
+            Self::collect_signing_cycles(daemon_id, sign_cycles);
         };
 
-        let mut signature = signing::sign(sign_args)
-            .await
-            .map_err(|(_, msg)| MessageError::SignWithECDSAError(msg))?
-            .0
-            .signature;
+        let public_key = KeysStorage::get_public_key(key.clone()).await?;
 
+        let signature = signing::sign(&key, message_hash.clone()).await?;
+
+        // the signature is verified here, so a message never carries a signature
+        // its destination would reject
         match chain_metadata.chain_type {
-            ChainType::Evm => {
-                signature.push(get_eth_v(&signature, &message_hash));
-            }
-            _ => return Err(MessageError::UnknownChainType),
+            ChainType::Evm => Ok(to_eth_signature(&signature, &message_hash, &public_key)?),
+            // the Ed25519 program of the destination verifies it as is
+            ChainType::Solana if signature.len() == ED25519_SIGNATURE_LENGTH => Ok(signature),
+            ChainType::Solana => Err(MessageError::InvalidEd25519Signature(signature.len())),
+            _ => Err(MessageError::UnknownChainType),
         }
+    }
 
-        let mut message = self.clone();
-        message.signature = Some(signature);
+    /// The key the messages of the daemon to the chain are attested with.
+    pub fn attestation_key(daemon_id: u64, to_chain_id: u64) -> DerivedKey {
+        Self::derived_key(KeysStorage::attestation_key(), daemon_id, to_chain_id)
+    }
 
-        Ok(message)
+    pub fn next_attestation_key(daemon_id: u64, to_chain_id: u64) -> Option<DerivedKey> {
+        KeysStorage::next_attestation_key()
+            .map(|key_name| Self::derived_key(key_name, daemon_id, to_chain_id))
     }
 
-    pub fn collect_signing_cycles(daemon_id: u64) {
+    /// The signing algorithm is the one the destination chain verifies.
+    fn derived_key(key_name: String, daemon_id: u64, to_chain_id: u64) -> DerivedKey {
+        let algorithm = ChainsStorage::get_chain_metadata(to_chain_id)
+            .map(|chain_metadata| chain_metadata.chain_type.signing_algorithm())
+            .unwrap_or_default();
+
+        DerivedKey {
+            key_name,
+            derivation_path: DaemonsStorage::key_derivation(daemon_id)
+                .derivation_path(daemon_id, to_chain_id),
+            algorithm,
+        }
+    }
+
+    /// The key of the signature `for_delivery` picks, destinations that take
+    /// the signer with the message need it.
+    pub fn delivery_key(&self) -> DerivedKey {
+        match Self::next_attestation_key(self.daemon_id, self.to_chain_id) {
+            Some(next_key) if KeysStorage::is_switched(self.to_chain_id) => next_key,
+            _ => Self::attestation_key(self.daemon_id, self.to_chain_id),
+        }
+    }
+
+    /// Picks the signature of the next key for chains that already trust it.
+    fn for_delivery(mut self) -> Self {
+        if KeysStorage::is_switched(self.to_chain_id) && self.next_signature.is_some() {
+            self.signature = self.next_signature.take();
+        }
+
+        self
+    }
+
+    pub fn collect_signing_cycles(daemon_id: u64, sign_cycles: u64) {
         let daemon = DaemonsStorage::get_daemon(daemon_id).expect("daemon not found");
         let mut used_cycles = 0;
-        used_cycles += SIGNER_JOB_CYCLES_COST + ECDSA_SIGN_CYCLES;
+        used_cycles += SIGNER_JOB_CYCLES_COST + sign_cycles;
 
         BalancesStorage::reduce_cycles(&daemon.creator, Nat::from(used_cycles));
 
@@ -214,11 +502,115 @@ impl Message {
                         .cloned()
                 })?;
 
-                evm_chain.write(self).await?;
+                evm_chain.write(self.for_delivery()).await?;
+            }
+            ChainType::Solana => {
+                let solana_chain = SolanaChainsStorage::get_chain(self.to_chain_id)
+                    .ok_or(MessageError::ChainDoesNotExist)?;
+
+                solana_chain.write(self.for_delivery()).await?;
             }
+            ChainType::Icp => IcpChain.write(self).await?,
             _ => return Err(MessageError::UnknownChainType),
         }
 
         Ok(())
     }
+
+    /// Delivers the messages of a batch signed by `sign_batch` in one transaction.
+    pub async fn send_batch(messages: Vec<Self>) -> Result<(), MessageError> {
+        let Some(first) = messages.first() else {
+            return Ok(());
+        };
+
+        log!(
+            "[WRITER] sending batch of {} messages to chain: {}",
+            messages.len(),
+            first.to_chain_id
+        );
+        let chain_metadata = Self::chain_metadata(first.to_chain_id)?;
+
+        match chain_metadata.chain_type {
+            ChainType::Evm => {
+                let evm_chain = EvmChainsStorage::get_chain(first.to_chain_id)
+                    .ok_or(MessageError::ChainDoesNotExist)?;
+
+                evm_chain
+                    .write_batch(messages.into_iter().map(Self::for_delivery).collect())
+                    .await?;
+            }
+            ChainType::Solana => {
+                let solana_chain = SolanaChainsStorage::get_chain(first.to_chain_id)
+                    .ok_or(MessageError::ChainDoesNotExist)?;
+
+                solana_chain
+                    .write_batch(messages.into_iter().map(Self::for_delivery).collect())
+                    .await?;
+            }
+            ChainType::Icp => IcpChain.write_batch(messages).await?,
+            _ => return Err(MessageError::UnknownChainType),
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tagged_digest_matches_known_vector() {
+        let digest = tagged_digest(1, &keccak256(b"payload"));
+
+        assert_eq!(
+            hex::encode(digest),
+            "bd25cae574fb5534398102bf65c7abd048d44def94abc9dd570f992bd8d516b3"
+        );
+    }
+
+    #[test]
+    fn digests_of_encodings_differ() {
+        let hash = keccak256(b"payload");
+
+        let tags = [
+            Encoding::Plain,
+            Encoding::AbiEncodePacked,
+            Encoding::AbiEncode,
+        ]
+        .iter()
+        .map(|encoding| encoding.digest_tag().unwrap())
+        .chain([BATCH_ROOT_TAG]);
+        let digests: Vec<_> = tags.map(|tag| tagged_digest(tag, &hash)).collect();
+
+        for (i, digest) in digests.iter().enumerate() {
+            assert_ne!(*digest, hash);
+            assert!(digests[i + 1..].iter().all(|other| other != digest));
+        }
+    }
+
+    #[test]
+    fn digest_is_tagged_only_for_chains_that_opted_in() {
+        let message = Message {
+            message: b"payload".to_vec(),
+            ..Default::default()
+        };
+        let mut chain_metadata = ChainMetadata {
+            chain_type: ChainType::Evm,
+            encoding: Encoding::Plain,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            message.hash(&chain_metadata).unwrap(),
+            keccak256(b"payload").to_vec()
+        );
+
+        chain_metadata.tagged_digest = true;
+
+        assert_eq!(
+            message.hash(&chain_metadata).unwrap(),
+            tagged_digest(0, &keccak256(b"payload")).to_vec()
+        );
+    }
 }
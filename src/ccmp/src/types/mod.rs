@@ -1,28 +1,49 @@
+pub mod audit_log;
 pub mod balances;
+pub mod bitcoin_chains;
 pub mod chains;
 pub mod config;
 pub mod daemons;
+pub mod dead_letters;
 pub mod evm_chains;
+pub mod icp_chains;
 pub mod job;
+pub mod keys;
+pub mod lanes;
+pub mod message_queue;
 pub mod messages;
 pub mod pending_tx;
+pub mod quotas;
+pub mod roles;
+pub mod scheduler;
+pub mod solana_chains;
 
 use candid::CandidType;
 use ic_web3_rs::ic::get_public_key;
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
-use crate::{storage_get, storage_set};
+use crate::{storage_get, storage_set, STORAGE};
+use audit_log::AuditLog;
 use balances::BalancesStorage;
 use chains::ChainsStorage;
+use icp_chains::IcpSendersStorage;
 use job::Job;
-use messages::Message;
+use keys::KeysStorage;
+use message_queue::MessageQueue;
+use quotas::QuotasStorage;
+use roles::RolesStorage;
+use scheduler::Scheduler;
 
-use self::{daemons::DaemonsStorage, pending_tx::PendingTransactionsStorage};
+use self::{
+    daemons::DaemonsStorage, dead_letters::DeadLettersStorage,
+    pending_tx::PendingTransactionsStorage,
+};
 
 pub const MINIMUM_CYCLES: u64 = 100_000_000_000;
 pub const HTTP_OUTCALL_CYCLES_COST: u64 = 49_140_000;
 pub const ECDSA_SIGN_CYCLES: u64 = 23_000_000_000;
+pub const SCHNORR_SIGN_CYCLES: u64 = 26_200_000_000;
 
 #[derive(Error, Debug)]
 pub enum StorageError {
@@ -38,11 +59,21 @@ pub struct Storage {
     pub signer_job: Job,
     pub writer_job: Job,
     pub checker_job: Job,
-    pub listened_messages: Vec<Message>,
-    pub signed_messages: Vec<Message>,
+    pub listened_messages: MessageQueue,
+    pub signed_messages: MessageQueue,
     pub balances_storage: BalancesStorage,
     pub daemon_storage: DaemonsStorage,
     pub pending_txs_storage: PendingTransactionsStorage,
+    pub scheduler: Scheduler,
+    pub quotas_storage: QuotasStorage,
+    pub dead_letters_storage: DeadLettersStorage,
+    pub keys_storage: KeysStorage,
+    pub icp_senders_storage: IcpSendersStorage,
+    // the circuit breaker of the signer and the writer, queued messages are kept
+    pub relay_paused: bool,
+    pub max_concurrency_per_chain: u64,
+    pub roles_storage: RolesStorage,
+    pub audit_log: AuditLog,
 }
 
 impl Storage {
@@ -52,9 +83,10 @@ impl Storage {
             return Ok(cached_public_key);
         }
 
-        let raw_public_key = get_public_key(Some(ic_cdk::id()), vec![], storage_get!(key))
-            .await
-            .map_err(StorageError::IcError)?;
+        let raw_public_key =
+            get_public_key(Some(ic_cdk::id()), vec![], KeysStorage::attestation_key())
+                .await
+                .map_err(StorageError::IcError)?;
 
         let public_key = hex::encode(raw_public_key);
 
@@ -62,4 +94,25 @@ impl Storage {
 
         Ok(public_key)
     }
+
+    /// Stops or starts the signer and the writer, returns whether the relay was
+    /// paused before.
+    pub fn set_relay_paused(paused: bool) -> bool {
+        STORAGE.with(|storage| {
+            let storage = &mut *storage.borrow_mut();
+
+            let before = storage.relay_paused;
+            storage.relay_paused = paused;
+
+            if paused {
+                storage.signer_job.stop(&mut storage.scheduler);
+                storage.writer_job.stop(&mut storage.scheduler);
+            } else {
+                storage.signer_job.start(&mut storage.scheduler);
+                storage.writer_job.start(&mut storage.scheduler);
+            }
+
+            before
+        })
+    }
 }
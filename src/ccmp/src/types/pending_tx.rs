@@ -1,15 +1,20 @@
-use std::str::FromStr;
+use std::{str::FromStr, time::Duration};
 
 use candid::{CandidType, Nat, Principal};
-use ethabi::ethereum_types::H256;
-use ic_web3_rs::{transports::ICHttp, Error as Web3Error, Web3};
+use ethabi::ethereum_types::{H256, U256};
+use ic_cdk::api::time;
+use ic_web3_rs::{types::BlockNumber, Error as Web3Error, Web3};
 use scopeguard::defer;
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    log,
+    log, storage_get,
     types::{daemons::Daemon, messages::Message},
-    utils::{transform_processors::call_options, u256_to_nat},
+    utils::{
+        evm_transport::EvmTransport,
+        transform_processors::{call_options, Transform},
+        u256_to_nat,
+    },
     STORAGE,
 };
 
@@ -17,17 +22,38 @@ use super::{
     balances::BalancesStorage,
     chains::{ChainType, ChainsStorage},
     daemons::DaemonsStorage,
-    evm_chains::EvmChainsStorage,
+    evm_chains::{EvmChain, EvmChainError, EvmChainsStorage},
+    solana_chains::{SolanaChainError, SolanaChainsStorage},
     HTTP_OUTCALL_CYCLES_COST, MINIMUM_CYCLES,
 };
 
-const EVM_CHECKER_HTTP_OUTCALLS_COUNT: u64 = 1;
+pub const EVM_CHECKER_HTTP_OUTCALLS_COUNT: u64 = 1;
+const SOLANA_CHECKER_HTTP_OUTCALLS_COUNT: u64 = 1;
 const CHECKER_JOB_EXECTUTION_COST: u64 = 2_000_000;
+const MAX_REBROADCASTS: u32 = 10;
+const MAX_REPLACEMENTS: usize = 10;
+const REPLACEMENT_INTERVAL: Duration = Duration::from_secs(10 * 60);
 
 #[derive(Debug, thiserror::Error)]
 pub enum PendingTransactionError {
     #[error("Web3 error: {0}")]
     Web3Error(#[from] Web3Error),
+    #[error("EVM chain error: {0}")]
+    EvmChainError(#[from] EvmChainError),
+    #[error("Solana chain error: {0}")]
+    SolanaChainError(#[from] SolanaChainError),
+    #[error("transaction failed: {0}")]
+    Failed(String),
+}
+
+/// Receiver contract call a transaction is signed for, kept to sign its
+/// replacements.
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone, Default)]
+pub struct ReceiverCall {
+    pub receiver: Vec<u8>,
+    pub method: String,
+    // the abi encoded parameters
+    pub params: Vec<u8>,
 }
 
 #[derive(CandidType, Deserialize, Serialize, Debug, Clone, Default)]
@@ -35,6 +61,14 @@ pub struct PendingTransaction {
     pub tx_hash: String,
     pub message: Message,
     pub gas_price: Nat,
+    // the signed transaction, broadcast again while it has no receipt
+    pub raw_tx: Option<Vec<u8>>,
+    pub broadcasts: u32,
+    pub nonce: Option<u64>,
+    pub call: Option<ReceiverCall>,
+    pub sent_at: u64,
+    // transactions sent earlier with the same nonce, one of them may be mined
+    pub replaced_tx_hashes: Vec<String>,
 }
 
 impl PendingTransaction {
@@ -43,38 +77,54 @@ impl PendingTransaction {
             tx_hash,
             message,
             gas_price,
+            raw_tx: None,
+            broadcasts: 0,
+            nonce: None,
+            call: None,
+            sent_at: 0,
+            replaced_tx_hashes: vec![],
         }
     }
 
-    pub async fn check(self) -> Result<bool, PendingTransactionError> {
+    pub async fn check(&mut self) -> Result<bool, PendingTransactionError> {
         let chain_metadata = ChainsStorage::get_chain_metadata(self.message.to_chain_id)
             .expect("Chain metadata not found");
 
         match chain_metadata.chain_type {
             ChainType::Evm => self.check_evm().await,
+            ChainType::Solana => self.check_solana().await,
             _ => panic!("Unsupported chain type"),
         }
     }
 
-    pub async fn check_evm(&self) -> Result<bool, PendingTransactionError> {
+    /// A transaction is checked until its nonce is taken, it is never given up
+    /// while its nonce may still be used by it or its replacements.
+    pub async fn check_evm(&mut self) -> Result<bool, PendingTransactionError> {
         let daemon = DaemonsStorage::get_daemon(self.message.daemon_id).expect("Daemon not found");
-        defer! {
-            Self::collect_checking_cycles(daemon.id, &daemon.creator)
-        }
         let evm_chain =
             EvmChainsStorage::get_chain(self.message.to_chain_id).expect("EVM chain not found");
+        let outcalls = EVM_CHECKER_HTTP_OUTCALLS_COUNT * evm_chain.provider.outcalls_per_request();
+        defer! {
+            Self::collect_checking_cycles(daemon.id, &daemon.creator, outcalls)
+        }
 
-        let w3 = Web3::new(ICHttp::new(&evm_chain.rpc, None).unwrap());
+        let w3 = Web3::new(evm_chain.transport(None, Some(daemon.creator)));
 
         let tx_hash = H256::from_str(&self.tx_hash).expect("invalid tx hash");
 
         let tx = w3
             .eth()
-            .transaction_receipt(tx_hash, call_options("transform".to_string()))
+            .transaction_receipt(tx_hash, call_options(Transform::Default))
             .await?;
 
         let Some(tx) = tx else {
-            return Ok(false);
+            let waited = Duration::from_nanos(time().saturating_sub(self.sent_at));
+            if self.nonce.is_none() || waited < REPLACEMENT_INTERVAL {
+                self.rebroadcast(&evm_chain, tx_hash).await;
+                return Ok(false);
+            }
+
+            return self.resolve_nonce(&w3, &evm_chain, &daemon).await;
         };
 
         let used_gas = u256_to_nat(tx.gas_used.expect("used gas not found"));
@@ -85,12 +135,165 @@ impl PendingTransaction {
             used_gas * self.gas_price.clone(),
         );
 
+        // the gas of a reverted transaction is paid all the same
+        if tx.status == Some(0.into()) {
+            return Err(PendingTransactionError::Failed("reverted".to_string()));
+        }
+
+        Ok(true)
+    }
+
+    /// Sends the transaction again, for the case its first broadcast did not
+    /// reach the chain. Failures are left to the next check.
+    async fn rebroadcast(&mut self, evm_chain: &EvmChain, tx_hash: H256) {
+        let Some(raw_tx) = self.raw_tx.clone() else {
+            return;
+        };
+        if self.broadcasts >= MAX_REBROADCASTS || self.is_paused() {
+            return;
+        }
+        self.broadcasts += 1;
+
+        let daemon = DaemonsStorage::get_daemon(self.message.daemon_id).expect("Daemon not found");
+        Self::collect_checking_cycles(
+            daemon.id,
+            &daemon.creator,
+            evm_chain.provider.outcalls_per_request(),
+        );
+
+        if let Err(err) = evm_chain
+            .send_raw_transaction(raw_tx, tx_hash, daemon.creator)
+            .await
+        {
+            log!(
+                "[CHECKER] rebroadcast failed, tx hash: 0x{}, error: {}",
+                self.tx_hash,
+                err
+            );
+        }
+    }
+
+    /// Handles a transaction without a receipt long after it was sent. A taken
+    /// nonce means one of the transactions sent with it was mined, otherwise
+    /// the transaction is replaced by one paying a higher gas price.
+    async fn resolve_nonce(
+        &mut self,
+        w3: &Web3<EvmTransport>,
+        evm_chain: &EvmChain,
+        daemon: &Daemon,
+    ) -> Result<bool, PendingTransactionError> {
+        let nonce = self.nonce.expect("nonce not found");
+        let outcalls = evm_chain.provider.outcalls_per_request();
+        Self::collect_checking_cycles(daemon.id, &daemon.creator, outcalls);
+
+        let tx_count = w3
+            .eth()
+            .transaction_count(
+                EvmChain::sender_address(&daemon.creator),
+                Some(BlockNumber::Latest),
+                call_options(Transform::Default),
+            )
+            .await?;
+
+        if tx_count <= U256::from(nonce) {
+            if self.is_paused() {
+                return Ok(false);
+            }
+            if self.replaced_tx_hashes.len() >= MAX_REPLACEMENTS {
+                log!(
+                    "[CHECKER] transaction is still not mined, nonce: {}, tx hash: 0x{}",
+                    nonce,
+                    self.tx_hash
+                );
+                return Ok(false);
+            }
+
+            EvmChain::collect_writing_cycles(daemon.id, daemon.creator, outcalls);
+            evm_chain.replace_transaction(self, daemon.creator).await?;
+
+            return Ok(false);
+        }
+
+        // the mined one is charged with the gas price it was sent with
+        let tx_hashes = self
+            .replaced_tx_hashes
+            .iter()
+            .chain(std::iter::once(&self.tx_hash))
+            .rev();
+        for tx_hash in tx_hashes {
+            Self::collect_checking_cycles(daemon.id, &daemon.creator, outcalls);
+            let receipt = w3
+                .eth()
+                .transaction_receipt(
+                    H256::from_str(tx_hash).expect("invalid tx hash"),
+                    call_options(Transform::Default),
+                )
+                .await?;
+
+            if let Some(receipt) = receipt {
+                let used_gas = u256_to_nat(receipt.gas_used.expect("used gas not found"));
+                let gas_price = receipt
+                    .effective_gas_price
+                    .map_or(self.gas_price.clone(), u256_to_nat);
+
+                BalancesStorage::reduce_tokens_on_chain(
+                    &daemon.creator,
+                    self.message.to_chain_id,
+                    used_gas * gas_price,
+                );
+
+                if receipt.status == Some(0.into()) {
+                    return Err(PendingTransactionError::Failed("reverted".to_string()));
+                }
+
+                return Ok(true);
+            }
+        }
+
+        log!(
+            "[CHECKER] nonce taken without a known receipt, nonce: {}, tx hash: 0x{}",
+            nonce,
+            self.tx_hash
+        );
+
         Ok(true)
     }
 
-    pub fn collect_checking_cycles(daemon_id: u64, principal: &Principal) {
+    fn is_paused(&self) -> bool {
+        storage_get!(relay_paused)
+            || ChainsStorage::get_chain_metadata(self.message.to_chain_id)
+                .map_or(false, |chain_metadata| chain_metadata.pause.outbound)
+    }
+
+    /// Solana fees are paid by the fee payer of the daemon creator, so only the
+    /// status of the transaction is checked, a failed one is given to the dead
+    /// letters.
+    pub async fn check_solana(&self) -> Result<bool, PendingTransactionError> {
+        let daemon = DaemonsStorage::get_daemon(self.message.daemon_id).expect("Daemon not found");
+        defer! {
+            Self::collect_checking_cycles(
+                daemon.id,
+                &daemon.creator,
+                SOLANA_CHECKER_HTTP_OUTCALLS_COUNT,
+            )
+        }
+        let solana_chain = SolanaChainsStorage::get_chain(self.message.to_chain_id)
+            .expect("Solana chain not found");
+
+        let Some(status) = solana_chain.get_signature_status(&self.tx_hash).await? else {
+            return Ok(false);
+        };
+
+        if let Some(err) = status.err {
+            return Err(PendingTransactionError::Failed(err.to_string()));
+        }
+
+        Ok(status.confirmation_status.as_deref() == Some("finalized"))
+    }
+
+    pub fn collect_checking_cycles(daemon_id: u64, principal: &Principal, outcalls: u64) {
         let mut used_cycles = 0;
-        used_cycles += HTTP_OUTCALL_CYCLES_COST * EVM_CHECKER_HTTP_OUTCALLS_COUNT;
+        used_cycles += HTTP_OUTCALL_CYCLES_COST * outcalls;
         used_cycles += CHECKER_JOB_EXECTUTION_COST;
 
         BalancesStorage::reduce_cycles(principal, Nat::from(used_cycles));
@@ -0,0 +1,91 @@
+use std::collections::HashMap;
+
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+
+use crate::STORAGE;
+
+const DEFAULT_WEIGHT: u64 = 1;
+const DEFAULT_MAX_DAEMONS: u64 = 10;
+const DEFAULT_MAX_QUEUED_MESSAGES: u64 = 1_000;
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone)]
+pub struct PrincipalQuota {
+    pub weight: u64,
+    pub max_daemons: u64,
+    pub max_queued_messages: u64,
+}
+
+impl Default for PrincipalQuota {
+    fn default() -> Self {
+        Self {
+            weight: DEFAULT_WEIGHT,
+            max_daemons: DEFAULT_MAX_DAEMONS,
+            max_queued_messages: DEFAULT_MAX_QUEUED_MESSAGES,
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone, Default)]
+pub struct QuotasStorage(pub HashMap<Principal, PrincipalQuota>);
+
+impl QuotasStorage {
+    pub fn get(&self, principal: &Principal) -> PrincipalQuota {
+        self.0.get(principal).cloned().unwrap_or_default()
+    }
+
+    pub fn weight(&self, principal: &Principal) -> u64 {
+        self.0
+            .get(principal)
+            .map_or(DEFAULT_WEIGHT, |quota| quota.weight)
+    }
+
+    pub fn get_quota(principal: &Principal) -> PrincipalQuota {
+        STORAGE.with(|storage| storage.borrow().quotas_storage.get(principal))
+    }
+
+    pub fn set_quota(principal: Principal, quota: PrincipalQuota) {
+        STORAGE.with(|storage| {
+            storage
+                .borrow_mut()
+                .quotas_storage
+                .0
+                .insert(principal, quota);
+        })
+    }
+
+    pub fn can_add_daemon(principal: &Principal) -> bool {
+        STORAGE.with(|storage| {
+            let storage = storage.borrow();
+
+            let daemons_count = storage
+                .daemon_storage
+                .daemons
+                .values()
+                .filter(|daemon| daemon.creator == *principal)
+                .count() as u64;
+
+            daemons_count < storage.quotas_storage.get(principal).max_daemons
+        })
+    }
+
+    pub fn can_queue_messages(principal: &Principal) -> bool {
+        Self::queue_room(principal) > 0
+    }
+
+    /// Number of messages the principal may still have queued.
+    pub fn queue_room(principal: &Principal) -> u64 {
+        STORAGE.with(|storage| {
+            let storage = storage.borrow();
+
+            let queued_messages = storage.listened_messages.len_of(principal)
+                + storage.signed_messages.len_of(principal);
+
+            storage
+                .quotas_storage
+                .get(principal)
+                .max_queued_messages
+                .saturating_sub(queued_messages as u64)
+        })
+    }
+}
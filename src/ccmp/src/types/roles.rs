@@ -0,0 +1,98 @@
+use std::collections::{HashMap, HashSet};
+
+use candid::{CandidType, Principal};
+use ic_cdk::api::is_controller;
+use serde::{Deserialize, Serialize};
+
+use crate::STORAGE;
+
+/// Controllers hold every role and an admin holds every other role, the rest
+/// are granted separately.
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Role {
+    // grants and revokes roles, changes the config and rotates keys
+    Admin,
+    // adds, updates and removes chains
+    ChainOperator,
+    // sets the quotas of principals
+    FeeManager,
+    // reads the config, the scheduler and the role holders
+    Auditor,
+    // pauses and resumes chains and the relay
+    Pauser,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone, Default)]
+pub struct RolesStorage(pub HashMap<Role, HashSet<Principal>>);
+
+impl RolesStorage {
+    pub fn has_role(principal: &Principal, role: Role) -> bool {
+        if is_controller(principal) {
+            return true;
+        }
+
+        STORAGE.with(|storage| {
+            let roles = &storage.borrow().roles_storage.0;
+
+            [Role::Admin, role].iter().any(|role| {
+                roles
+                    .get(role)
+                    .map_or(false, |holders| holders.contains(principal))
+            })
+        })
+    }
+
+    /// Returns false if the principal already holds the role.
+    pub fn grant(role: Role, principal: Principal) -> bool {
+        STORAGE.with(|storage| {
+            storage
+                .borrow_mut()
+                .roles_storage
+                .0
+                .entry(role)
+                .or_default()
+                .insert(principal)
+        })
+    }
+
+    /// Returns false if the principal does not hold the role.
+    pub fn revoke(role: Role, principal: &Principal) -> bool {
+        STORAGE.with(|storage| {
+            let roles = &mut storage.borrow_mut().roles_storage.0;
+
+            let Some(holders) = roles.get_mut(&role) else {
+                return false;
+            };
+            let is_revoked = holders.remove(principal);
+            if holders.is_empty() {
+                roles.remove(&role);
+            }
+
+            is_revoked
+        })
+    }
+
+    pub fn holders(role: Role) -> Vec<Principal> {
+        STORAGE.with(|storage| {
+            storage
+                .borrow()
+                .roles_storage
+                .0
+                .get(&role)
+                .map_or(vec![], |holders| holders.iter().cloned().collect())
+        })
+    }
+
+    /// The granted roles, controllers are not listed.
+    pub fn get_role_holders() -> HashMap<Role, Vec<Principal>> {
+        STORAGE.with(|storage| {
+            storage
+                .borrow()
+                .roles_storage
+                .0
+                .iter()
+                .map(|(role, holders)| (*role, holders.iter().cloned().collect()))
+                .collect()
+        })
+    }
+}
@@ -0,0 +1,181 @@
+use std::time::Duration;
+
+use candid::CandidType;
+use ic_cdk::api::{instruction_counter, time};
+use ic_cdk_timers::set_timer_interval;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    bitcoin_chains::BITCOIN_DAEMON_HTTP_OUTCALLS_COUNT,
+    daemons::{Daemon, DAEMON_HTTP_OUTCALLS_COUNT},
+    dead_letters::DeadLettersStorage,
+    evm_chains::EVM_WRITER_HTTP_OUTCALLS_COUNT,
+    job::JobType,
+    pending_tx::EVM_CHECKER_HTTP_OUTCALLS_COUNT,
+    solana_chains::SOLANA_DAEMON_HTTP_OUTCALLS_COUNT,
+    Storage,
+};
+use crate::{
+    jobs::{checker, signer, writer},
+    log, STORAGE,
+};
+
+const TICK_INTERVAL_SECS: u64 = 1;
+// leaves enough room below the 5B update message limit for the spawned tasks
+// which execute their synchronous part inside the tick
+const TICK_INSTRUCTIONS_BUDGET: u64 = 2_000_000_000;
+const TICK_OUTCALLS_BUDGET: u64 = 50;
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Task {
+    Daemon(u64),
+    Job(JobType),
+    DeadLetter(u64),
+}
+
+impl Task {
+    /// The outcalls of the task, jobs make at most one request per item of
+    /// their current batch.
+    pub fn outcalls(&self, storage: &Storage) -> u64 {
+        match self {
+            // the chain of the daemon is not looked up, the most expensive one is assumed
+            Task::Daemon(_) => DAEMON_HTTP_OUTCALLS_COUNT
+                .max(SOLANA_DAEMON_HTTP_OUTCALLS_COUNT)
+                .max(BITCOIN_DAEMON_HTTP_OUTCALLS_COUNT),
+            Task::Job(JobType::Writer) => {
+                storage.writer_job.batch_size() * EVM_WRITER_HTTP_OUTCALLS_COUNT
+            }
+            Task::Job(JobType::Checker) => {
+                storage.checker_job.batch_size() * EVM_CHECKER_HTTP_OUTCALLS_COUNT
+            }
+            Task::Job(_) | Task::DeadLetter(_) => 0,
+        }
+    }
+
+    fn execute(self) {
+        match self {
+            Task::Daemon(id) => Daemon::run(id),
+            Task::Job(JobType::Signer) => signer::run(),
+            Task::Job(JobType::Writer) => writer::run(),
+            Task::Job(JobType::Checker) => checker::run(),
+            Task::Job(JobType::Unknown) => panic!("Unknown job type"),
+            Task::DeadLetter(id) => {
+                if let Err(err) = DeadLettersStorage::retry(id) {
+                    log!("[DEAD LETTERS] retry error: {}, id: {}", err, id);
+                }
+            }
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone)]
+pub struct ScheduledTask {
+    pub due_at: u64,
+    pub task: Task,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone, Default)]
+pub struct TickStats {
+    pub started_at: u64,
+    pub executed_tasks: u64,
+    pub deferred_tasks: u64,
+    pub used_instructions: u64,
+    pub used_outcalls: u64,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone, Default)]
+pub struct Scheduler {
+    // sorted by `due_at`, tasks with the same due time keep their insertion order
+    pub queue: Vec<ScheduledTask>,
+    pub last_tick: TickStats,
+}
+
+impl Scheduler {
+    /// Arms the global tick timer, must be called on every init and upgrade
+    /// since timers do not survive upgrades while the queue does.
+    pub fn start() {
+        set_timer_interval(Duration::from_secs(TICK_INTERVAL_SECS), tick);
+    }
+
+    pub fn schedule(&mut self, task: Task, delay: Duration) {
+        self.unschedule(task);
+
+        let due_at = time() + delay.as_nanos() as u64;
+        let position = self.queue.partition_point(|t| t.due_at <= due_at);
+
+        self.queue.insert(position, ScheduledTask { due_at, task });
+    }
+
+    pub fn unschedule(&mut self, task: Task) {
+        self.queue.retain(|t| t.task != task);
+    }
+
+    pub fn is_scheduled(&self, task: Task) -> bool {
+        self.queue.iter().any(|t| t.task == task)
+    }
+
+    /// Returns the next due task with its outcalls.
+    fn pop_due(
+        storage: &mut Storage,
+        now: u64,
+        used_outcalls: u64,
+        force: bool,
+    ) -> Option<(Task, u64)> {
+        let next = storage.scheduler.queue.first()?;
+        if next.due_at > now {
+            return None;
+        }
+
+        let outcalls = next.task.outcalls(storage);
+        if !force && used_outcalls + outcalls > TICK_OUTCALLS_BUDGET {
+            return None;
+        }
+
+        Some((storage.scheduler.queue.remove(0).task, outcalls))
+    }
+}
+
+fn tick() {
+    let now = time();
+    let mut stats = TickStats {
+        started_at: now,
+        ..Default::default()
+    };
+
+    while instruction_counter() < TICK_INSTRUCTIONS_BUDGET {
+        // at least one task is executed per tick, so a task that exceeds the
+        // whole outcalls budget on its own can not stall the queue
+        let task = STORAGE.with(|storage| {
+            Scheduler::pop_due(
+                &mut storage.borrow_mut(),
+                now,
+                stats.used_outcalls,
+                stats.executed_tasks == 0,
+            )
+        });
+
+        let Some((task, outcalls)) = task else {
+            break;
+        };
+
+        stats.used_outcalls += outcalls;
+        stats.executed_tasks += 1;
+
+        task.execute();
+    }
+
+    stats.used_instructions = instruction_counter();
+
+    STORAGE.with(|storage| {
+        let mut storage = storage.borrow_mut();
+
+        stats.deferred_tasks = storage
+            .scheduler
+            .queue
+            .iter()
+            .take_while(|t| t.due_at <= now)
+            .count() as u64;
+
+        storage.scheduler.last_tick = stats;
+    });
+}
@@ -0,0 +1,733 @@
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use candid::{CandidType, Nat, Principal};
+use scopeguard::defer;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use thiserror::Error;
+
+use super::{
+    chains::{Chain, ChainMetadata, ChainType},
+    daemons::{Daemon, DaemonsStorage},
+    keys::{DerivedKey, KeysError, KeysStorage},
+    messages::{Message, MessageError},
+    pending_tx::{PendingTransaction, PendingTransactionsStorage},
+    HTTP_OUTCALL_CYCLES_COST, MINIMUM_CYCLES, SCHNORR_SIGN_CYCLES,
+};
+use crate::{
+    log, storage_get,
+    types::balances::BalancesStorage,
+    utils::{
+        base58,
+        json_rpc::{self, JsonRpcError},
+        signing::{self, SigningAlgorithm, SigningError},
+        UtilsError,
+    },
+    STORAGE,
+};
+
+pub const SOLANA_WRITER_HTTP_OUTCALLS_COUNT: u64 = 2;
+/// Transactions of the CCMP program fetched by a daemon in one round.
+pub const SOLANA_TXS_PER_ROUND: usize = 5;
+/// Pages of signatures a daemon reads back in one round to reach its cursor.
+pub const SOLANA_SIGNATURE_PAGES_PER_ROUND: u64 = 3;
+pub const SOLANA_DAEMON_HTTP_OUTCALLS_COUNT: u64 =
+    SOLANA_SIGNATURE_PAGES_PER_ROUND + SOLANA_TXS_PER_ROUND as u64;
+pub const SOLANA_SIGNATURES_LIMIT: usize = 1000;
+const WRITER_JOB_EXECTUTION_COST: u64 = 2_000_000;
+const SOLANA_KEY_LENGTH: usize = 32;
+const ED25519_SIGNATURE_LENGTH: usize = 64;
+const FINALIZED: &str = "finalized";
+
+const ED25519_PROGRAM_ID: &str = "Ed25519SigVerify111111111111111111111111111";
+const INSTRUCTIONS_SYSVAR_ID: &str = "Sysvar1nstructions1111111111111111111111111";
+/// `Program data:` payloads of the CCMP program start with it.
+const CCMP_EVENT_DISCRIMINATOR: &[u8; 8] = b"ccmp_msg";
+const PROGRAM_DATA_LOG_PREFIX: &str = "Program data: ";
+
+#[derive(Error, Debug)]
+pub enum SolanaChainError {
+    #[error("json rpc error: {0}")]
+    JsonRpc(#[from] JsonRpcError),
+    #[error("utils error: {0}")]
+    Utils(#[from] UtilsError),
+    #[error("signing error: {0}")]
+    Signing(#[from] SigningError),
+    #[error("keys error: {0}")]
+    Keys(#[from] KeysError),
+    #[error("message error: {0}")]
+    Message(Box<MessageError>),
+    #[error("solana chain not found")]
+    SolanaChainNotFound,
+    #[error("invalid public key length: {0}")]
+    InvalidKeyLength(usize),
+    #[error("batched delivery is not supported by solana chains")]
+    BatchNotSupported,
+    #[error("message is not signed")]
+    MissingSignature,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Default, Clone)]
+pub struct SolanaChain {
+    pub name: String,
+    pub rpc: String,
+    pub genesis_hash: String,
+}
+
+/// A `CcmpMessage` event of the CCMP program, borsh encoded after the discriminator:
+/// `index: u64, to_chain_id: u64, sender: [u8; 32], message: Vec<u8>, receiver: Vec<u8>`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SolanaCcmpEvent {
+    pub index: u64,
+    pub to_chain_id: u64,
+    pub sender: Vec<u8>,
+    pub message: Vec<u8>,
+    pub receiver: Vec<u8>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct SignatureInfo {
+    pub signature: String,
+    pub err: Option<Value>,
+}
+
+#[derive(Deserialize, Debug)]
+struct LatestBlockhash {
+    value: BlockhashValue,
+}
+
+#[derive(Deserialize, Debug)]
+struct BlockhashValue {
+    blockhash: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct TransactionResponse {
+    meta: Option<TransactionMeta>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct TransactionMeta {
+    log_messages: Option<Vec<String>>,
+}
+
+#[derive(Deserialize, Debug)]
+struct SignatureStatuses {
+    value: Vec<Option<SignatureStatus>>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SignatureStatus {
+    pub err: Option<Value>,
+    pub confirmation_status: Option<String>,
+}
+
+impl SolanaChain {
+    pub async fn new(name: String, rpc: String) -> Result<Self, SolanaChainError> {
+        let genesis_hash: String = json_rpc::request(&rpc, "getGenesisHash", json!([])).await?;
+
+        Ok(Self {
+            name,
+            rpc,
+            genesis_hash,
+        })
+    }
+
+    /// Returns a page of at most `SOLANA_SIGNATURES_LIMIT` signatures of the
+    /// program's transactions older than `before` and newer than `until`, newest
+    /// first. A shorter page reaches `until`.
+    pub async fn get_signatures_for_address(
+        &self,
+        address: &str,
+        before: Option<String>,
+        until: Option<String>,
+    ) -> Result<Vec<SignatureInfo>, SolanaChainError> {
+        let mut config = json!({
+            "commitment": FINALIZED,
+            "limit": SOLANA_SIGNATURES_LIMIT,
+        });
+        if let Some(before) = before {
+            config["before"] = json!(before);
+        }
+        if let Some(until) = until {
+            config["until"] = json!(until);
+        }
+
+        Ok(json_rpc::request(
+            &self.rpc,
+            "getSignaturesForAddress",
+            json!([address, config]),
+        )
+        .await?)
+    }
+
+    pub async fn get_transaction_logs(
+        &self,
+        signature: &str,
+    ) -> Result<Vec<String>, SolanaChainError> {
+        let transaction: Option<TransactionResponse> = json_rpc::request(
+            &self.rpc,
+            "getTransaction",
+            json!([signature, {
+                "encoding": "json",
+                "commitment": FINALIZED,
+                "maxSupportedTransactionVersion": 0,
+            }]),
+        )
+        .await?;
+
+        Ok(transaction
+            .and_then(|transaction| transaction.meta)
+            .and_then(|meta| meta.log_messages)
+            .unwrap_or_default())
+    }
+
+    pub async fn get_signature_status(
+        &self,
+        signature: &str,
+    ) -> Result<Option<SignatureStatus>, SolanaChainError> {
+        let statuses: SignatureStatuses = json_rpc::request(
+            &self.rpc,
+            "getSignatureStatuses",
+            json!([[signature], { "searchTransactionHistory": true }]),
+        )
+        .await?;
+
+        Ok(statuses.value.into_iter().next().flatten())
+    }
+
+    /// The fee payer of the principal's transactions, funded by the principal.
+    pub fn fee_payer_key(principal: &Principal) -> DerivedKey {
+        DerivedKey {
+            key_name: storage_get!(key),
+            derivation_path: vec![principal.as_slice().to_vec()],
+            algorithm: SigningAlgorithm::SchnorrEd25519,
+        }
+    }
+
+    pub async fn get_fee_payer(principal: &Principal) -> Result<String, SolanaChainError> {
+        let public_key = KeysStorage::get_public_key(Self::fee_payer_key(principal)).await?;
+
+        Ok(base58::encode(&public_key))
+    }
+
+    pub fn collect_writing_cycles(id: u64, principal: Principal) {
+        let mut used_cycles = 0;
+        used_cycles += HTTP_OUTCALL_CYCLES_COST * SOLANA_WRITER_HTTP_OUTCALLS_COUNT;
+        used_cycles += WRITER_JOB_EXECTUTION_COST;
+        used_cycles += SCHNORR_SIGN_CYCLES;
+
+        BalancesStorage::reduce_cycles(&principal, Nat::from(used_cycles));
+
+        let balance = BalancesStorage::get_balance(&principal).expect("Balance not found");
+        if balance.cycles < MINIMUM_CYCLES {
+            log!("[DAEMONS] insufficient cycles, principal: {}", principal);
+            Daemon::stop(id);
+        }
+    }
+}
+
+#[async_trait]
+impl Chain for SolanaChain {
+    type Error = SolanaChainError;
+
+    async fn write(&self, message: Message) -> Result<(), Self::Error> {
+        if message.receiver.len() != SOLANA_KEY_LENGTH {
+            return Ok(());
+        }
+
+        let daemon = DaemonsStorage::get_daemon(message.daemon_id).expect("daemon not found");
+        defer! {
+            Self::collect_writing_cycles(daemon.id, daemon.creator);
+        };
+
+        let signature = message
+            .signature
+            .clone()
+            .ok_or(SolanaChainError::MissingSignature)?;
+        let digest = message
+            .digest()
+            .map_err(|e| SolanaChainError::Message(Box::new(e)))?;
+        let attestation_key = KeysStorage::get_public_key(message.delivery_key()).await?;
+
+        let payer_key = Self::fee_payer_key(&daemon.creator);
+        let payer = KeysStorage::get_public_key(payer_key.clone()).await?;
+
+        let LatestBlockhash { value } = json_rpc::request(
+            &self.rpc,
+            "getLatestBlockhash",
+            json!([{ "commitment": FINALIZED }]),
+        )
+        .await?;
+
+        let transaction = CcmpTransaction {
+            payer: to_key(&payer)?,
+            receiver: to_key(&message.receiver)?,
+            recent_blockhash: to_key(&base58::decode(&value.blockhash)?)?,
+            attestation_key: to_key(&attestation_key)?,
+            signature,
+            digest,
+            data: receiver_instruction_data(&message),
+        };
+
+        let transaction_message = transaction.serialize_message()?;
+        let payer_signature = signing::sign(&payer_key, transaction_message.clone()).await?;
+
+        let mut raw_transaction = compact_u16(1);
+        raw_transaction.extend(&payer_signature);
+        raw_transaction.extend(transaction_message);
+
+        let tx_signature: String = json_rpc::request(
+            &self.rpc,
+            "sendTransaction",
+            json!([base64::encode(raw_transaction), {
+                "encoding": "base64",
+                "preflightCommitment": FINALIZED,
+            }]),
+        )
+        .await?;
+
+        log!(
+            "[WRITER] message sent to solana chain, id: {}, tx signature: {}",
+            message.to_chain_id,
+            tx_signature
+        );
+
+        PendingTransactionsStorage::add(PendingTransaction::new(
+            tx_signature,
+            message,
+            Nat::from(0u64),
+        ));
+        STORAGE.with(|storage| {
+            let storage = &mut *storage.borrow_mut();
+
+            storage.checker_job.start(&mut storage.scheduler);
+        });
+
+        Ok(())
+    }
+
+    async fn write_batch(&self, _messages: Vec<Message>) -> Result<(), Self::Error> {
+        Err(SolanaChainError::BatchNotSupported)
+    }
+}
+
+/// A legacy transaction of two instructions: the Ed25519 program verifies the
+/// attestation signature, the receiver program gets the message and reads the
+/// verified signature through the instructions sysvar.
+struct CcmpTransaction {
+    payer: [u8; 32],
+    receiver: [u8; 32],
+    recent_blockhash: [u8; 32],
+    attestation_key: [u8; 32],
+    signature: Vec<u8>,
+    digest: Vec<u8>,
+    data: Vec<u8>,
+}
+
+impl CcmpTransaction {
+    fn serialize_message(&self) -> Result<Vec<u8>, SolanaChainError> {
+        let ed25519_program = to_key(&base58::decode(ED25519_PROGRAM_ID)?)?;
+        let instructions_sysvar = to_key(&base58::decode(INSTRUCTIONS_SYSVAR_ID)?)?;
+
+        // the payer is the only signer, the other accounts are read-only
+        let mut message = vec![1, 0, 3];
+
+        message.extend(compact_u16(4));
+        for key in [
+            &self.payer,
+            &ed25519_program,
+            &self.receiver,
+            &instructions_sysvar,
+        ] {
+            message.extend(key);
+        }
+
+        message.extend(&self.recent_blockhash);
+
+        message.extend(compact_u16(2));
+
+        message.push(1);
+        message.extend(compact_u16(0));
+        let verify_data = self.ed25519_instruction_data();
+        message.extend(compact_u16(verify_data.len()));
+        message.extend(verify_data);
+
+        message.push(2);
+        message.extend(compact_u16(2));
+        message.extend([0, 3]);
+        message.extend(compact_u16(self.data.len()));
+        message.extend(&self.data);
+
+        Ok(message)
+    }
+
+    fn ed25519_instruction_data(&self) -> Vec<u8> {
+        const HEADER_LENGTH: u16 = 16;
+        const CURRENT_INSTRUCTION: u16 = u16::MAX;
+
+        let public_key_offset = HEADER_LENGTH;
+        let signature_offset = public_key_offset + SOLANA_KEY_LENGTH as u16;
+        let message_offset = signature_offset + ED25519_SIGNATURE_LENGTH as u16;
+
+        let mut data = vec![1, 0];
+        for value in [
+            signature_offset,
+            CURRENT_INSTRUCTION,
+            public_key_offset,
+            CURRENT_INSTRUCTION,
+            message_offset,
+            self.digest.len() as u16,
+            CURRENT_INSTRUCTION,
+        ] {
+            data.extend(value.to_le_bytes());
+        }
+
+        data.extend(&self.attestation_key);
+        data.extend(&self.signature);
+        data.extend(&self.digest);
+
+        data
+    }
+}
+
+/// The receiver program's instruction, borsh encoded:
+/// `index: u64, from_chain_id: u64, to_chain_id: u64, sender: Vec<u8>, message: Vec<u8>`.
+fn receiver_instruction_data(message: &Message) -> Vec<u8> {
+    let mut data = vec![];
+    data.extend(message.index.to_le_bytes());
+    data.extend(message.from_chain_id.to_le_bytes());
+    data.extend(message.to_chain_id.to_le_bytes());
+    for bytes in [&message.sender, &message.message] {
+        data.extend((bytes.len() as u32).to_le_bytes());
+        data.extend(bytes);
+    }
+
+    data
+}
+
+fn to_key(bytes: &[u8]) -> Result<[u8; 32], SolanaChainError> {
+    bytes
+        .try_into()
+        .map_err(|_| SolanaChainError::InvalidKeyLength(bytes.len()))
+}
+
+fn compact_u16(value: usize) -> Vec<u8> {
+    let mut value = value as u16;
+    let mut bytes = vec![];
+    loop {
+        let mut byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            bytes.push(byte);
+            return bytes;
+        }
+
+        byte |= 0x80;
+        bytes.push(byte);
+    }
+}
+
+/// Extracts the events emitted by the program itself, logs of the programs it
+/// invokes or is invoked by are skipped, so another program can not forge them.
+pub fn parse_ccmp_events(logs: &[String], program_id: &str) -> Vec<SolanaCcmpEvent> {
+    let invoke_prefix = format!("Program {program_id} invoke");
+    let mut invocations: Vec<bool> = vec![];
+    let mut events = vec![];
+
+    for log in logs {
+        if log.starts_with("Program ") && log.contains(" invoke [") {
+            invocations.push(log.starts_with(&invoke_prefix));
+        } else if log.starts_with("Program ")
+            && (log.ends_with(" success") || log.contains(" failed"))
+        {
+            invocations.pop();
+        } else if let Some(data) = log.strip_prefix(PROGRAM_DATA_LOG_PREFIX) {
+            if invocations.last() != Some(&true) {
+                continue;
+            }
+
+            let Ok(data) = base64::decode(data) else {
+                continue;
+            };
+
+            if let Some(event) = SolanaCcmpEvent::parse(&data) {
+                events.push(event);
+            }
+        }
+    }
+
+    events
+}
+
+impl SolanaCcmpEvent {
+    fn parse(data: &[u8]) -> Option<Self> {
+        let mut data = data.strip_prefix(CCMP_EVENT_DISCRIMINATOR.as_slice())?;
+
+        let index = read_u64(&mut data)?;
+        let to_chain_id = read_u64(&mut data)?;
+        let sender = read_bytes(&mut data, SOLANA_KEY_LENGTH)?;
+        let message_length = read_u32(&mut data)? as usize;
+        let message = read_bytes(&mut data, message_length)?;
+        let receiver_length = read_u32(&mut data)? as usize;
+        let receiver = read_bytes(&mut data, receiver_length)?;
+
+        Some(Self {
+            index,
+            to_chain_id,
+            sender,
+            message,
+            receiver,
+        })
+    }
+}
+
+fn read_bytes(data: &mut &[u8], length: usize) -> Option<Vec<u8>> {
+    if data.len() < length {
+        return None;
+    }
+
+    let (bytes, rest) = data.split_at(length);
+    *data = rest;
+
+    Some(bytes.to_vec())
+}
+
+fn read_u64(data: &mut &[u8]) -> Option<u64> {
+    Some(u64::from_le_bytes(read_bytes(data, 8)?.try_into().ok()?))
+}
+
+fn read_u32(data: &mut &[u8]) -> Option<u32> {
+    Some(u32::from_le_bytes(read_bytes(data, 4)?.try_into().ok()?))
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Default, Clone)]
+pub struct SolanaChainsStorage(pub HashMap<u64, SolanaChain>);
+
+impl SolanaChainsStorage {
+    pub fn add(solana_chain: SolanaChain) -> u64 {
+        STORAGE.with(|storage| {
+            let mut storage = storage.borrow_mut();
+
+            let index = storage.chains_storage.chains_count;
+            storage.chains_storage.chains_metadata.insert(
+                index,
+                ChainMetadata::new(
+                    index,
+                    solana_chain.name.clone(),
+                    ChainType::Solana,
+                    Some(solana_chain.genesis_hash.clone()),
+                ),
+            );
+            storage
+                .chains_storage
+                .solana_chains_storage
+                .0
+                .insert(index, solana_chain);
+            storage.chains_storage.chains_count += 1;
+
+            index
+        })
+    }
+
+    pub fn get_chain(id: u64) -> Option<SolanaChain> {
+        STORAGE.with(|storage| {
+            storage
+                .borrow()
+                .chains_storage
+                .solana_chains_storage
+                .0
+                .get(&id)
+                .cloned()
+        })
+    }
+
+    pub fn update_rpc(id: u64, rpc: String) -> Result<(), SolanaChainError> {
+        STORAGE.with(|storage| {
+            let mut storage = storage.borrow_mut();
+
+            let chain = storage
+                .chains_storage
+                .solana_chains_storage
+                .0
+                .get_mut(&id)
+                .ok_or(SolanaChainError::SolanaChainNotFound)?;
+            chain.rpc = rpc;
+
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROGRAM_ID: &str = "CcmpProgram11111111111111111111111111111111";
+
+    fn event_data(index: u64, message: &[u8]) -> String {
+        let mut data = CCMP_EVENT_DISCRIMINATOR.to_vec();
+        data.extend(index.to_le_bytes());
+        data.extend(2u64.to_le_bytes());
+        data.extend([7; 32]);
+        data.extend((message.len() as u32).to_le_bytes());
+        data.extend(message);
+        data.extend(20u32.to_le_bytes());
+        data.extend([9; 20]);
+
+        format!("{PROGRAM_DATA_LOG_PREFIX}{}", base64::encode(data))
+    }
+
+    fn event(index: u64, message: &[u8]) -> SolanaCcmpEvent {
+        SolanaCcmpEvent {
+            index,
+            to_chain_id: 2,
+            sender: vec![7; 32],
+            message: message.to_vec(),
+            receiver: vec![9; 20],
+        }
+    }
+
+    fn logs(lines: &[&str]) -> Vec<String> {
+        lines.iter().map(|line| line.to_string()).collect()
+    }
+
+    #[test]
+    fn compact_u16_matches_the_solana_encoding() {
+        let vectors: [(usize, &[u8]); 6] = [
+            (0, &[0x00]),
+            (0x7f, &[0x7f]),
+            (0x80, &[0x80, 0x01]),
+            (0x3fff, &[0xff, 0x7f]),
+            (0x4000, &[0x80, 0x80, 0x01]),
+            (0xffff, &[0xff, 0xff, 0x03]),
+        ];
+
+        for (value, encoded) in vectors {
+            assert_eq!(compact_u16(value), encoded, "{value}");
+        }
+    }
+
+    #[test]
+    fn serializes_the_transaction_message() {
+        let transaction = CcmpTransaction {
+            payer: [1; 32],
+            receiver: [2; 32],
+            recent_blockhash: [3; 32],
+            attestation_key: [4; 32],
+            signature: vec![5; 64],
+            digest: vec![6; 32],
+            data: vec![7, 8],
+        };
+
+        let ed25519_program = base58::decode(ED25519_PROGRAM_ID).unwrap();
+        let instructions_sysvar = base58::decode(INSTRUCTIONS_SYSVAR_ID).unwrap();
+
+        let mut expected = vec![1, 0, 3, 4];
+        expected.extend([1; 32]);
+        expected.extend(&ed25519_program);
+        expected.extend([2; 32]);
+        expected.extend(&instructions_sysvar);
+        expected.extend([3; 32]);
+        expected.push(2);
+        // the ed25519 program without accounts, 16 bytes of offsets and 128 of data
+        expected.extend([1, 0, 0x90, 0x01]);
+        expected.extend([1, 0]);
+        expected.extend([
+            48, 0, 0xff, 0xff, 16, 0, 0xff, 0xff, 112, 0, 32, 0, 0xff, 0xff,
+        ]);
+        expected.extend([4; 32]);
+        expected.extend([5; 64]);
+        expected.extend([6; 32]);
+        // the receiver with the payer and the instructions sysvar
+        expected.extend([2, 2, 0, 3, 2, 7, 8]);
+
+        assert_eq!(transaction.serialize_message().unwrap(), expected);
+    }
+
+    #[test]
+    fn encodes_the_receiver_instruction() {
+        let message = Message {
+            index: 1,
+            from_chain_id: 2,
+            to_chain_id: 3,
+            sender: vec![4, 5],
+            message: vec![6],
+            ..Default::default()
+        };
+
+        let mut expected = vec![];
+        expected.extend(1u64.to_le_bytes());
+        expected.extend(2u64.to_le_bytes());
+        expected.extend(3u64.to_le_bytes());
+        expected.extend([2, 0, 0, 0, 4, 5]);
+        expected.extend([1, 0, 0, 0, 6]);
+
+        assert_eq!(receiver_instruction_data(&message), expected);
+    }
+
+    #[test]
+    fn parses_events_of_the_program() {
+        let logs = logs(&[
+            &format!("Program {PROGRAM_ID} invoke [1]"),
+            "Program log: Instruction: SendMessage",
+            &event_data(0, b"hello"),
+            &event_data(1, b""),
+            &format!("Program {PROGRAM_ID} consumed 5000 of 200000 compute units"),
+            &format!("Program {PROGRAM_ID} success"),
+        ]);
+
+        assert_eq!(
+            parse_ccmp_events(&logs, PROGRAM_ID),
+            vec![event(0, b"hello"), event(1, b"")]
+        );
+    }
+
+    #[test]
+    fn skips_events_of_other_programs() {
+        let other = "OtherProgram1111111111111111111111111111111";
+        let logs = logs(&[
+            // another program emitting the same event
+            &format!("Program {other} invoke [1]"),
+            &event_data(0, b"forged"),
+            // the CCMP program invoked by it emits a real one
+            &format!("Program {PROGRAM_ID} invoke [2]"),
+            &event_data(1, b"real"),
+            // and invokes another program, which emits a forged one
+            &format!("Program {other} invoke [3]"),
+            &event_data(2, b"forged"),
+            &format!("Program {other} success"),
+            &event_data(3, b"real"),
+            &format!("Program {PROGRAM_ID} success"),
+            &event_data(4, b"forged"),
+            &format!("Program {other} success"),
+        ]);
+
+        assert_eq!(
+            parse_ccmp_events(&logs, PROGRAM_ID),
+            vec![event(1, b"real"), event(3, b"real")]
+        );
+    }
+
+    #[test]
+    fn skips_malformed_data() {
+        let truncated = CCMP_EVENT_DISCRIMINATOR
+            .iter()
+            .chain(&[0; 10])
+            .copied()
+            .collect::<Vec<_>>();
+        let logs = logs(&[
+            &format!("Program {PROGRAM_ID} invoke [1]"),
+            "Program data: not base64!",
+            &format!("Program data: {}", base64::encode(b"other_ev")),
+            &format!("Program data: {}", base64::encode(truncated)),
+            &event_data(5, b"ok"),
+            &format!("Program {PROGRAM_ID} success"),
+        ]);
+
+        assert_eq!(parse_ccmp_events(&logs, PROGRAM_ID), vec![event(5, b"ok")]);
+    }
+}
@@ -0,0 +1,104 @@
+use super::UtilsError;
+
+const ALPHABET: &[u8; 58] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+
+/// Base58 with the Bitcoin alphabet, the one Solana encodes keys and signatures with.
+pub fn encode(bytes: &[u8]) -> String {
+    let zeros = bytes.iter().take_while(|b| **b == 0).count();
+
+    // little endian base58 digits
+    let mut digits: Vec<u8> = vec![];
+    for byte in &bytes[zeros..] {
+        let mut carry = *byte as u32;
+        for digit in digits.iter_mut() {
+            carry += (*digit as u32) << 8;
+            *digit = (carry % 58) as u8;
+            carry /= 58;
+        }
+
+        while carry > 0 {
+            digits.push((carry % 58) as u8);
+            carry /= 58;
+        }
+    }
+
+    let mut encoded = String::with_capacity(zeros + digits.len());
+    encoded.extend(std::iter::repeat('1').take(zeros));
+    encoded.extend(
+        digits
+            .iter()
+            .rev()
+            .map(|digit| ALPHABET[*digit as usize] as char),
+    );
+
+    encoded
+}
+
+pub fn decode(encoded: &str) -> Result<Vec<u8>, UtilsError> {
+    let zeros = encoded.bytes().take_while(|c| *c == b'1').count();
+
+    // little endian bytes
+    let mut bytes: Vec<u8> = vec![];
+    for c in encoded.bytes().skip(zeros) {
+        let mut carry = ALPHABET
+            .iter()
+            .position(|a| *a == c)
+            .ok_or_else(|| UtilsError::InvalidBase58(encoded.to_string()))?
+            as u32;
+
+        for byte in bytes.iter_mut() {
+            carry += (*byte as u32) * 58;
+            *byte = (carry & 0xff) as u8;
+            carry >>= 8;
+        }
+
+        while carry > 0 {
+            bytes.push((carry & 0xff) as u8);
+            carry >>= 8;
+        }
+    }
+
+    let mut decoded = vec![0; zeros];
+    decoded.extend(bytes.iter().rev());
+
+    Ok(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_known_vectors() {
+        assert_eq!(encode(b""), "");
+        assert_eq!(encode(b"Hello World!"), "2NEpo7TZRRrLZSi2U");
+        assert_eq!(encode(&[0, 0, 0x28, 0x7f, 0xb4, 0xcd]), "11233QC4");
+        assert_eq!(encode(&[0; 32]), "11111111111111111111111111111111");
+    }
+
+    #[test]
+    fn decodes_solana_program_ids() {
+        assert_eq!(
+            hex::encode(decode("Ed25519SigVerify111111111111111111111111111").unwrap()),
+            "037d46d67c93fbbe12f9428f838d40ff0570744927f48a64fcca704480000000"
+        );
+        assert_eq!(
+            hex::encode(decode("Sysvar1nstructions1111111111111111111111111").unwrap()),
+            "06a7d517187bd16635dad40455fdc2c0c124c68f215675a5dbbacb5f08000000"
+        );
+    }
+
+    #[test]
+    fn round_trips_leading_zeros() {
+        for bytes in [vec![0], vec![0, 0, 1], vec![0, 255, 0], vec![255; 64]] {
+            assert_eq!(decode(&encode(&bytes)).unwrap(), bytes);
+        }
+    }
+
+    #[test]
+    fn rejects_characters_outside_the_alphabet() {
+        for encoded in ["0", "O", "I", "l", "abc+"] {
+            assert!(decode(encoded).is_err(), "{encoded}");
+        }
+    }
+}
@@ -0,0 +1,245 @@
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+const OP_RETURN: u8 = 0x6a;
+const OP_PUSHDATA1: u8 = 0x4c;
+const OP_PUSHDATA2: u8 = 0x4d;
+const OP_PUSHDATA4: u8 = 0x4e;
+const SEGWIT_MARKER: u8 = 0x00;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum BitcoinError {
+    #[error("unexpected end of transaction")]
+    UnexpectedEnd,
+    #[error("trailing bytes after transaction")]
+    TrailingBytes,
+}
+
+/// The parts of a raw transaction the daemons need.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Transaction {
+    // double SHA-256 of the transaction without witnesses, in internal byte order
+    pub txid: [u8; 32],
+    pub output_scripts: Vec<Vec<u8>>,
+}
+
+impl Transaction {
+    /// Parses a serialized transaction, with or without witnesses.
+    pub fn parse(raw: &[u8]) -> Result<Self, BitcoinError> {
+        let mut reader = Reader { data: raw };
+
+        let version = reader.read(4)?;
+
+        let is_segwit = reader.peek()? == SEGWIT_MARKER;
+        if is_segwit {
+            reader.read(2)?;
+        }
+
+        let inputs_start = reader.data;
+        let inputs_count = reader.read_var_int()?;
+        for _ in 0..inputs_count {
+            // previous outpoint
+            reader.read(36)?;
+            let script_length = reader.read_var_int()?;
+            reader.read(script_length)?;
+            // sequence
+            reader.read(4)?;
+        }
+
+        let outputs_count = reader.read_var_int()?;
+        let mut output_scripts = vec![];
+        for _ in 0..outputs_count {
+            // value
+            reader.read(8)?;
+            let script_length = reader.read_var_int()?;
+            output_scripts.push(reader.read(script_length)?.to_vec());
+        }
+        let body = &inputs_start[..inputs_start.len() - reader.data.len()];
+
+        if is_segwit {
+            for _ in 0..inputs_count {
+                let items_count = reader.read_var_int()?;
+                for _ in 0..items_count {
+                    let item_length = reader.read_var_int()?;
+                    reader.read(item_length)?;
+                }
+            }
+        }
+
+        let lock_time = reader.read(4)?;
+
+        if !reader.data.is_empty() {
+            return Err(BitcoinError::TrailingBytes);
+        }
+
+        let mut hasher = Sha256::new();
+        hasher.update(version);
+        hasher.update(body);
+        hasher.update(lock_time);
+        let txid = Sha256::digest(hasher.finalize()).into();
+
+        Ok(Self {
+            txid,
+            output_scripts,
+        })
+    }
+
+    /// The hex txid in the reversed byte order explorers and RPCs use.
+    pub fn display_txid(txid: &[u8]) -> String {
+        hex::encode(txid.iter().rev().copied().collect::<Vec<_>>())
+    }
+
+    /// Concatenated pushes of every `OP_RETURN` output.
+    pub fn op_return_payloads(&self) -> Vec<Vec<u8>> {
+        self.output_scripts
+            .iter()
+            .filter_map(|script| op_return_payload(script))
+            .collect()
+    }
+}
+
+fn op_return_payload(script: &[u8]) -> Option<Vec<u8>> {
+    let (&OP_RETURN, pushes) = script.split_first()? else {
+        return None;
+    };
+
+    let mut reader = Reader { data: pushes };
+    let mut payload = vec![];
+    while !reader.data.is_empty() {
+        let length = match reader.read(1).ok()?[0] {
+            length @ 0x01..=0x4b => length as u64,
+            OP_PUSHDATA1 => reader.read(1).ok()?[0] as u64,
+            OP_PUSHDATA2 => u16::from_le_bytes(reader.read(2).ok()?.try_into().ok()?) as u64,
+            OP_PUSHDATA4 => u32::from_le_bytes(reader.read(4).ok()?.try_into().ok()?) as u64,
+            // only data pushes are expected after OP_RETURN
+            _ => return None,
+        };
+
+        payload.extend(reader.read(length).ok()?);
+    }
+
+    Some(payload)
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn peek(&self) -> Result<u8, BitcoinError> {
+        self.data
+            .first()
+            .copied()
+            .ok_or(BitcoinError::UnexpectedEnd)
+    }
+
+    fn read(&mut self, length: u64) -> Result<&'a [u8], BitcoinError> {
+        let length = usize::try_from(length).map_err(|_| BitcoinError::UnexpectedEnd)?;
+        if self.data.len() < length {
+            return Err(BitcoinError::UnexpectedEnd);
+        }
+
+        let (bytes, rest) = self.data.split_at(length);
+        self.data = rest;
+
+        Ok(bytes)
+    }
+
+    fn read_var_int(&mut self) -> Result<u64, BitcoinError> {
+        let value = match self.read(1)?[0] {
+            0xfd => u16::from_le_bytes(self.read(2)?.try_into().unwrap()) as u64,
+            0xfe => u32::from_le_bytes(self.read(4)?.try_into().unwrap()) as u64,
+            0xff => u64::from_le_bytes(self.read(8)?.try_into().unwrap()),
+            value => value as u64,
+        };
+
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the coinbase transaction of the genesis block
+    const GENESIS_COINBASE: &str = "01000000010000000000000000000000000000000000000000000000000000000000000000ffffffff4d04ffff001d0104455468652054696d65732030332f4a616e2f32303039204368616e63656c6c6f72206f6e206272696e6b206f66207365636f6e64206261696c6f757420666f722062616e6b73ffffffff0100f2052a01000000434104678afdb0fe5548271967f1a67130b7105cd6a828e03909a67962e0ea1f61deb649f6bc3f4cef38c4f35504e51ec112de5c384df7ba0b8d578a4c702b6bf11d5fac00000000";
+    // one input with two witness items, a P2WPKH output and an OP_RETURN output
+    const SEGWIT_TX: &str = "02000000000101000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f0100000000fdffffff02e80300000000000016001411111111111111111111111111111111111111110000000000000000286a2643434d50000000000000000714aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa68656c6c6f0203010203010400000000";
+
+    #[test]
+    fn parses_legacy_transaction() {
+        let tx = Transaction::parse(&hex::decode(GENESIS_COINBASE).unwrap()).unwrap();
+
+        assert_eq!(
+            Transaction::display_txid(&tx.txid),
+            "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b"
+        );
+        assert_eq!(tx.output_scripts.len(), 1);
+        assert_eq!(tx.output_scripts[0].len(), 67);
+        assert!(tx.op_return_payloads().is_empty());
+    }
+
+    #[test]
+    fn txid_of_segwit_transaction_skips_witnesses() {
+        let tx = Transaction::parse(&hex::decode(SEGWIT_TX).unwrap()).unwrap();
+
+        assert_eq!(
+            Transaction::display_txid(&tx.txid),
+            "432cb90a34723fa6291360d388ebc0716bdfb97ae1f1285c0d86206642c3d4b0"
+        );
+        assert_eq!(tx.output_scripts.len(), 2);
+    }
+
+    #[test]
+    fn extracts_op_return_payloads() {
+        let tx = Transaction::parse(&hex::decode(SEGWIT_TX).unwrap()).unwrap();
+
+        let mut expected = b"CCMP".to_vec();
+        expected.extend(7u64.to_be_bytes());
+        expected.push(20);
+        expected.extend([0xaa; 20]);
+        expected.extend(b"hello");
+
+        assert_eq!(tx.op_return_payloads(), vec![expected]);
+    }
+
+    #[test]
+    fn rejects_truncated_and_trailing_bytes() {
+        let raw = hex::decode(SEGWIT_TX).unwrap();
+
+        assert_eq!(
+            Transaction::parse(&raw[..raw.len() - 1]),
+            Err(BitcoinError::UnexpectedEnd)
+        );
+
+        let mut trailing = raw;
+        trailing.push(0);
+        assert_eq!(
+            Transaction::parse(&trailing),
+            Err(BitcoinError::TrailingBytes)
+        );
+    }
+
+    #[test]
+    fn op_return_concatenates_pushes() {
+        let mut script = vec![OP_RETURN, 0x02, 0x01, 0x02];
+        script.extend([OP_PUSHDATA1, 0x01, 0x03]);
+        script.extend([OP_PUSHDATA2, 0x02, 0x00, 0x04, 0x05]);
+        script.extend([OP_PUSHDATA4, 0x01, 0x00, 0x00, 0x00, 0x06]);
+
+        assert_eq!(op_return_payload(&script), Some(vec![1, 2, 3, 4, 5, 6]));
+    }
+
+    #[test]
+    fn op_return_rejects_other_scripts() {
+        // OP_DUP after OP_RETURN
+        assert_eq!(op_return_payload(&[OP_RETURN, 0x01, 0xff, 0x76]), None);
+        // push longer than the script
+        assert_eq!(op_return_payload(&[OP_RETURN, 0x02, 0xff]), None);
+        // P2WPKH
+        assert_eq!(op_return_payload(&[0x00, 0x14]), None);
+        assert_eq!(op_return_payload(&[]), None);
+
+        assert_eq!(op_return_payload(&[OP_RETURN]), Some(vec![]));
+    }
+}
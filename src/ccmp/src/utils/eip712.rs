@@ -0,0 +1,133 @@
+use std::str::FromStr;
+
+use candid::CandidType;
+use ethabi::{Address, Token};
+use ic_web3_rs::signing::keccak256;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+
+use super::{format_evm_address, UtilsError};
+
+const EIP712_DOMAIN_TYPE: &str =
+    "EIP712Domain(string name,string version,uint256 chainId,address verifyingContract)";
+const CCMP_MESSAGE_TYPE: &str = "CcmpMessage(uint256 index,uint256 fromChainId,uint256 toChainId,bytes sender,bytes message,address receiver)";
+const EIP712_PREFIX: &[u8] = b"\x19\x01";
+
+lazy_static! {
+    static ref EIP712_DOMAIN_TYPEHASH: [u8; 32] = keccak256(EIP712_DOMAIN_TYPE.as_bytes());
+    static ref CCMP_MESSAGE_TYPEHASH: [u8; 32] = keccak256(CCMP_MESSAGE_TYPE.as_bytes());
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone, Default)]
+pub struct Eip712Domain {
+    pub name: String,
+    pub version: String,
+    pub chain_id: u64,
+    pub verifying_contract: String,
+}
+
+impl Eip712Domain {
+    pub fn separator(&self) -> Result<[u8; 32], UtilsError> {
+        let verifying_contract = parse_address(&self.verifying_contract)?;
+
+        Ok(keccak256(&ethabi::encode(&[
+            Token::FixedBytes(EIP712_DOMAIN_TYPEHASH.to_vec()),
+            Token::FixedBytes(keccak256(self.name.as_bytes()).to_vec()),
+            Token::FixedBytes(keccak256(self.version.as_bytes()).to_vec()),
+            Token::Uint(self.chain_id.into()),
+            Token::Address(verifying_contract),
+        ])))
+    }
+}
+
+/// The `CcmpMessage` fields in the order of the type definition.
+pub struct CcmpMessage<'a> {
+    pub index: u64,
+    pub from_chain_id: u64,
+    pub to_chain_id: u64,
+    pub sender: &'a [u8],
+    pub message: &'a [u8],
+    pub receiver: Address,
+}
+
+impl CcmpMessage<'_> {
+    pub fn struct_hash(&self) -> [u8; 32] {
+        keccak256(&ethabi::encode(&[
+            Token::FixedBytes(CCMP_MESSAGE_TYPEHASH.to_vec()),
+            Token::Uint(self.index.into()),
+            Token::Uint(self.from_chain_id.into()),
+            Token::Uint(self.to_chain_id.into()),
+            Token::FixedBytes(keccak256(self.sender).to_vec()),
+            Token::FixedBytes(keccak256(self.message).to_vec()),
+            Token::Address(self.receiver),
+        ]))
+    }
+
+    /// The typed data whose keccak256 is the digest produced by `_hashTypedDataV4`.
+    pub fn encode(&self, domain: &Eip712Domain) -> Result<Vec<u8>, UtilsError> {
+        let domain_separator = domain.separator()?;
+
+        Ok([
+            EIP712_PREFIX,
+            domain_separator.as_slice(),
+            self.struct_hash().as_slice(),
+        ]
+        .concat())
+    }
+}
+
+pub fn parse_address(address: &str) -> Result<Address, UtilsError> {
+    let formatted = format_evm_address(address.to_string())?;
+
+    Address::from_str(&formatted).map_err(|e| UtilsError::InvalidAddress(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VERIFYING_CONTRACT: &str = "0xCcCCccccCCCCcCCCCCCcCcCccCcCCCcCcccccccC";
+
+    fn domain(name: &str) -> Eip712Domain {
+        Eip712Domain {
+            name: name.to_string(),
+            version: "1".to_string(),
+            chain_id: 1,
+            verifying_contract: VERIFYING_CONTRACT.to_string(),
+        }
+    }
+
+    // the domain of the `Mail` example of EIP-712
+    #[test]
+    fn separator_matches_the_eip712_example() {
+        let separator = domain("Ether Mail").separator().unwrap();
+
+        assert_eq!(
+            hex::encode(separator),
+            "f2cee375fa42b42143804025fc449deafd50cc031ca257e0b194a650a912090f"
+        );
+    }
+
+    #[test]
+    fn digest_matches_known_vector() {
+        let message = CcmpMessage {
+            index: 7,
+            from_chain_id: 0,
+            to_chain_id: 1,
+            sender: &[1, 2],
+            message: b"hello",
+            receiver: parse_address("0xbBbBBBBbbBBBbbbBbbBbbbbBBbBbbbbBbBbbBBbB").unwrap(),
+        };
+
+        assert_eq!(
+            hex::encode(message.struct_hash()),
+            "977914d4ed5524db921409d71f96b7be8a55af6da92215c0b83b900557423697"
+        );
+
+        let typed_data = message.encode(&domain("CCMP")).unwrap();
+        assert_eq!(
+            hex::encode(keccak256(&typed_data)),
+            "32667cd8ad2bd1c241ce937074b7b7c8aa707d3e3598bd94ec2a4ce49875f56b"
+        );
+    }
+}
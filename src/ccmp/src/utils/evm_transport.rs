@@ -0,0 +1,243 @@
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+use candid::{CandidType, Nat, Principal, Reserved};
+use futures::future::{join_all, BoxFuture};
+use ic_cdk::api::{
+    call::{call_with_payment128, msg_cycles_refunded128},
+    management_canister::http_request::HttpHeader,
+};
+use ic_web3_rs::{
+    error::{Error as Web3Error, TransportError},
+    helpers,
+    transports::{ic_http_client::CallOptions, ICHttp},
+    RequestId, Transport,
+};
+use jsonrpc_core::{Call, Response, Value};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::types::balances::BalancesStorage;
+
+const EVM_RPC_REQUEST_METHOD: &str = "request";
+const DEFAULT_MAX_RESPONSE_BYTES: u64 = 500_000;
+// a transaction is broadcast once any provider accepts it
+const SEND_RAW_TRANSACTION_METHOD: &str = "eth_sendRawTransaction";
+// a call which could not be sent has no reply to read the refund from, its
+// cycles are not taken
+const CALL_NOT_SENT_MESSAGE: &str = "Couldn't send message";
+
+#[derive(Error, Debug)]
+pub enum EvmProviderError {
+    #[error("evm rpc provider has no services")]
+    NoServices,
+    #[error("min agreement of {min_agreement} is more than the {services} services")]
+    MinAgreementTooHigh { min_agreement: u64, services: usize },
+}
+
+/// How an EVM chain is reached.
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone, Default, PartialEq)]
+pub enum EvmProvider {
+    // HTTPS outcalls to the rpc of the chain
+    #[default]
+    Http,
+    // the EVM RPC canister, which calls its providers on behalf of this canister
+    EvmRpc(EvmRpcConfig),
+}
+
+impl EvmProvider {
+    /// HTTPS outcalls this canister makes for one request. The cycles a call to
+    /// the EVM RPC canister uses are charged by the transport once it returns.
+    pub fn outcalls_per_request(&self) -> u64 {
+        match self {
+            EvmProvider::Http => 1,
+            EvmProvider::EvmRpc(_) => 0,
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), EvmProviderError> {
+        let EvmProvider::EvmRpc(config) = self else {
+            return Ok(());
+        };
+
+        if config.services.is_empty() {
+            return Err(EvmProviderError::NoServices);
+        }
+
+        if config.min_agreement > config.services.len() as u64 {
+            return Err(EvmProviderError::MinAgreementTooHigh {
+                min_agreement: config.min_agreement,
+                services: config.services.len(),
+            });
+        }
+
+        Ok(())
+    }
+}
+
+/// Every request goes to each of the services, a result is accepted once
+/// `min_agreement` of them return it.
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct EvmRpcConfig {
+    pub canister_id: Principal,
+    pub services: Vec<RpcService>,
+    pub min_agreement: u64,
+    // attached to every request, the canister refunds what it does not use
+    pub cycles: u64,
+}
+
+// the services of the EVM RPC canister's interface this canister uses
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub enum RpcService {
+    Provider(u64),
+    Custom(RpcApi),
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct RpcApi {
+    pub url: String,
+    pub headers: Option<Vec<HttpHeader>>,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+struct JsonRpcError {
+    code: i64,
+    message: String,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+enum RpcError {
+    JsonRpcError(JsonRpcError),
+    ProviderError(Reserved),
+    ValidationError(Reserved),
+    HttpOutcallError(Reserved),
+}
+
+/// A `Web3` transport over the configured provider, so daemons, the writer and
+/// the checker make the same calls whichever provider backs the chain.
+#[derive(Debug, Clone)]
+pub enum EvmTransport {
+    Http(ICHttp),
+    EvmRpc(EvmRpcTransport),
+}
+
+impl EvmTransport {
+    /// The cycles of calls to the EVM RPC canister are charged to `payer`, if any.
+    pub fn new(
+        rpc: &str,
+        provider: &EvmProvider,
+        max_resp: Option<u64>,
+        payer: Option<Principal>,
+    ) -> Self {
+        match provider {
+            EvmProvider::Http => Self::Http(ICHttp::new(rpc, max_resp).unwrap()),
+            EvmProvider::EvmRpc(config) => Self::EvmRpc(EvmRpcTransport {
+                config: config.clone(),
+                max_response_bytes: max_resp.unwrap_or(DEFAULT_MAX_RESPONSE_BYTES),
+                id: Arc::new(AtomicUsize::new(1)),
+                payer,
+            }),
+        }
+    }
+}
+
+impl Transport for EvmTransport {
+    type Out = BoxFuture<'static, Result<Value, Web3Error>>;
+
+    fn prepare(&self, method: &str, params: Vec<Value>) -> (RequestId, Call) {
+        match self {
+            Self::Http(http) => http.prepare(method, params),
+            Self::EvmRpc(evm_rpc) => {
+                let id = evm_rpc.id.fetch_add(1, Ordering::AcqRel);
+                (id, helpers::build_request(id, method, params))
+            }
+        }
+    }
+
+    fn send(&self, id: RequestId, request: Call, options: CallOptions) -> Self::Out {
+        match self {
+            Self::Http(http) => Box::pin(http.send(id, request, options)),
+            Self::EvmRpc(evm_rpc) => Box::pin(evm_rpc.clone().request(request)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct EvmRpcTransport {
+    config: EvmRpcConfig,
+    max_response_bytes: u64,
+    id: Arc<AtomicUsize>,
+    payer: Option<Principal>,
+}
+
+impl EvmRpcTransport {
+    async fn request(self, call: Call) -> Result<Value, Web3Error> {
+        let method = match &call {
+            Call::MethodCall(method_call) => method_call.method.clone(),
+            _ => String::new(),
+        };
+        let body = helpers::to_string(&call);
+
+        let responses = join_all(self.config.services.iter().map(|service| async {
+            let response = call_with_payment128::<_, (Result<String, RpcError>,)>(
+                self.config.canister_id,
+                EVM_RPC_REQUEST_METHOD,
+                (service.clone(), body.clone(), self.max_response_bytes),
+                self.config.cycles as u128,
+            )
+            .await;
+
+            // the refund is read in the reply of this very call
+            let is_sent = !matches!(&response, Err((_, msg)) if msg == CALL_NOT_SENT_MESSAGE);
+            if let Some(payer) = self.payer.filter(|_| is_sent) {
+                let used_cycles =
+                    (self.config.cycles as u128).saturating_sub(msg_cycles_refunded128());
+                BalancesStorage::reduce_cycles(&payer, Nat::from(used_cycles));
+            }
+
+            response
+        }))
+        .await;
+
+        let mut results = vec![];
+        let mut errors = vec![];
+        for response in responses {
+            match response {
+                Ok((Ok(response),)) => match Self::parse(&response) {
+                    Ok(value) => results.push(value),
+                    Err(err) => errors.push(err.to_string()),
+                },
+                Ok((Err(err),)) => errors.push(format!("{err:?}")),
+                Err((code, msg)) => errors.push(format!("{code:?}: {msg}")),
+            }
+        }
+
+        let min_agreement = if method == SEND_RAW_TRANSACTION_METHOD {
+            1
+        } else {
+            self.config.min_agreement.max(1) as usize
+        };
+
+        results
+            .iter()
+            .find(|value| results.iter().filter(|other| other == value).count() >= min_agreement)
+            .cloned()
+            .ok_or_else(|| {
+                Web3Error::Transport(TransportError::Message(format!(
+                    "no {} providers agree on {}, results: {:?}, errors: {:?}",
+                    min_agreement, method, results, errors
+                )))
+            })
+    }
+
+    fn parse(response: &str) -> Result<Value, Web3Error> {
+        match helpers::to_response_from_slice(response.as_bytes())? {
+            Response::Single(output) => helpers::to_result_from_output(output),
+            Response::Batch(_) => Err(Web3Error::InvalidResponse(
+                "unexpected batch response".to_string(),
+            )),
+        }
+    }
+}
@@ -0,0 +1,92 @@
+use candid::Nat;
+use ic_cdk::api::management_canister::http_request::{
+    http_request, CanisterHttpRequestArgument, HttpHeader, HttpMethod,
+};
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::{json, Value};
+use thiserror::Error;
+
+use super::transform_processors::{transform_context, Transform};
+
+const JSON_RPC_VERSION: &str = "2.0";
+const DEFAULT_MAX_RESPONSE_BYTES: u64 = 500_000;
+// https outcall fees of a 13 node subnet
+const HTTP_REQUEST_BASE_CYCLES: u128 = 49_140_000;
+const HTTP_REQUEST_BYTE_CYCLES: u128 = 5_200;
+const HTTP_RESPONSE_BYTE_CYCLES: u128 = 10_400;
+
+#[derive(Error, Debug)]
+pub enum JsonRpcError {
+    #[error("http request error: {0}")]
+    HttpRequest(String),
+    #[error("http status: {0}")]
+    HttpStatus(Nat),
+    #[error("json error: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("rpc error {code}: {message}")]
+    Rpc { code: i64, message: String },
+    #[error("empty rpc response")]
+    EmptyResponse,
+}
+
+#[derive(Deserialize)]
+struct RpcErrorObject {
+    code: i64,
+    message: String,
+}
+
+#[derive(Deserialize)]
+struct RpcResponse<T> {
+    result: Option<T>,
+    error: Option<RpcErrorObject>,
+}
+
+/// Calls a JSON-RPC 2.0 method over an HTTPS outcall, used for chains which
+/// are not served by the web3 client.
+pub async fn request<T: DeserializeOwned>(
+    url: &str,
+    method: &str,
+    params: Value,
+) -> Result<T, JsonRpcError> {
+    let body = json!({
+        "jsonrpc": JSON_RPC_VERSION,
+        "id": 1,
+        "method": method,
+        "params": params,
+    });
+
+    let request = CanisterHttpRequestArgument {
+        url: url.to_string(),
+        max_response_bytes: Some(DEFAULT_MAX_RESPONSE_BYTES),
+        method: HttpMethod::POST,
+        headers: vec![HttpHeader {
+            name: "Content-Type".to_string(),
+            value: "application/json".to_string(),
+        }],
+        body: Some(serde_json::to_vec(&body)?),
+        transform: Some(transform_context(Transform::Default, vec![])),
+    };
+
+    let cycles = HTTP_REQUEST_BASE_CYCLES
+        + HTTP_REQUEST_BYTE_CYCLES * request.body.as_ref().map_or(0, Vec::len) as u128
+        + HTTP_RESPONSE_BYTE_CYCLES * DEFAULT_MAX_RESPONSE_BYTES as u128;
+
+    let (response,) = http_request(request, cycles)
+        .await
+        .map_err(|(_, msg)| JsonRpcError::HttpRequest(msg))?;
+
+    if response.status != Nat::from(200u64) {
+        return Err(JsonRpcError::HttpStatus(response.status));
+    }
+
+    let response: RpcResponse<T> = serde_json::from_slice(&response.body)?;
+
+    if let Some(error) = response.error {
+        return Err(JsonRpcError::Rpc {
+            code: error.code,
+            message: error.message,
+        });
+    }
+
+    response.result.ok_or(JsonRpcError::EmptyResponse)
+}
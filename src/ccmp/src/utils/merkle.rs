@@ -0,0 +1,138 @@
+use ic_web3_rs::signing::keccak256;
+
+/// A Merkle tree hashed the same way as OpenZeppelin's `MerkleProof`: pairs are
+/// sorted before hashing, so proofs do not need to carry the side of a sibling.
+/// A node without a sibling is promoted to the next layer unchanged.
+pub struct MerkleTree {
+    layers: Vec<Vec<[u8; 32]>>,
+}
+
+impl MerkleTree {
+    pub fn new(leaves: Vec<[u8; 32]>) -> Self {
+        let mut layers = vec![leaves];
+
+        while layers.last().map_or(false, |layer| layer.len() > 1) {
+            let next = layers
+                .last()
+                .unwrap()
+                .chunks(2)
+                .map(|pair| match pair {
+                    [a, b] => hash_pair(a, b),
+                    [a] => *a,
+                    _ => unreachable!(),
+                })
+                .collect();
+
+            layers.push(next);
+        }
+
+        Self { layers }
+    }
+
+    pub fn root(&self) -> [u8; 32] {
+        self.layers
+            .last()
+            .and_then(|layer| layer.first())
+            .copied()
+            .unwrap_or_default()
+    }
+
+    pub fn proof(&self, mut index: usize) -> Vec<[u8; 32]> {
+        let mut proof = vec![];
+
+        for layer in &self.layers[..self.layers.len() - 1] {
+            let sibling = index ^ 1;
+            if sibling < layer.len() {
+                proof.push(layer[sibling]);
+            }
+
+            index /= 2;
+        }
+
+        proof
+    }
+}
+
+pub fn hash_pair(a: &[u8; 32], b: &[u8; 32]) -> [u8; 32] {
+    let (first, second) = if a <= b { (a, b) } else { (b, a) };
+
+    keccak256(&[first.as_slice(), second.as_slice()].concat())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leaves(n: u8) -> Vec<[u8; 32]> {
+        (0..n).map(|i| keccak256(&[i])).collect()
+    }
+
+    // `MerkleProof.processProof` of OpenZeppelin
+    fn process_proof(leaf: [u8; 32], proof: &[[u8; 32]]) -> [u8; 32] {
+        proof
+            .iter()
+            .fold(leaf, |hash, sibling| hash_pair(&hash, sibling))
+    }
+
+    #[test]
+    fn roots_match_known_vectors() {
+        let vectors = [
+            (
+                2,
+                "b2521d64679bc4720dabfbae7ce17947a5d373d987d3b0cc1e3042ba2054da4a",
+            ),
+            (
+                3,
+                "d359d2743bb3a93ded4c902716931497ae3080f478c14e7af96344a92e9ddd51",
+            ),
+            (
+                5,
+                "11aeafa56c9b34805cc86b1c320c9331672c07e600f0a44317051cfa05a0c296",
+            ),
+        ];
+
+        for (n, root) in vectors {
+            assert_eq!(hex::encode(MerkleTree::new(leaves(n)).root()), root);
+        }
+    }
+
+    #[test]
+    fn proofs_verify_against_the_root() {
+        for n in 1..=9 {
+            let leaves = leaves(n);
+            let tree = MerkleTree::new(leaves.clone());
+
+            for (i, leaf) in leaves.into_iter().enumerate() {
+                assert_eq!(
+                    process_proof(leaf, &tree.proof(i)),
+                    tree.root(),
+                    "{n} leaves, leaf {i}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn single_leaf_is_the_root() {
+        let leaf = keccak256(b"leaf");
+        let tree = MerkleTree::new(vec![leaf]);
+
+        assert_eq!(tree.root(), leaf);
+        assert!(tree.proof(0).is_empty());
+    }
+
+    #[test]
+    fn odd_node_is_promoted_without_a_proof_step() {
+        let leaves = leaves(3);
+        let tree = MerkleTree::new(leaves.clone());
+
+        assert_eq!(tree.proof(2), vec![hash_pair(&leaves[0], &leaves[1])]);
+    }
+
+    #[test]
+    fn pairs_are_sorted() {
+        let (a, b) = (keccak256(b"a"), keccak256(b"b"));
+
+        assert_eq!(hash_pair(&a, &b), hash_pair(&b, &a));
+    }
+}
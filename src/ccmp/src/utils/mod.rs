@@ -1,4 +1,10 @@
+pub mod base58;
+pub mod bitcoin;
+pub mod eip712;
 pub mod encoding;
+pub mod evm_transport;
+pub mod json_rpc;
+pub mod merkle;
 pub mod signing;
 pub mod transform_processors;
 
@@ -18,6 +24,8 @@ pub enum UtilsError {
     InvalidAddress(String),
     #[error("parsing int error: {0}")]
     ParseInt(#[from] ParseIntError),
+    #[error("invalid base58: {0}")]
+    InvalidBase58(String),
 }
 
 pub fn format_evm_address(addr: String) -> Result<String, UtilsError> {
@@ -56,3 +64,8 @@ pub fn u256_to_nat(u256: U256) -> Nat {
 
     Nat(num_bigint::BigUint::from_bytes_be(&buf))
 }
+
+/// The nat must fit in 256 bits, as amounts read from an EVM chain do.
+pub fn nat_to_u256(nat: &Nat) -> U256 {
+    U256::from_big_endian(&nat.0.to_bytes_be())
+}
@@ -1,34 +1,435 @@
-use candid::Principal;
+use candid::{utils::ArgumentDecoder, CandidType, Principal};
 use ic_cdk::api::{
     call::{call_with_payment, CallResult},
-    management_canister::ecdsa::{SignWithEcdsaArgument, SignWithEcdsaResponse},
+    management_canister::ecdsa::{
+        ecdsa_public_key, EcdsaCurve, EcdsaKeyId, EcdsaPublicKeyArgument, SignWithEcdsaArgument,
+        SignWithEcdsaResponse,
+    },
 };
-use libsecp256k1::{recover, Message, RecoveryId, Signature};
+use ic_web3_rs::signing::keccak256;
+use libsecp256k1::{recover, Error as Secp256k1Error, Message, PublicKey, RecoveryId, Signature};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
 
-use crate::storage_get;
+use crate::types::{keys::DerivedKey, ECDSA_SIGN_CYCLES, SCHNORR_SIGN_CYCLES};
 
-const ECDSA_SIGN_CYCLES: u64 = 23_000_000_000;
+const ETH_V_OFFSET: u8 = 27;
+const ETH_SIGNATURE_LENGTH: usize = 65;
 
-pub fn get_eth_v(sig: &[u8], msg: &[u8]) -> u8 {
-    let message = Message::parse_slice(msg).expect("invalid message");
-    let signature = Signature::parse_overflowing_slice(sig).expect("invalid signature");
-    let recovery_id = RecoveryId::parse(0).expect("invalid recovery id");
+#[derive(Error, Debug)]
+pub enum SigningError {
+    #[error("sign with ecdsa error: {0}")]
+    SignWithEcdsa(String),
+    #[error("sign with schnorr error: {0}")]
+    SignWithSchnorr(String),
+    #[error("public key error: {0}")]
+    PublicKey(String),
+}
+
+/// The threshold signing schemes of the management canister.
+#[derive(CandidType, Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SigningAlgorithm {
+    #[default]
+    EcdsaSecp256k1,
+    SchnorrBip340,
+    SchnorrEd25519,
+}
+
+impl SigningAlgorithm {
+    pub fn sign_cycles(&self) -> u64 {
+        match self {
+            SigningAlgorithm::EcdsaSecp256k1 => ECDSA_SIGN_CYCLES,
+            SigningAlgorithm::SchnorrBip340 | SigningAlgorithm::SchnorrEd25519 => {
+                SCHNORR_SIGN_CYCLES
+            }
+        }
+    }
+
+    fn schnorr_algorithm(&self) -> Option<SchnorrAlgorithm> {
+        match self {
+            SigningAlgorithm::EcdsaSecp256k1 => None,
+            SigningAlgorithm::SchnorrBip340 => Some(SchnorrAlgorithm::Bip340Secp256k1),
+            SigningAlgorithm::SchnorrEd25519 => Some(SchnorrAlgorithm::Ed25519),
+        }
+    }
+}
+
+// the management canister's schnorr interface, ic-cdk does not provide it yet
+#[derive(CandidType, Deserialize, Debug, Clone, Copy)]
+enum SchnorrAlgorithm {
+    #[serde(rename = "bip340secp256k1")]
+    Bip340Secp256k1,
+    #[serde(rename = "ed25519")]
+    Ed25519,
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+struct SchnorrKeyId {
+    algorithm: SchnorrAlgorithm,
+    name: String,
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+struct SignWithSchnorrArgument {
+    message: Vec<u8>,
+    derivation_path: Vec<Vec<u8>>,
+    key_id: SchnorrKeyId,
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+struct SignWithSchnorrResponse {
+    signature: Vec<u8>,
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+struct SchnorrPublicKeyArgument {
+    canister_id: Option<Principal>,
+    derivation_path: Vec<Vec<u8>>,
+    key_id: SchnorrKeyId,
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+struct SchnorrPublicKeyResponse {
+    public_key: Vec<u8>,
+    chain_code: Vec<u8>,
+}
+
+/// Signs the message with the key, ECDSA signs a 32-byte hash, Schnorr signs
+/// the message as is.
+pub async fn sign(key: &DerivedKey, message: Vec<u8>) -> Result<Vec<u8>, SigningError> {
+    let Some(algorithm) = key.algorithm.schnorr_algorithm() else {
+        let args = SignWithEcdsaArgument {
+            message_hash: message,
+            derivation_path: key.derivation_path.clone(),
+            key_id: ecdsa_key_id(key),
+        };
+
+        let (response,): (SignWithEcdsaResponse,) =
+            management_call("sign_with_ecdsa", args, key.algorithm.sign_cycles())
+                .await
+                .map_err(|(_, msg)| SigningError::SignWithEcdsa(msg))?;
+
+        return Ok(response.signature);
+    };
+
+    let args = SignWithSchnorrArgument {
+        message,
+        derivation_path: key.derivation_path.clone(),
+        key_id: SchnorrKeyId {
+            algorithm,
+            name: key.key_name.clone(),
+        },
+    };
+
+    let (response,): (SignWithSchnorrResponse,) =
+        management_call("sign_with_schnorr", args, key.algorithm.sign_cycles())
+            .await
+            .map_err(|(_, msg)| SigningError::SignWithSchnorr(msg))?;
+
+    Ok(response.signature)
+}
+
+/// Returns the public key of this canister for the key, SEC1 compressed for
+/// secp256k1 keys and 32 bytes for ed25519 ones.
+pub async fn public_key(key: &DerivedKey) -> Result<Vec<u8>, SigningError> {
+    let Some(algorithm) = key.algorithm.schnorr_algorithm() else {
+        let args = EcdsaPublicKeyArgument {
+            canister_id: None,
+            derivation_path: key.derivation_path.clone(),
+            key_id: ecdsa_key_id(key),
+        };
+
+        let (response,) = ecdsa_public_key(args)
+            .await
+            .map_err(|(_, msg)| SigningError::PublicKey(msg))?;
+
+        return Ok(response.public_key);
+    };
+
+    let args = SchnorrPublicKeyArgument {
+        canister_id: None,
+        derivation_path: key.derivation_path.clone(),
+        key_id: SchnorrKeyId {
+            algorithm,
+            name: key.key_name.clone(),
+        },
+    };
+
+    let (response,): (SchnorrPublicKeyResponse,) = management_call("schnorr_public_key", args, 0)
+        .await
+        .map_err(|(_, msg)| SigningError::PublicKey(msg))?;
+
+    Ok(response.public_key)
+}
+
+fn ecdsa_key_id(key: &DerivedKey) -> EcdsaKeyId {
+    EcdsaKeyId {
+        curve: EcdsaCurve::Secp256k1,
+        name: key.key_name.clone(),
+    }
+}
+
+async fn management_call<A, R>(method: &str, args: A, cycles: u64) -> CallResult<R>
+where
+    A: CandidType,
+    R: for<'a> ArgumentDecoder<'a>,
+{
+    call_with_payment(Principal::management_canister(), method, (args,), cycles).await
+}
+
+#[derive(Error, Debug)]
+pub enum SignatureError {
+    #[error("invalid message hash: {0:?}")]
+    InvalidMessageHash(Secp256k1Error),
+    #[error("invalid signature: {0:?}")]
+    InvalidSignature(Secp256k1Error),
+    #[error("invalid public key: {0:?}")]
+    InvalidPublicKey(Secp256k1Error),
+    #[error("signature does not belong to the public key")]
+    UnknownRecoveryId,
+    #[error("signature is signed by 0x{recovered}, expected 0x{expected}")]
+    SignerMismatch { recovered: String, expected: String },
+}
+
+/// Turns a 64-byte `r || s` signature of the management canister into the
+/// 65-byte `r || s || v` form `ecrecover` accepts. `s` is normalised to the lower
+/// half of the curve order, since EVM verifiers reject malleable signatures.
+pub fn to_eth_signature(
+    signature: &[u8],
+    message_hash: &[u8],
+    public_key: &[u8],
+) -> Result<Vec<u8>, SignatureError> {
+    let message = Message::parse_slice(message_hash).map_err(SignatureError::InvalidMessageHash)?;
+    let mut signature =
+        Signature::parse_standard_slice(signature).map_err(SignatureError::InvalidSignature)?;
+    signature.normalize_s();
+
+    let public_key =
+        PublicKey::parse_slice(public_key, None).map_err(SignatureError::InvalidPublicKey)?;
+
+    // the recovery id is the one that recovers the signer's key
+    let recovery_id = (0..=1)
+        .filter_map(|id| RecoveryId::parse(id).ok())
+        .find(|recovery_id| {
+            recover(&message, &signature, recovery_id)
+                .map_or(false, |key| key.serialize() == public_key.serialize())
+        })
+        .ok_or(SignatureError::UnknownRecoveryId)?;
+
+    let mut eth_signature = signature.serialize().to_vec();
+    eth_signature.push(ETH_V_OFFSET + recovery_id.serialize());
+
+    verify_eth_signature(&eth_signature, message_hash, &evm_address(&public_key))?;
+
+    Ok(eth_signature)
+}
 
-    let rec_pub_key = recover(&message, &signature, &recovery_id).expect("unable to recover");
-    let pub_key = hex::decode(storage_get!(public_key)).expect("invalid public key");
-    if pub_key == rec_pub_key.serialize_compressed() {
-        return 27;
+/// Checks the signature the way `ecrecover` does, against the signer's address.
+pub fn verify_eth_signature(
+    eth_signature: &[u8],
+    message_hash: &[u8],
+    expected_address: &[u8; 20],
+) -> Result<(), SignatureError> {
+    if eth_signature.len() != ETH_SIGNATURE_LENGTH {
+        return Err(SignatureError::InvalidSignature(
+            Secp256k1Error::InvalidInputLength,
+        ));
     }
 
-    28
+    let message = Message::parse_slice(message_hash).map_err(SignatureError::InvalidMessageHash)?;
+    let signature = Signature::parse_standard_slice(&eth_signature[..64])
+        .map_err(SignatureError::InvalidSignature)?;
+    let recovery_id =
+        RecoveryId::parse_rpc(eth_signature[64]).map_err(SignatureError::InvalidSignature)?;
+
+    let recovered =
+        recover(&message, &signature, &recovery_id).map_err(SignatureError::InvalidSignature)?;
+    let recovered_address = evm_address(&recovered);
+
+    if recovered_address != *expected_address {
+        return Err(SignatureError::SignerMismatch {
+            recovered: hex::encode(recovered_address),
+            expected: hex::encode(expected_address),
+        });
+    }
+
+    Ok(())
+}
+
+fn evm_address(public_key: &PublicKey) -> [u8; 20] {
+    let hash = keccak256(&public_key.serialize()[1..]);
+
+    let mut address = [0; 20];
+    address.copy_from_slice(&hash[12..]);
+    address
 }
 
-pub async fn sign(args: SignWithEcdsaArgument) -> CallResult<(SignWithEcdsaResponse,)> {
-    call_with_payment(
-        Principal::management_canister(),
-        "sign_with_ecdsa",
-        (args,),
-        ECDSA_SIGN_CYCLES,
-    )
-    .await
+#[cfg(test)]
+mod tests {
+    use libsecp256k1::{sign, SecretKey};
+
+    use super::*;
+
+    const SECRET_KEY: &str = "2db4e413136a38f94aad31b4733cdaf0f9a33591bf39848e87dda2dcf4864666";
+    const PUBLIC_KEY: &str = "03b0493533f924235cf02160481b8718e39060e766c329bfd394410a11953b1f70";
+    const ADDRESS: &str = "d42b89aeddb1848056ad725f1e270df0aaa4b597";
+    const MESSAGE_HASH: &str = "bd25cae574fb5534398102bf65c7abd048d44def94abc9dd570f992bd8d516b3";
+    // `r || s || v` of the hash signed by an independent implementation
+    const ETH_SIGNATURE: &str = "5fb55363dbb21e41c710c5406600a549d5466075c45ac56dcc748730e6e1fedd22262e49a304a98f7ffa59bc5f818fdc45ff790875c55232e545f8ab1cfa8a151c";
+    // the order of secp256k1
+    const CURVE_ORDER: [u8; 32] = [
+        0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+        0xfe, 0xba, 0xae, 0xdc, 0xe6, 0xaf, 0x48, 0xa0, 0x3b, 0xbf, 0xd2, 0x5e, 0x8c, 0xd0, 0x36,
+        0x41, 0x41,
+    ];
+
+    fn hex_bytes(value: &str) -> Vec<u8> {
+        hex::decode(value).unwrap()
+    }
+
+    // the same signature with `s` replaced by `n - s`
+    fn with_high_s(signature: &[u8]) -> Vec<u8> {
+        let mut high_s = [0u8; 32];
+        let mut borrow = 0i16;
+        for i in (0..32).rev() {
+            let difference = CURVE_ORDER[i] as i16 - signature[32 + i] as i16 - borrow;
+            borrow = (difference < 0) as i16;
+            high_s[i] = difference.rem_euclid(256) as u8;
+        }
+
+        [&signature[..32], high_s.as_slice()].concat()
+    }
+
+    fn is_low_s(s: &[u8]) -> bool {
+        // the half order is `CURVE_ORDER >> 1`
+        let half_order: Vec<u8> = CURVE_ORDER
+            .iter()
+            .enumerate()
+            .map(|(i, byte)| (byte >> 1) | if i > 0 { CURVE_ORDER[i - 1] << 7 } else { 0 })
+            .collect();
+
+        s <= half_order.as_slice()
+    }
+
+    // the algorithms as named by the schnorr interface of the management canister
+    #[allow(non_camel_case_types)]
+    #[derive(CandidType, Deserialize, Debug, PartialEq)]
+    enum ManagementSchnorrAlgorithm {
+        bip340secp256k1,
+        ed25519,
+    }
+
+    #[derive(CandidType, Deserialize, Debug)]
+    struct ManagementSchnorrKeyId {
+        algorithm: ManagementSchnorrAlgorithm,
+        name: String,
+    }
+
+    #[test]
+    fn schnorr_algorithms_match_the_management_canister() {
+        let algorithms = [
+            (
+                SigningAlgorithm::SchnorrBip340,
+                ManagementSchnorrAlgorithm::bip340secp256k1,
+            ),
+            (
+                SigningAlgorithm::SchnorrEd25519,
+                ManagementSchnorrAlgorithm::ed25519,
+            ),
+        ];
+
+        for (algorithm, expected) in algorithms {
+            let key_id = SchnorrKeyId {
+                algorithm: algorithm.schnorr_algorithm().unwrap(),
+                name: "key_1".to_string(),
+            };
+
+            let bytes = candid::encode_one(key_id).unwrap();
+            let decoded: ManagementSchnorrKeyId = candid::decode_one(&bytes).unwrap();
+
+            assert_eq!(decoded.algorithm, expected);
+            assert_eq!(decoded.name, "key_1");
+        }
+
+        assert!(SigningAlgorithm::EcdsaSecp256k1
+            .schnorr_algorithm()
+            .is_none());
+    }
+
+    #[test]
+    fn matches_known_signature() {
+        let eth_signature = hex_bytes(ETH_SIGNATURE);
+
+        let converted = to_eth_signature(
+            &eth_signature[..64],
+            &hex_bytes(MESSAGE_HASH),
+            &hex_bytes(PUBLIC_KEY),
+        )
+        .unwrap();
+
+        assert_eq!(converted, eth_signature);
+    }
+
+    #[test]
+    fn recovery_id_recovers_the_signer() {
+        let secret_key = SecretKey::parse_slice(&hex_bytes(SECRET_KEY)).unwrap();
+        let public_key = PublicKey::from_secret_key(&secret_key);
+
+        for i in 0..8u8 {
+            let message_hash = keccak256(&[i]);
+            let (signature, recovery_id) = sign(&Message::parse(&message_hash), &secret_key);
+
+            let eth_signature = to_eth_signature(
+                &signature.serialize(),
+                &message_hash,
+                &public_key.serialize_compressed(),
+            )
+            .unwrap();
+
+            assert_eq!(eth_signature[64], ETH_V_OFFSET + recovery_id.serialize());
+            verify_eth_signature(
+                &eth_signature,
+                &message_hash,
+                &hex_bytes(ADDRESS).try_into().unwrap(),
+            )
+            .unwrap();
+        }
+    }
+
+    #[test]
+    fn high_s_is_normalised() {
+        let eth_signature = hex_bytes(ETH_SIGNATURE);
+        let high_s = with_high_s(&eth_signature[..64]);
+        assert!(!is_low_s(&high_s[32..]));
+
+        let converted =
+            to_eth_signature(&high_s, &hex_bytes(MESSAGE_HASH), &hex_bytes(PUBLIC_KEY)).unwrap();
+
+        assert!(is_low_s(&converted[32..64]));
+        assert_eq!(converted, eth_signature);
+    }
+
+    #[test]
+    fn other_public_key_is_rejected() {
+        let eth_signature = hex_bytes(ETH_SIGNATURE);
+        let other_secret_key = SecretKey::parse(&keccak256(b"other")).unwrap();
+        let other_public_key = PublicKey::from_secret_key(&other_secret_key);
+
+        let result = to_eth_signature(
+            &eth_signature[..64],
+            &hex_bytes(MESSAGE_HASH),
+            &other_public_key.serialize_compressed(),
+        );
+
+        assert!(matches!(result, Err(SignatureError::UnknownRecoveryId)));
+    }
+
+    #[test]
+    fn signature_of_another_address_is_rejected() {
+        let eth_signature = hex_bytes(ETH_SIGNATURE);
+
+        let result = verify_eth_signature(&eth_signature, &hex_bytes(MESSAGE_HASH), &[0; 20]);
+
+        assert!(matches!(result, Err(SignatureError::SignerMismatch { .. })));
+    }
 }
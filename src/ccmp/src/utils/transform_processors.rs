@@ -1,17 +1,233 @@
-use ic_cdk::api::management_canister::http_request::{TransformContext, TransformFunc};
+use candid::Nat;
+use ic_cdk::api::management_canister::http_request::{
+    HttpResponse, TransformContext, TransformFunc,
+};
 use ic_web3_rs::transports::ic_http_client::{CallOptions, CallOptionsBuilder};
+use serde_json::{json, Value};
 
-pub fn call_options(transformer: String) -> CallOptions {
+// replicas may number their requests differently, every response gets this id
+const CANONICAL_JSON_RPC_ID: u64 = 1;
+// errors a node returns for a transaction it has already accepted
+const ALREADY_SENT_ERRORS: [&str; 3] = ["already known", "nonce too low", "known transaction"];
+
+/// The transform of an HTTPS outcall, named after the query method implementing
+/// it. Every response is normalised the same way, only a broadcast of a
+/// transaction needs a transform of its own.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Transform {
+    Default,
+    SendRawTransaction,
+}
+
+impl Transform {
+    pub fn method(&self) -> &'static str {
+        match self {
+            Transform::Default => "transform",
+            Transform::SendRawTransaction => "transform_send_raw_transaction",
+        }
+    }
+}
+
+pub fn call_options(transform: Transform) -> CallOptions {
+    call_options_with_context(transform, vec![])
+}
+
+pub fn call_options_with_context(transform: Transform, context: Vec<u8>) -> CallOptions {
     CallOptionsBuilder::default()
-        .transform(Some(TransformContext {
-            function: TransformFunc(candid::Func {
-                principal: ic_cdk::api::id(),
-                method: transformer,
-            }),
-            context: vec![],
-        }))
+        .transform(Some(transform_context(transform, context)))
         .max_resp(None)
         .cycles(None)
         .build()
         .unwrap()
 }
+
+pub fn transform_context(transform: Transform, context: Vec<u8>) -> TransformContext {
+    TransformContext {
+        function: TransformFunc(candid::Func {
+            principal: ic_cdk::api::id(),
+            method: transform.method().to_string(),
+        }),
+        context,
+    }
+}
+
+/// Drops the headers and normalises the JSON body of a response, error bodies
+/// are dropped as well since nodes tend to put request ids and times in them.
+pub fn process_response(response: HttpResponse) -> HttpResponse {
+    if response.status != Nat::from(200u64) {
+        return HttpResponse {
+            status: response.status,
+            headers: vec![],
+            body: vec![],
+        };
+    }
+
+    HttpResponse {
+        status: response.status,
+        headers: vec![],
+        body: normalize_json(&response.body),
+    }
+}
+
+/// Like `process_response`, and a transaction the node already has is reported
+/// as sent. The context is the hash of the transaction, without it the error
+/// is kept, with a canonical message.
+pub fn process_send_raw_transaction_response(
+    response: HttpResponse,
+    tx_hash: &[u8],
+) -> HttpResponse {
+    let mut response = process_response(response);
+
+    let Ok(mut body) = serde_json::from_slice::<Value>(&response.body) else {
+        return response;
+    };
+    let Some(message) = body
+        .pointer("/error/message")
+        .and_then(Value::as_str)
+        .map(str::to_lowercase)
+    else {
+        return response;
+    };
+    let Some(error) = ALREADY_SENT_ERRORS
+        .into_iter()
+        .find(|error| message.contains(error))
+    else {
+        return response;
+    };
+
+    body = if tx_hash.is_empty() {
+        json!({
+            "jsonrpc": "2.0",
+            "id": CANONICAL_JSON_RPC_ID,
+            "error": { "code": -32000, "message": error },
+        })
+    } else {
+        json!({
+            "jsonrpc": "2.0",
+            "id": CANONICAL_JSON_RPC_ID,
+            "result": format!("0x{}", hex::encode(tx_hash)),
+        })
+    };
+    response.body = serde_json::to_vec(&body).expect("failed to serialize json");
+
+    response
+}
+
+// keys of the re-serialized objects are sorted, so the formatting of the node
+// does not matter either
+fn normalize_json(body: &[u8]) -> Vec<u8> {
+    let Ok(mut value) = serde_json::from_slice::<Value>(body) else {
+        return body.to_vec();
+    };
+
+    if let Some(id) = value.get_mut("id") {
+        *id = json!(CANONICAL_JSON_RPC_ID);
+    }
+
+    serde_json::to_vec(&value).expect("failed to serialize json")
+}
+
+#[cfg(test)]
+mod tests {
+    use ic_cdk::api::management_canister::http_request::HttpHeader;
+
+    use super::*;
+
+    const TX_HASH: [u8; 4] = [0xde, 0xad, 0xbe, 0xef];
+
+    fn response(status: u64, body: &str) -> HttpResponse {
+        HttpResponse {
+            status: Nat::from(status),
+            headers: vec![HttpHeader {
+                name: "date".to_string(),
+                value: "Thu, 01 Jan 1970 00:00:00 GMT".to_string(),
+            }],
+            body: body.as_bytes().to_vec(),
+        }
+    }
+
+    fn body(response: &HttpResponse) -> String {
+        String::from_utf8(response.body.clone()).unwrap()
+    }
+
+    fn error_response(message: &str) -> HttpResponse {
+        response(
+            200,
+            &format!(
+                r#"{{"jsonrpc":"2.0","id":7,"error":{{"code":-32000,"message":"{message}"}}}}"#
+            ),
+        )
+    }
+
+    #[test]
+    fn normalize_json_sets_the_id_and_sorts_keys() {
+        let body = normalize_json(br#"{ "result": "0x1", "jsonrpc": "2.0", "id": 42 }"#);
+
+        assert_eq!(body, br#"{"id":1,"jsonrpc":"2.0","result":"0x1"}"#);
+    }
+
+    #[test]
+    fn normalize_json_keeps_other_bodies() {
+        assert_eq!(normalize_json(b"not json"), b"not json");
+        assert_eq!(normalize_json(b"[1, 2]"), b"[1,2]");
+    }
+
+    #[test]
+    fn process_response_drops_headers_and_error_bodies() {
+        let ok = process_response(response(200, r#"{"id":3,"result":null}"#));
+        assert!(ok.headers.is_empty());
+        assert_eq!(body(&ok), r#"{"id":1,"result":null}"#);
+
+        let failed = process_response(response(503, "request 3 failed at 12:00"));
+        assert_eq!(failed.status, Nat::from(503u64));
+        assert!(failed.headers.is_empty());
+        assert!(failed.body.is_empty());
+    }
+
+    #[test]
+    fn already_sent_transaction_is_reported_as_sent() {
+        for message in ["already known", "nonce too low: next nonce 5, tx nonce 4"] {
+            let response = process_send_raw_transaction_response(error_response(message), &TX_HASH);
+
+            assert_eq!(
+                body(&response),
+                r#"{"id":1,"jsonrpc":"2.0","result":"0xdeadbeef"}"#
+            );
+        }
+    }
+
+    #[test]
+    fn already_sent_error_is_canonical_without_tx_hash() {
+        let already_known =
+            process_send_raw_transaction_response(error_response("Already Known"), &[]);
+        assert_eq!(
+            body(&already_known),
+            r#"{"error":{"code":-32000,"message":"already known"},"id":1,"jsonrpc":"2.0"}"#
+        );
+
+        let nonce_too_low = process_send_raw_transaction_response(
+            error_response("nonce too low: next nonce 5, tx nonce 4"),
+            &[],
+        );
+        assert_eq!(
+            body(&nonce_too_low),
+            r#"{"error":{"code":-32000,"message":"nonce too low"},"id":1,"jsonrpc":"2.0"}"#
+        );
+    }
+
+    #[test]
+    fn other_responses_are_only_normalized() {
+        let insufficient_funds =
+            process_send_raw_transaction_response(error_response("insufficient funds"), &TX_HASH);
+        assert_eq!(
+            body(&insufficient_funds),
+            r#"{"error":{"code":-32000,"message":"insufficient funds"},"id":1,"jsonrpc":"2.0"}"#
+        );
+
+        let sent = process_send_raw_transaction_response(
+            response(200, r#"{"jsonrpc":"2.0","id":9,"result":"0x01"}"#),
+            &TX_HASH,
+        );
+        assert_eq!(body(&sent), r#"{"id":1,"jsonrpc":"2.0","result":"0x01"}"#);
+    }
+}
@@ -0,0 +1,122 @@
+//! A stand-in for the EVM RPC canister in local deployments. It serves the
+//! `request` method only, forwarding the JSON-RPC payload as is to the url of
+//! a custom service or, for any provider id, to the url given at install.
+
+use std::cell::RefCell;
+
+use candid::{CandidType, Nat};
+use ic_cdk::{
+    api::call::msg_cycles_accept128,
+    api::management_canister::http_request::{
+        http_request, CanisterHttpRequestArgument, HttpHeader, HttpMethod, HttpResponse,
+        TransformArgs, TransformContext, TransformFunc,
+    },
+    init, query, update,
+};
+use serde::Deserialize;
+
+// https outcall fees of a 13 node subnet
+const HTTP_REQUEST_BASE_CYCLES: u128 = 49_140_000;
+const HTTP_REQUEST_BYTE_CYCLES: u128 = 5_200;
+const HTTP_RESPONSE_BYTE_CYCLES: u128 = 10_400;
+
+thread_local! {
+    static PROVIDER_URL: RefCell<String> = RefCell::default();
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct RpcApi {
+    pub url: String,
+    pub headers: Option<Vec<HttpHeader>>,
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub enum RpcService {
+    Provider(u64),
+    Custom(RpcApi),
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+pub struct JsonRpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+pub enum RpcError {
+    JsonRpcError(JsonRpcError),
+    ProviderError(String),
+    ValidationError(String),
+    HttpOutcallError(String),
+}
+
+#[init]
+fn init(provider_url: String) {
+    PROVIDER_URL.with(|url| *url.borrow_mut() = provider_url);
+}
+
+#[update]
+async fn request(
+    service: RpcService,
+    json: String,
+    max_response_bytes: u64,
+) -> Result<String, RpcError> {
+    let (url, mut headers) = match service {
+        RpcService::Provider(_) => (PROVIDER_URL.with(|url| url.borrow().clone()), vec![]),
+        RpcService::Custom(api) => (api.url, api.headers.unwrap_or_default()),
+    };
+    if url.is_empty() {
+        return Err(RpcError::ProviderError(
+            "provider url is not set".to_string(),
+        ));
+    }
+
+    headers.push(HttpHeader {
+        name: "Content-Type".to_string(),
+        value: "application/json".to_string(),
+    });
+
+    let cycles = HTTP_REQUEST_BASE_CYCLES
+        + HTTP_REQUEST_BYTE_CYCLES * json.len() as u128
+        + HTTP_RESPONSE_BYTE_CYCLES * max_response_bytes as u128;
+
+    let request = CanisterHttpRequestArgument {
+        url,
+        max_response_bytes: Some(max_response_bytes),
+        method: HttpMethod::POST,
+        headers,
+        body: Some(json.into_bytes()),
+        transform: Some(TransformContext {
+            function: TransformFunc(candid::Func {
+                principal: ic_cdk::api::id(),
+                method: "transform".to_string(),
+            }),
+            context: vec![],
+        }),
+    };
+
+    // the outcall is paid with the cycles attached by the caller
+    msg_cycles_accept128(cycles);
+
+    let (response,) = http_request(request, cycles)
+        .await
+        .map_err(|(code, msg)| RpcError::HttpOutcallError(format!("{code:?}: {msg}")))?;
+
+    if response.status != Nat::from(200u64) {
+        return Err(RpcError::HttpOutcallError(format!(
+            "http status: {}",
+            response.status
+        )));
+    }
+
+    String::from_utf8(response.body).map_err(|e| RpcError::ValidationError(e.to_string()))
+}
+
+#[query]
+fn transform(args: TransformArgs) -> HttpResponse {
+    HttpResponse {
+        status: args.response.status,
+        body: args.response.body,
+        headers: vec![],
+    }
+}